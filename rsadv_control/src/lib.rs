@@ -1,121 +1,180 @@
 use std::io::{self, Read, Write};
 use std::net::Ipv6Addr;
+#[cfg(feature = "unix-socket")]
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(feature = "unix-socket")]
 use std::os::unix::net::UnixStream;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 use bytes::{Buf, BufMut};
 
+#[cfg(feature = "unix-socket")]
 const CONTROL_SOCKET_ADDR: &str = "/run/rsadv.sock";
 
+/// How long [`Connection::send`] waits for the response by default.
+#[cfg(feature = "unix-socket")]
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The length of the header preceding every frame on the control socket.
+///
+/// The header is the length of the encoded [`Request`] or [`Response`]
+/// that follows it as a little-endian `u32`.
+pub const FRAME_HEADER_LEN: usize = 4;
+
+/// The maximum length of the body of a frame in bytes.
+///
+/// Longer frames are rejected without reading their body.
+pub const MAX_FRAME_LEN: u32 = 1 << 20;
+
+/// The version of the control protocol.
+///
+/// This is bumped whenever the wire format of [`Request`] or [`Response`] changes.
+/// The encoding of [`Request::Hello`] and [`Response::Hello`] never changes,
+/// so that peers with different versions can detect the mismatch.
+pub const PROTOCOL_VERSION: u32 = 19;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Request {
     AddPrefix(Prefix),
     RemovePrefix(Prefix),
     AddDnsServer(DnsServer),
     RemoveDnsServer(DnsServer),
+    /// Removes all prefixes.
+    FlushPrefixes,
+    /// Removes all DNS servers.
+    FlushDnsServers,
+    /// Checks whether the daemon is healthy, answered with [`Response::Pong`].
+    Ping,
+    /// Requests the status of the daemon, answered with [`Response::Status`].
+    GetStatus,
+    /// Replaces all prefixes and DNS servers with the ones stored in the
+    /// database of the daemon.
+    Reload,
+    /// Requests the state of the daemon, answered with [`Response::State`].
+    Export,
+    /// Replaces the state of the daemon with one returned by [`Request::Export`].
+    Import(Vec<u8>),
+    /// Requests the last `max` recorded events, answered with [`Response::Log`].
+    Log {
+        max: u32,
+    },
+    /// Requests the addresses the daemon configured on the interface,
+    /// answered with [`Response::Addresses`].
+    ListConfiguredAddresses,
+    /// Authenticates the connection with the token configured in the daemon.
+    ///
+    /// Must be the first request on a connection, apart from
+    /// [`Request::Hello`]. Without it, requests that change the state are
+    /// rejected if the daemon has a token configured.
+    Authenticate(String),
+    /// Exchanges the protocol versions, answered with [`Response::Hello`].
+    Hello {
+        client_version: u32,
+    },
+    /// Requests the announced prefix with the given address, answered with
+    /// [`Response::Prefix`].
+    GetPrefix(Ipv6Addr),
+    /// Stops sending RAs and answering RSs until [`Request::Resume`], e.g.
+    /// for maintenance. The state is kept.
+    ///
+    /// Answered with [`Response::Ok`], affecting 1 if the daemon was not
+    /// paused yet.
+    Pause,
+    /// Resumes sending RAs after [`Request::Pause`].
+    ///
+    /// Answered with [`Response::Ok`], affecting 1 if the daemon was paused.
+    Resume,
 }
 
 impl Request {
-    pub fn encode<B>(&self, mut buf: B)
+    /// Encodes the request into `buf`, returning the number of bytes written.
+    pub fn encode<B>(&self, mut buf: B) -> usize
     where
         B: BufMut,
     {
+        let remaining = buf.remaining_mut();
+
         match self {
             Self::AddPrefix(prefix) => {
                 buf.put_u32_le(1);
 
-                buf.put_slice(&prefix.prefix.octets());
-                buf.put_u8(prefix.prefix_length);
-
-                match prefix.preferred_lifetime {
-                    Lifetime::Duration(dur) => {
-                        buf.put_u8(1);
-                        buf.put_u32_le(dur.as_secs() as u32);
-                    }
-                    Lifetime::Until(ts) => {
-                        let dur = ts.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-                        buf.put_u8(2u8);
-                        buf.put_u32_le(dur.as_secs() as u32);
-                    }
-                }
-
-                match prefix.valid_lifetime {
-                    Lifetime::Duration(dur) => {
-                        buf.put_u8(1);
-                        buf.put_u32_le(dur.as_secs() as u32);
-                    }
-                    Lifetime::Until(ts) => {
-                        let dur = ts.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-                        buf.put_u8(2u8);
-                        buf.put_u32_le(dur.as_secs() as u32);
-                    }
-                }
+                encode_prefix(prefix, &mut buf);
             }
             Self::RemovePrefix(prefix) => {
                 buf.put_u32_le(2);
 
-                buf.put_slice(&prefix.prefix.octets());
-                buf.put_u8(prefix.prefix_length);
-
-                match prefix.preferred_lifetime {
-                    Lifetime::Duration(dur) => {
-                        buf.put_u8(1);
-                        buf.put_u32_le(dur.as_secs() as u32);
-                    }
-                    Lifetime::Until(ts) => {
-                        let dur = ts.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-                        buf.put_u8(2u8);
-                        buf.put_u32_le(dur.as_secs() as u32);
-                    }
-                }
-
-                match prefix.valid_lifetime {
-                    Lifetime::Duration(dur) => {
-                        buf.put_u8(1);
-                        buf.put_u32(dur.as_secs() as u32);
-                    }
-                    Lifetime::Until(ts) => {
-                        let dur = ts.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-                        buf.put_u8(2u8);
-                        buf.put_u32_le(dur.as_secs() as u32);
-                    }
-                }
+                encode_prefix(prefix, &mut buf);
             }
             Self::AddDnsServer(server) => {
                 buf.put_u32_le(3);
 
                 buf.put_slice(&server.addr.octets());
 
-                match server.lifetime {
-                    Lifetime::Duration(dur) => {
-                        buf.put_u8(1);
-                        buf.put_u32_le(dur.as_secs() as u32);
-                    }
-                    Lifetime::Until(ts) => {
-                        let dur = ts.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-                        buf.put_u8(2u8);
-                        buf.put_u32_le(dur.as_secs() as u32);
-                    }
-                }
+                encode_lifetime(server.lifetime, &mut buf);
+
+                encode_label(server.label.as_deref(), &mut buf);
             }
             Self::RemoveDnsServer(server) => {
                 buf.put_u32_le(4);
 
                 buf.put_slice(&server.addr.octets());
 
-                match server.lifetime {
-                    Lifetime::Duration(dur) => {
-                        buf.put_u8(1);
-                        buf.put_u32_le(dur.as_secs() as u32);
-                    }
-                    Lifetime::Until(ts) => {
-                        let dur = ts.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-                        buf.put_u8(2u8);
-                        buf.put_u32_le(dur.as_secs() as u32);
-                    }
-                }
+                encode_lifetime(server.lifetime, &mut buf);
+
+                encode_label(server.label.as_deref(), &mut buf);
+            }
+            Self::FlushPrefixes => {
+                buf.put_u32_le(5);
+            }
+            Self::FlushDnsServers => {
+                buf.put_u32_le(6);
+            }
+            Self::Ping => {
+                buf.put_u32_le(7);
+            }
+            Self::GetStatus => {
+                buf.put_u32_le(8);
+            }
+            Self::Reload => {
+                buf.put_u32_le(9);
+            }
+            Self::Export => {
+                buf.put_u32_le(10);
+            }
+            Self::Import(state) => {
+                buf.put_u32_le(11);
+                buf.put_u32_le(state.len() as u32);
+                buf.put_slice(state);
+            }
+            Self::Log { max } => {
+                buf.put_u32_le(12);
+                buf.put_u32_le(*max);
+            }
+            Self::ListConfiguredAddresses => {
+                buf.put_u32_le(13);
+            }
+            Self::Authenticate(token) => {
+                buf.put_u32_le(14);
+                buf.put_u32_le(token.len() as u32);
+                buf.put_slice(token.as_bytes());
+            }
+            Self::Hello { client_version } => {
+                buf.put_u32_le(15);
+                buf.put_u32_le(*client_version);
+            }
+            Self::GetPrefix(addr) => {
+                buf.put_u32_le(16);
+                buf.put_slice(&addr.octets());
+            }
+            Self::Pause => {
+                buf.put_u32_le(17);
+            }
+            Self::Resume => {
+                buf.put_u32_le(18);
             }
         };
+
+        remaining - buf.remaining_mut()
     }
 
     pub fn decode<B>(mut buf: B) -> Result<Self, Error>
@@ -127,76 +186,8 @@ impl Request {
         }
 
         match buf.get_u32_le() {
-            1 => {
-                if buf.remaining() < 16 + 1 + 1 + 4 + 1 + 4 {
-                    return Err(Error::Eof);
-                }
-
-                let mut prefix = [0; 16];
-                for index in 0..16 {
-                    prefix[index] = buf.get_u8();
-                }
-
-                let prefix_length = buf.get_u8();
-
-                let preferred_lifetime = match buf.get_u8() {
-                    1 => Lifetime::Duration(Duration::from_secs(buf.get_u32_le().into())),
-                    2 => Lifetime::Until(
-                        SystemTime::UNIX_EPOCH + Duration::from_secs(buf.get_u32_le().into()),
-                    ),
-                    _ => return Err(Error::Eof),
-                };
-
-                let valid_lifetime = match buf.get_u8() {
-                    1 => Lifetime::Duration(Duration::from_secs(buf.get_u32_le().into())),
-                    2 => Lifetime::Until(
-                        SystemTime::UNIX_EPOCH + Duration::from_secs(buf.get_u32_le().into()),
-                    ),
-                    _ => return Err(Error::Eof),
-                };
-
-                Ok(Self::AddPrefix(Prefix {
-                    prefix: Ipv6Addr::from(prefix),
-                    prefix_length,
-                    preferred_lifetime,
-                    valid_lifetime,
-                }))
-            }
-            2 => {
-                if buf.remaining() < 16 + 1 + 1 + 4 + 1 + 4 {
-                    return Err(Error::Eof);
-                }
-
-                let mut prefix = [0; 16];
-                for index in 0..16 {
-                    prefix[index] = buf.get_u8();
-                }
-
-                let prefix_length = buf.get_u8();
-
-                let preferred_lifetime = match buf.get_u8() {
-                    1 => Lifetime::Duration(Duration::from_secs(buf.get_u32_le().into())),
-                    2 => Lifetime::Until(
-                        SystemTime::UNIX_EPOCH + Duration::from_secs(buf.get_u32_le().into()),
-                    ),
-                    _ => return Err(Error::Eof),
-                };
-
-                let valid_lifetime = match buf.get_u8() {
-                    1 => Lifetime::Duration(Duration::from_secs(buf.get_u32_le().into())),
-                    2 => Lifetime::Until(
-                        SystemTime::UNIX_EPOCH + Duration::from_secs(buf.get_u32_le().into()),
-                    ),
-                    _ => return Err(Error::Eof),
-                };
-
-                Ok(Self::RemovePrefix(Prefix {
-                    prefix: Ipv6Addr::from(prefix),
-                    prefix_length,
-                    preferred_lifetime,
-                    valid_lifetime,
-                }))
-            }
+            1 => Ok(Self::AddPrefix(decode_prefix(&mut buf)?.normalize()?)),
+            2 => Ok(Self::RemovePrefix(decode_prefix(&mut buf)?.normalize()?)),
             3 => {
                 if buf.remaining() < 16 + 1 + 4 {
                     return Err(Error::Eof);
@@ -207,17 +198,14 @@ impl Request {
                     addr[index] = buf.get_u8();
                 }
 
-                let lifetime = match buf.get_u8() {
-                    1 => Lifetime::Duration(Duration::from_secs(buf.get_u32_le().into())),
-                    2 => Lifetime::Until(
-                        SystemTime::UNIX_EPOCH + Duration::from_secs(buf.get_u32_le().into()),
-                    ),
-                    _ => return Err(Error::Eof),
-                };
+                let lifetime = decode_lifetime(&mut buf)?;
+
+                let label = decode_label(&mut buf)?;
 
                 Ok(Self::AddDnsServer(DnsServer {
                     addr: Ipv6Addr::from(addr),
                     lifetime,
+                    label,
                 }))
             }
             4 => {
@@ -230,76 +218,515 @@ impl Request {
                     addr[index] = buf.get_u8();
                 }
 
-                let lifetime = match buf.get_u8() {
-                    1 => Lifetime::Duration(Duration::from_secs(buf.get_u32_le().into())),
-                    2 => Lifetime::Until(
-                        SystemTime::UNIX_EPOCH + Duration::from_secs(buf.get_u32_le().into()),
-                    ),
-                    _ => return Err(Error::Eof),
-                };
+                let lifetime = decode_lifetime(&mut buf)?;
+
+                let label = decode_label(&mut buf)?;
 
                 Ok(Self::RemoveDnsServer(DnsServer {
                     addr: Ipv6Addr::from(addr),
                     lifetime,
+                    label,
                 }))
             }
+            5 => Ok(Self::FlushPrefixes),
+            6 => Ok(Self::FlushDnsServers),
+            7 => Ok(Self::Ping),
+            8 => Ok(Self::GetStatus),
+            9 => Ok(Self::Reload),
+            10 => Ok(Self::Export),
+            11 => {
+                if buf.remaining() < 4 {
+                    return Err(Error::Eof);
+                }
+
+                let len = buf.get_u32_le() as usize;
+                if buf.remaining() < len {
+                    return Err(Error::Eof);
+                }
+
+                let mut state = vec![0; len];
+                buf.copy_to_slice(&mut state);
+                Ok(Self::Import(state))
+            }
+            12 => {
+                if buf.remaining() < 4 {
+                    return Err(Error::Eof);
+                }
+
+                let max = buf.get_u32_le();
+                Ok(Self::Log { max })
+            }
+            13 => Ok(Self::ListConfiguredAddresses),
+            14 => {
+                if buf.remaining() < 4 {
+                    return Err(Error::Eof);
+                }
+
+                let len = buf.get_u32_le() as usize;
+                if buf.remaining() < len {
+                    return Err(Error::Eof);
+                }
+
+                let mut token = vec![0; len];
+                buf.copy_to_slice(&mut token);
+                Ok(Self::Authenticate(
+                    String::from_utf8_lossy(&token).into_owned(),
+                ))
+            }
+            15 => {
+                if buf.remaining() < 4 {
+                    return Err(Error::Eof);
+                }
+
+                let client_version = buf.get_u32_le();
+                Ok(Self::Hello { client_version })
+            }
+            16 => {
+                if buf.remaining() < 16 {
+                    return Err(Error::Eof);
+                }
+
+                let mut addr = [0; 16];
+                buf.copy_to_slice(&mut addr);
+                Ok(Self::GetPrefix(Ipv6Addr::from(addr)))
+            }
+            17 => Ok(Self::Pause),
+            18 => Ok(Self::Resume),
             _ => Err(Error::Eof),
         }
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Prefix {
     pub prefix: Ipv6Addr,
     pub prefix_length: u8,
     pub preferred_lifetime: Lifetime,
     pub valid_lifetime: Lifetime,
+    /// Deprecate the prefix (advertise a preferred lifetime of 0) this long
+    /// before its valid lifetime expires. Only applies to [`Lifetime::Until`].
+    pub deprecate_before: Option<Duration>,
+    /// Whether hosts may assume that addresses in the prefix are reachable
+    /// directly on the link. If `false` the daemon also configures no address
+    /// from the prefix on its interface.
+    pub on_link: bool,
+    /// An informational label, e.g. `guest-vlan`. Never advertised in RAs.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub label: Option<String>,
+    /// Addresses from the prefix the daemon configures on its interface in
+    /// addition to the generated one, e.g. for anycast services.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub extra_addrs: Vec<Ipv6Addr>,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+impl Prefix {
+    /// Returns the canonical form of the prefix with all host bits cleared
+    /// and the extra addresses sorted and deduplicated.
+    ///
+    /// Returns an error if the prefix length is longer than 128 bits, the
+    /// preferred lifetime is longer than the valid lifetime or an extra
+    /// address is not in the prefix.
+    pub fn normalize(mut self) -> Result<Self, Error> {
+        if self.prefix_length > 128 {
+            return Err(Error::InvalidPrefixLength(self.prefix_length));
+        }
+
+        if !self.valid_lifetime.is_infinite()
+            && (self.preferred_lifetime.is_infinite()
+                || self.preferred_lifetime.duration() > self.valid_lifetime.duration())
+        {
+            return Err(Error::PreferredExceedsValid);
+        }
+
+        let mask = u128::MAX
+            .checked_shl(128 - u32::from(self.prefix_length))
+            .unwrap_or(0);
+        let prefix = u128::from(self.prefix) & mask;
+
+        if let Some(addr) = self
+            .extra_addrs
+            .iter()
+            .find(|addr| u128::from(**addr) & mask != prefix)
+        {
+            return Err(Error::AddressOutsidePrefix(*addr));
+        }
+        self.extra_addrs.sort();
+        self.extra_addrs.dedup();
+
+        Ok(Self {
+            prefix: Ipv6Addr::from(prefix),
+            ..self
+        })
+    }
+}
+
+fn encode_prefix<B>(prefix: &Prefix, mut buf: B)
+where
+    B: BufMut,
+{
+    buf.put_slice(&prefix.prefix.octets());
+    buf.put_u8(prefix.prefix_length);
+    encode_lifetime(prefix.preferred_lifetime, &mut buf);
+    encode_lifetime(prefix.valid_lifetime, &mut buf);
+    encode_deprecate_before(prefix.deprecate_before, &mut buf);
+    buf.put_u8(prefix.on_link as u8);
+    encode_label(prefix.label.as_deref(), &mut buf);
+    buf.put_u32_le(prefix.extra_addrs.len() as u32);
+    for addr in &prefix.extra_addrs {
+        buf.put_slice(&addr.octets());
+    }
+}
+
+/// Decodes a prefix without normalizing it.
+fn decode_prefix<B>(mut buf: B) -> Result<Prefix, Error>
+where
+    B: Buf,
+{
+    if buf.remaining() < 16 + 1 + 1 + 4 + 1 + 4 + 4 + 1 {
+        return Err(Error::Eof);
+    }
+
+    let mut prefix = [0; 16];
+    buf.copy_to_slice(&mut prefix);
+    let prefix_length = buf.get_u8();
+    let preferred_lifetime = decode_lifetime(&mut buf)?;
+    let valid_lifetime = decode_lifetime(&mut buf)?;
+    let deprecate_before = match buf.get_u32_le() {
+        0 => None,
+        secs => Some(Duration::from_secs(secs.into())),
+    };
+    let on_link = buf.get_u8() != 0;
+    let label = decode_label(&mut buf)?;
+
+    if buf.remaining() < 4 {
+        return Err(Error::Eof);
+    }
+    let len = buf.get_u32_le() as usize;
+    if buf.remaining() < len * 16 {
+        return Err(Error::Eof);
+    }
+    let mut extra_addrs = Vec::with_capacity(len);
+    for _ in 0..len {
+        let mut addr = [0; 16];
+        buf.copy_to_slice(&mut addr);
+        extra_addrs.push(Ipv6Addr::from(addr));
+    }
+
+    Ok(Prefix {
+        prefix: Ipv6Addr::from(prefix),
+        prefix_length,
+        preferred_lifetime,
+        valid_lifetime,
+        deprecate_before,
+        on_link,
+        label,
+        extra_addrs,
+    })
+}
+
+/// Encodes a lifetime as a tag followed by seconds. Durations of
+/// `0xffffffff` seconds or more are encoded as [`Lifetime::Infinite`].
+fn encode_lifetime<B>(lifetime: Lifetime, mut buf: B)
+where
+    B: BufMut,
+{
+    match lifetime {
+        Lifetime::Duration(dur) if dur < Lifetime::INFINITE => {
+            buf.put_u8(1);
+            buf.put_u32_le(dur.as_secs() as u32);
+        }
+        Lifetime::Until(ts) => {
+            let dur = ts.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+            buf.put_u8(2);
+            buf.put_u32_le(dur.as_secs() as u32);
+        }
+        Lifetime::Duration(_) | Lifetime::Infinite => {
+            buf.put_u8(3);
+            buf.put_u32_le(u32::MAX);
+        }
+    }
+}
+
+fn decode_lifetime<B>(mut buf: B) -> Result<Lifetime, Error>
+where
+    B: Buf,
+{
+    if buf.remaining() < 1 + 4 {
+        return Err(Error::Eof);
+    }
+
+    match buf.get_u8() {
+        1 => Ok(Lifetime::from_secs(buf.get_u32_le().into())),
+        2 => Ok(Lifetime::Until(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(buf.get_u32_le().into()),
+        )),
+        3 => {
+            buf.get_u32_le();
+            Ok(Lifetime::Infinite)
+        }
+        _ => Err(Error::Eof),
+    }
+}
+
+fn encode_deprecate_before<B>(deprecate_before: Option<Duration>, mut buf: B)
+where
+    B: BufMut,
+{
+    buf.put_u32_le(
+        deprecate_before
+            .map(|dur| dur.as_secs() as u32)
+            .unwrap_or(0),
+    );
+}
+
+/// Encodes a label as length-prefixed UTF-8. A missing label is encoded as
+/// an empty string.
+fn encode_label<B>(label: Option<&str>, mut buf: B)
+where
+    B: BufMut,
+{
+    let label = label.unwrap_or_default();
+    buf.put_u32_le(label.len() as u32);
+    buf.put_slice(label.as_bytes());
+}
+
+fn decode_label<B>(mut buf: B) -> Result<Option<String>, Error>
+where
+    B: Buf,
+{
+    if buf.remaining() < 4 {
+        return Err(Error::Eof);
+    }
+
+    let len = buf.get_u32_le() as usize;
+    if buf.remaining() < len {
+        return Err(Error::Eof);
+    }
+
+    let mut label = vec![0; len];
+    buf.copy_to_slice(&mut label);
+
+    if label.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(String::from_utf8_lossy(&label).into_owned()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DnsServer {
     pub addr: Ipv6Addr,
     pub lifetime: Lifetime,
+    /// An informational label, e.g. `iot`. Never advertised in RAs.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub label: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Lifetime {
     Duration(Duration),
-    Until(SystemTime),
+    /// A fixed point in time. Serialized as seconds since the UNIX epoch.
+    Until(#[cfg_attr(feature = "serde", serde(with = "unix_secs"))] SystemTime),
+    /// A lifetime that never expires.
+    Infinite,
 }
 
 impl Lifetime {
+    /// The duration representing an infinite lifetime in RAs (`0xffffffff` seconds).
+    pub const INFINITE: Duration = Duration::from_secs(u32::MAX as u64);
+
+    /// Returns the lifetime of `secs` seconds.
+    ///
+    /// `0xffffffff` seconds and more are [`Lifetime::Infinite`], like in RAs
+    /// and netlink.
+    pub fn from_secs(secs: u64) -> Self {
+        if secs >= Self::INFINITE.as_secs() {
+            Self::Infinite
+        } else {
+            Self::Duration(Duration::from_secs(secs))
+        }
+    }
+
+    /// Returns `true` if this lifetime never expires.
+    pub const fn is_infinite(&self) -> bool {
+        matches!(self, Self::Infinite)
+    }
+
+    /// Returns the length of the lifetime.
+    ///
+    /// For [`Lifetime::Duration`] this is the configured duration, independent
+    /// of when it was configured. A [`Lifetime::Until`] has no configured
+    /// length, so this is the time left until the point in time is reached on
+    /// the wall clock.
     pub fn duration(&self) -> Duration {
         match self {
             Self::Duration(dur) => *dur,
             Self::Until(ts) => ts
                 .duration_since(SystemTime::now())
                 .unwrap_or(Duration::ZERO),
+            Self::Infinite => Self::INFINITE,
+        }
+    }
+
+    /// Returns the remaining time of the lifetime if it was configured at `since`.
+    ///
+    /// Unlike [`duration`], this counts down for both variants.
+    ///
+    /// [`duration`]: Self::duration
+    pub fn remaining(&self, since: Instant) -> Duration {
+        match self {
+            Self::Duration(dur) => dur.saturating_sub(since.elapsed()),
+            Self::Until(_) | Self::Infinite => self.duration(),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+/// (De)serializes a [`SystemTime`] as seconds since the UNIX epoch.
+#[cfg(feature = "serde")]
+mod unix_secs {
+    use std::time::{Duration, SystemTime};
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(ts: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let secs = ts
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(serde::ser::Error::custom)?
+            .as_secs();
+        secs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let secs = u64::deserialize(deserializer)?;
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Response {
-    Ok,
+    /// The request was successful.
+    ///
+    /// `affected` is the number of entries that were changed by the request,
+    /// e.g. `0` for a remove request of an entry that does not exist.
+    Ok { affected: u32 },
+    /// Response to a [`Request::Ping`].
+    Pong {
+        /// The time since the daemon was started.
+        uptime: Duration,
+        /// Whether the daemon is currently able to send RAs.
+        healthy: bool,
+    },
+    /// Response to a [`Request::GetStatus`].
+    Status(Status),
+    /// The request failed.
+    Error { message: String },
+    /// Response to a [`Request::Export`].
+    ///
+    /// The state is opaque to clients and only meant to be passed to
+    /// [`Request::Import`].
+    State(Vec<u8>),
+    /// Response to a [`Request::Log`], oldest entry first.
+    Log(Vec<LogEntry>),
+    /// Response to a [`Request::ListConfiguredAddresses`].
+    Addresses(Vec<ConfiguredAddress>),
+    /// Response to a [`Request::AddPrefix`] for a new prefix.
+    Added,
+    /// Response to a [`Request::AddPrefix`] that replaced an existing prefix.
+    Updated,
+    /// Response to a [`Request::Hello`].
+    Hello { server_version: u32 },
+    /// Response to a [`Request::GetPrefix`]. `None` if the prefix is not
+    /// announced.
+    Prefix(Option<Prefix>),
 }
 
 impl Response {
     pub const fn is_ok(&self) -> bool {
-        matches!(self, Self::Ok)
+        matches!(self, Self::Ok { .. } | Self::Added | Self::Updated)
     }
 }
 
 impl Response {
-    pub fn encode<B>(&self, mut buf: B)
+    /// Encodes the response into `buf`, returning the number of bytes written.
+    pub fn encode<B>(&self, mut buf: B) -> usize
     where
         B: BufMut,
     {
+        let remaining = buf.remaining_mut();
+
         match self {
-            Self::Ok => {
+            Self::Ok { affected } => {
                 buf.put_u32_le(0);
+                buf.put_u32_le(*affected);
+            }
+            Self::Pong { uptime, healthy } => {
+                buf.put_u32_le(1);
+                buf.put_u64_le(uptime.as_secs());
+                buf.put_u8(*healthy as u8);
+            }
+            Self::Status(status) => {
+                buf.put_u32_le(2);
+                status.encode(&mut buf);
+            }
+            Self::Error { message } => {
+                buf.put_u32_le(3);
+                buf.put_u32_le(message.len() as u32);
+                buf.put_slice(message.as_bytes());
+            }
+            Self::State(state) => {
+                buf.put_u32_le(4);
+                buf.put_u32_le(state.len() as u32);
+                buf.put_slice(state);
+            }
+            Self::Log(entries) => {
+                buf.put_u32_le(5);
+                buf.put_u32_le(entries.len() as u32);
+                for entry in entries {
+                    entry.encode(&mut buf);
+                }
+            }
+            Self::Addresses(addrs) => {
+                buf.put_u32_le(6);
+                buf.put_u32_le(addrs.len() as u32);
+                for addr in addrs {
+                    buf.put_slice(&addr.addr.octets());
+                    buf.put_u8(addr.prefix_length);
+                    buf.put_u8(addr.present as u8);
+                }
+            }
+            Self::Added => {
+                buf.put_u32_le(7);
+            }
+            Self::Updated => {
+                buf.put_u32_le(8);
+            }
+            Self::Hello { server_version } => {
+                buf.put_u32_le(9);
+                buf.put_u32_le(*server_version);
+            }
+            Self::Prefix(prefix) => {
+                buf.put_u32_le(10);
+                match prefix {
+                    Some(prefix) => {
+                        buf.put_u8(1);
+                        encode_prefix(prefix, &mut buf);
+                    }
+                    None => buf.put_u8(0),
+                }
             }
         }
+
+        remaining - buf.remaining_mut()
     }
 
     pub fn decode<B>(mut buf: B) -> Result<Self, Error>
@@ -311,71 +738,1314 @@ impl Response {
         }
 
         match buf.get_u32_le() {
-            0 => Ok(Self::Ok),
-            _ => Err(Error::Eof),
-        }
-    }
-}
+            0 => {
+                if buf.remaining() < 4 {
+                    return Err(Error::Eof);
+                }
 
-#[derive(Debug)]
-pub enum Error {
-    Eof,
-    Io(io::Error),
-}
+                let affected = buf.get_u32_le();
+                Ok(Self::Ok { affected })
+            }
+            1 => {
+                if buf.remaining() < 8 + 1 {
+                    return Err(Error::Eof);
+                }
 
-pub struct Connection {
-    stream: UnixStream,
-}
+                let uptime = Duration::from_secs(buf.get_u64_le());
+                let healthy = buf.get_u8() != 0;
+                Ok(Self::Pong { uptime, healthy })
+            }
+            2 => Ok(Self::Status(Status::decode(&mut buf)?)),
+            3 => {
+                if buf.remaining() < 4 {
+                    return Err(Error::Eof);
+                }
 
-impl Connection {
-    pub fn new() -> Result<Self, io::Error> {
-        let stream = UnixStream::connect(CONTROL_SOCKET_ADDR)?;
+                let len = buf.get_u32_le() as usize;
+                if buf.remaining() < len {
+                    return Err(Error::Eof);
+                }
 
-        Ok(Self { stream })
-    }
+                let mut message = vec![0; len];
+                buf.copy_to_slice(&mut message);
+                Ok(Self::Error {
+                    message: String::from_utf8_lossy(&message).into_owned(),
+                })
+            }
+            4 => {
+                if buf.remaining() < 4 {
+                    return Err(Error::Eof);
+                }
 
-    pub fn send(&mut self, req: Request) -> Result<Response, Error> {
-        let mut buf = Vec::new();
-        req.encode(&mut buf);
+                let len = buf.get_u32_le() as usize;
+                if buf.remaining() < len {
+                    return Err(Error::Eof);
+                }
 
-        let mut buf_with_len = Vec::new();
-        buf_with_len.extend((buf.len() as u32).to_le_bytes());
-        buf_with_len.extend(buf);
+                let mut state = vec![0; len];
+                buf.copy_to_slice(&mut state);
+                Ok(Self::State(state))
+            }
+            5 => {
+                if buf.remaining() < 4 {
+                    return Err(Error::Eof);
+                }
 
-        self.stream.write_all(&buf_with_len).map_err(Error::Io)?;
+                let len = buf.get_u32_le();
 
-        let mut len = [0; 4];
-        self.stream.read_exact(&mut len).map_err(Error::Io)?;
+                let mut entries = Vec::new();
+                for _ in 0..len {
+                    entries.push(LogEntry::decode(&mut buf)?);
+                }
 
-        let len = u32::from_le_bytes(len);
+                Ok(Self::Log(entries))
+            }
+            6 => {
+                if buf.remaining() < 4 {
+                    return Err(Error::Eof);
+                }
 
-        let mut buf = vec![0; len as usize];
-        self.stream.read_exact(&mut buf).map_err(Error::Io)?;
+                let len = buf.get_u32_le();
 
-        Response::decode(&buf[..])
-    }
-}
+                let mut addrs = Vec::new();
+                for _ in 0..len {
+                    if buf.remaining() < 16 + 1 + 1 {
+                        return Err(Error::Eof);
+                    }
 
-#[cfg(test)]
-mod tests {
-    use std::net::Ipv6Addr;
-    use std::time::Duration;
+                    let mut addr = [0; 16];
+                    buf.copy_to_slice(&mut addr);
+                    let prefix_length = buf.get_u8();
+                    let present = buf.get_u8() != 0;
 
-    use crate::{Lifetime, Prefix, Request};
+                    addrs.push(ConfiguredAddress {
+                        addr: Ipv6Addr::from(addr),
+                        prefix_length,
+                        present,
+                    });
+                }
 
-    #[test]
-    fn encode_decode() {
-        let req = Request::AddPrefix(Prefix {
-            prefix: Ipv6Addr::UNSPECIFIED,
-            prefix_length: 0,
-            preferred_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
-            valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
-        });
+                Ok(Self::Addresses(addrs))
+            }
+            7 => Ok(Self::Added),
+            8 => Ok(Self::Updated),
+            9 => {
+                if buf.remaining() < 4 {
+                    return Err(Error::Eof);
+                }
 
-        let mut buf = Vec::new();
-        req.encode(&mut buf);
+                let server_version = buf.get_u32_le();
+                Ok(Self::Hello { server_version })
+            }
+            10 => {
+                if buf.remaining() < 1 {
+                    return Err(Error::Eof);
+                }
 
-        let output = Request::decode(&buf[..]).unwrap();
-        assert_eq!(req, output);
+                match buf.get_u8() {
+                    0 => Ok(Self::Prefix(None)),
+                    _ => Ok(Self::Prefix(Some(decode_prefix(&mut buf)?))),
+                }
+            }
+            _ => Err(Error::Eof),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Status {
+    /// The most recently received RSs, oldest first.
+    pub solicitations: Vec<Solicitation>,
+    /// The number of RSs that were dropped because the daemon was overloaded.
+    pub dropped_solicitations: u64,
+    /// The size and contents of the sent RAs.
+    pub advertisements: AdvertisementStats,
+    /// Whether sending RAs is paused with [`Request::Pause`].
+    pub paused: bool,
+    /// The configured prefixes, excluding withdrawn ones.
+    pub prefixes: Vec<Prefix>,
+    /// The configured DNS servers.
+    pub dns_servers: Vec<ConfiguredDnsServer>,
+}
+
+/// The size and contents of the sent RAs, to notice RAs growing towards the
+/// MTU before they are truncated or dropped.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AdvertisementStats {
+    /// The size of the last sent RA in bytes.
+    pub size: u32,
+    /// The size of the largest sent RA in bytes.
+    pub max_size: u32,
+    /// The number of Prefix Information options in the last sent RA.
+    pub prefix_options: u32,
+    /// The number of DNS options in the last sent RA.
+    pub dns_options: u32,
+    /// The number of all other options in the last sent RA.
+    pub other_options: u32,
+}
+
+impl Status {
+    fn encode<B>(&self, mut buf: B)
+    where
+        B: BufMut,
+    {
+        buf.put_u64_le(self.dropped_solicitations);
+
+        buf.put_u32_le(self.advertisements.size);
+        buf.put_u32_le(self.advertisements.max_size);
+        buf.put_u32_le(self.advertisements.prefix_options);
+        buf.put_u32_le(self.advertisements.dns_options);
+        buf.put_u32_le(self.advertisements.other_options);
+        buf.put_u8(self.paused as u8);
+
+        buf.put_u32_le(self.solicitations.len() as u32);
+        for sol in &self.solicitations {
+            buf.put_slice(&sol.addr.octets());
+
+            match sol.link_layer_addr {
+                Some(addr) => {
+                    buf.put_u8(1);
+                    buf.put_slice(&addr);
+                }
+                None => {
+                    buf.put_u8(0);
+                    buf.put_slice(&[0; 6]);
+                }
+            }
+
+            buf.put_u32_le(sol.elapsed.as_secs() as u32);
+        }
+
+        buf.put_u32_le(self.prefixes.len() as u32);
+        for prefix in &self.prefixes {
+            encode_prefix(prefix, &mut buf);
+        }
+
+        buf.put_u32_le(self.dns_servers.len() as u32);
+        for server in &self.dns_servers {
+            buf.put_slice(&server.addr.octets());
+            encode_label(server.label.as_deref(), &mut buf);
+        }
+    }
+
+    fn decode<B>(mut buf: B) -> Result<Self, Error>
+    where
+        B: Buf,
+    {
+        if buf.remaining() < 8 + 5 * 4 + 1 + 4 {
+            return Err(Error::Eof);
+        }
+
+        let dropped_solicitations = buf.get_u64_le();
+        let advertisements = AdvertisementStats {
+            size: buf.get_u32_le(),
+            max_size: buf.get_u32_le(),
+            prefix_options: buf.get_u32_le(),
+            dns_options: buf.get_u32_le(),
+            other_options: buf.get_u32_le(),
+        };
+        let paused = buf.get_u8() != 0;
+        let len = buf.get_u32_le();
+
+        let mut solicitations = Vec::new();
+        for _ in 0..len {
+            if buf.remaining() < 16 + 1 + 6 + 4 {
+                return Err(Error::Eof);
+            }
+
+            let mut addr = [0; 16];
+            buf.copy_to_slice(&mut addr);
+
+            let has_link_layer_addr = buf.get_u8() != 0;
+            let mut link_layer_addr = [0; 6];
+            buf.copy_to_slice(&mut link_layer_addr);
+
+            let elapsed = Duration::from_secs(buf.get_u32_le().into());
+
+            solicitations.push(Solicitation {
+                addr: Ipv6Addr::from(addr),
+                link_layer_addr: has_link_layer_addr.then_some(link_layer_addr),
+                elapsed,
+            });
+        }
+
+        if buf.remaining() < 4 {
+            return Err(Error::Eof);
+        }
+        let len = buf.get_u32_le();
+
+        let mut prefixes = Vec::new();
+        for _ in 0..len {
+            prefixes.push(decode_prefix(&mut buf)?);
+        }
+
+        if buf.remaining() < 4 {
+            return Err(Error::Eof);
+        }
+        let len = buf.get_u32_le();
+
+        let mut dns_servers = Vec::new();
+        for _ in 0..len {
+            if buf.remaining() < 16 {
+                return Err(Error::Eof);
+            }
+
+            let mut addr = [0; 16];
+            buf.copy_to_slice(&mut addr);
+            let label = decode_label(&mut buf)?;
+
+            dns_servers.push(ConfiguredDnsServer {
+                addr: Ipv6Addr::from(addr),
+                label,
+            });
+        }
+
+        Ok(Self {
+            solicitations,
+            dropped_solicitations,
+            advertisements,
+            paused,
+            prefixes,
+            dns_servers,
+        })
+    }
+}
+
+/// An address the daemon configured on the interface for an advertised prefix.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ConfiguredAddress {
+    pub addr: Ipv6Addr,
+    pub prefix_length: u8,
+    /// Whether the address is still configured on the interface. Addresses
+    /// may disappear if DAD fails or they are removed manually.
+    pub present: bool,
+}
+
+/// A DNS server configured on the daemon.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfiguredDnsServer {
+    pub addr: Ipv6Addr,
+    pub label: Option<String>,
+}
+
+/// A received Router Solicitation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Solicitation {
+    /// The source address of the RS.
+    pub addr: Ipv6Addr,
+    /// The source link-layer address option of the RS, if present.
+    pub link_layer_addr: Option<[u8; 6]>,
+    /// The time since the RS was received.
+    pub elapsed: Duration,
+}
+
+/// An event recorded by the daemon.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    /// The time since the event happened.
+    pub elapsed: Duration,
+    pub event: Event,
+}
+
+impl LogEntry {
+    fn encode<B>(&self, mut buf: B)
+    where
+        B: BufMut,
+    {
+        buf.put_u32_le(self.elapsed.as_secs() as u32);
+
+        match self.event {
+            Event::PrefixAdded(addr, len) => {
+                buf.put_u8(1);
+                buf.put_slice(&addr.octets());
+                buf.put_u8(len);
+            }
+            Event::PrefixRemoved(addr, len) => {
+                buf.put_u8(2);
+                buf.put_slice(&addr.octets());
+                buf.put_u8(len);
+            }
+            Event::PrefixExpired(addr, len) => {
+                buf.put_u8(3);
+                buf.put_slice(&addr.octets());
+                buf.put_u8(len);
+            }
+            Event::DnsServerAdded(addr) => {
+                buf.put_u8(4);
+                buf.put_slice(&addr.octets());
+            }
+            Event::DnsServerRemoved(addr) => {
+                buf.put_u8(5);
+                buf.put_slice(&addr.octets());
+            }
+            Event::Reloaded => {
+                buf.put_u8(6);
+            }
+            Event::RouterAdvertisementSent(addr) => {
+                buf.put_u8(7);
+                buf.put_slice(&addr.octets());
+            }
+            Event::RouterSolicitationRejected(addr) => {
+                buf.put_u8(8);
+                buf.put_slice(&addr.octets());
+            }
+            Event::Paused => {
+                buf.put_u8(9);
+            }
+            Event::Resumed => {
+                buf.put_u8(10);
+            }
+        }
+    }
+
+    fn decode<B>(mut buf: B) -> Result<Self, Error>
+    where
+        B: Buf,
+    {
+        if buf.remaining() < 4 + 1 {
+            return Err(Error::Eof);
+        }
+
+        let elapsed = Duration::from_secs(buf.get_u32_le().into());
+
+        let event = match buf.get_u8() {
+            6 => Event::Reloaded,
+            9 => Event::Paused,
+            10 => Event::Resumed,
+            tag @ 1..=3 => {
+                if buf.remaining() < 16 + 1 {
+                    return Err(Error::Eof);
+                }
+
+                let mut addr = [0; 16];
+                buf.copy_to_slice(&mut addr);
+                let addr = Ipv6Addr::from(addr);
+                let len = buf.get_u8();
+
+                match tag {
+                    1 => Event::PrefixAdded(addr, len),
+                    2 => Event::PrefixRemoved(addr, len),
+                    _ => Event::PrefixExpired(addr, len),
+                }
+            }
+            tag @ (4 | 5 | 7 | 8) => {
+                if buf.remaining() < 16 {
+                    return Err(Error::Eof);
+                }
+
+                let mut addr = [0; 16];
+                buf.copy_to_slice(&mut addr);
+                let addr = Ipv6Addr::from(addr);
+
+                match tag {
+                    4 => Event::DnsServerAdded(addr),
+                    5 => Event::DnsServerRemoved(addr),
+                    7 => Event::RouterAdvertisementSent(addr),
+                    _ => Event::RouterSolicitationRejected(addr),
+                }
+            }
+            _ => return Err(Error::Eof),
+        };
+
+        Ok(Self { elapsed, event })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A prefix and its length was added.
+    PrefixAdded(Ipv6Addr, u8),
+    /// A prefix and its length was removed.
+    PrefixRemoved(Ipv6Addr, u8),
+    /// The valid lifetime of a prefix and its length expired.
+    PrefixExpired(Ipv6Addr, u8),
+    DnsServerAdded(Ipv6Addr),
+    DnsServerRemoved(Ipv6Addr),
+    /// The state was replaced by a reload or import.
+    Reloaded,
+    /// A RA was sent to the address.
+    RouterAdvertisementSent(Ipv6Addr),
+    /// An invalid RS was received from the address.
+    RouterSolicitationRejected(Ipv6Addr),
+    /// Sending RAs was paused.
+    Paused,
+    /// Sending RAs was resumed.
+    Resumed,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Eof,
+    Io(io::Error),
+    /// The prefix length is longer than 128 bits.
+    InvalidPrefixLength(u8),
+    /// The preferred lifetime of a prefix is longer than its valid lifetime.
+    PreferredExceedsValid,
+    /// An extra address of a prefix, given here, is not in the prefix.
+    AddressOutsidePrefix(Ipv6Addr),
+    /// The daemon uses a different [`PROTOCOL_VERSION`], given here.
+    IncompatibleVersion(u32),
+    /// A frame, whose length is given here, is longer than [`MAX_FRAME_LEN`].
+    FrameTooLarge(u32),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Reads length-prefixed [`Request`]s or [`Response`]s from a stream.
+///
+/// Frames can be read back to back from the same stream. Iterating yields
+/// [`Response`]s until the stream ends between two frames.
+#[derive(Debug)]
+pub struct FrameReader<R> {
+    reader: R,
+}
+
+impl<R> FrameReader<R>
+where
+    R: Read,
+{
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads the next frame as a [`Request`].
+    ///
+    /// Returns [`Error::Eof`] if the stream ends before the whole frame was read
+    /// and [`Error::FrameTooLarge`] if the frame is longer than [`MAX_FRAME_LEN`].
+    pub fn read_request(&mut self) -> Result<Request, Error> {
+        let buf = self.read_frame()?.ok_or(Error::Eof)?;
+        Request::decode(&buf[..])
+    }
+
+    /// Reads the next frame as a [`Response`].
+    ///
+    /// Returns [`Error::Eof`] if the stream ends before the whole frame was read
+    /// and [`Error::FrameTooLarge`] if the frame is longer than [`MAX_FRAME_LEN`].
+    pub fn read_response(&mut self) -> Result<Response, Error> {
+        let buf = self.read_frame()?.ok_or(Error::Eof)?;
+        Response::decode(&buf[..])
+    }
+
+    /// Reads the body of the next frame. Returns `None` if the stream ended
+    /// before the first byte of the frame.
+    fn read_frame(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut header = [0; FRAME_HEADER_LEN];
+        let mut filled = 0;
+        while filled < header.len() {
+            match self.reader.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(Error::Eof),
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let len = u32::from_le_bytes(header);
+        if len > MAX_FRAME_LEN {
+            return Err(Error::FrameTooLarge(len));
+        }
+
+        // Don't trust the length for the allocation, the peer may
+        // close the connection before sending the entire frame.
+        let mut buf = Vec::new();
+        (&mut self.reader).take(len.into()).read_to_end(&mut buf)?;
+        if buf.len() != len as usize {
+            return Err(Error::Eof);
+        }
+
+        Ok(Some(buf))
+    }
+}
+
+impl<R> Iterator for FrameReader<R>
+where
+    R: Read,
+{
+    type Item = Result<Response, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_frame() {
+            Ok(Some(buf)) => Some(Response::decode(&buf[..])),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+/// Writes length-prefixed [`Request`]s or [`Response`]s to a stream.
+///
+/// This is the counterpart of [`FrameReader`].
+#[derive(Debug)]
+pub struct FrameWriter<W> {
+    writer: W,
+}
+
+impl<W> FrameWriter<W>
+where
+    W: Write,
+{
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes `req` as a single frame.
+    pub fn write_request(&mut self, req: &Request) -> Result<(), Error> {
+        let mut buf = vec![0; FRAME_HEADER_LEN];
+        req.encode(&mut buf);
+        self.write_frame(buf)
+    }
+
+    /// Writes `resp` as a single frame.
+    pub fn write_response(&mut self, resp: &Response) -> Result<(), Error> {
+        let mut buf = vec![0; FRAME_HEADER_LEN];
+        resp.encode(&mut buf);
+        self.write_frame(buf)
+    }
+
+    /// Fills in the header of `buf` and writes it. The header and the body
+    /// are written at once so that frames of concurrent writers never
+    /// interleave on a shared socket.
+    fn write_frame(&mut self, mut buf: Vec<u8>) -> Result<(), Error> {
+        let len = (buf.len() - FRAME_HEADER_LEN) as u32;
+        buf[..FRAME_HEADER_LEN].copy_from_slice(&len.to_le_bytes());
+        self.writer.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+/// A blocking connection to the control socket of the daemon.
+///
+/// Only available with the `unix-socket` feature.
+#[cfg(feature = "unix-socket")]
+pub struct Connection {
+    stream: Stream,
+}
+
+#[cfg(feature = "unix-socket")]
+impl Connection {
+    /// Connects to the control socket of the daemon.
+    ///
+    /// Returns [`Error::IncompatibleVersion`] if the daemon uses a different
+    /// protocol version.
+    pub fn new() -> Result<Self, Error> {
+        let stream = UnixStream::connect(CONTROL_SOCKET_ADDR)?;
+        let mut conn = Self::from_stream(Stream::Unix(stream))?;
+        conn.hello()?;
+        Ok(conn)
+    }
+
+    /// Connects to the TCP control listener of the daemon at `addr`.
+    ///
+    /// Returns [`Error::IncompatibleVersion`] if the daemon uses a different
+    /// protocol version.
+    pub fn connect_tcp<A>(addr: A) -> Result<Self, Error>
+    where
+        A: ToSocketAddrs,
+    {
+        let stream = TcpStream::connect(addr)?;
+        let mut conn = Self::from_stream(Stream::Tcp(stream))?;
+        conn.hello()?;
+        Ok(conn)
+    }
+
+    /// Checks that the daemon uses the same protocol version.
+    fn hello(&mut self) -> Result<(), Error> {
+        let req = Request::Hello {
+            client_version: PROTOCOL_VERSION,
+        };
+
+        match self.send(req) {
+            Ok(Response::Hello { server_version }) if server_version == PROTOCOL_VERSION => Ok(()),
+            Ok(Response::Hello { server_version }) => {
+                Err(Error::IncompatibleVersion(server_version))
+            }
+            // Daemons before the handshake was added answer with something
+            // else or close the connection.
+            Ok(_) | Err(Error::Eof) => Err(Error::IncompatibleVersion(0)),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn from_stream(stream: Stream) -> Result<Self, io::Error> {
+        stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+        Ok(Self { stream })
+    }
+
+    /// Sets how long [`send`] waits for the response. `None` waits forever.
+    ///
+    /// [`send`]: Self::send
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), io::Error> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    /// Sends `req` and waits for the response.
+    ///
+    /// Returns [`Error::Eof`] if the daemon closes the connection before the
+    /// whole response was received.
+    pub fn send(&mut self, req: Request) -> Result<Response, Error> {
+        FrameWriter::new(&mut self.stream).write_request(&req)?;
+        FrameReader::new(&mut self.stream).read_response()
+    }
+}
+
+/// The transport of a [`Connection`].
+#[cfg(feature = "unix-socket")]
+enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+#[cfg(feature = "unix-socket")]
+impl Stream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), io::Error> {
+        match self {
+            Self::Unix(stream) => stream.set_read_timeout(timeout),
+            Self::Tcp(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+}
+
+#[cfg(feature = "unix-socket")]
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Unix(stream) => stream.read(buf),
+            Self::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+#[cfg(feature = "unix-socket")]
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Unix(stream) => stream.write(buf),
+            Self::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Unix(stream) => stream.flush(),
+            Self::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        AdvertisementStats, ConfiguredAddress, ConfiguredDnsServer, Error, Event, FrameReader,
+        FrameWriter, Lifetime, LogEntry, Prefix, Request, Response, Solicitation, Status,
+        MAX_FRAME_LEN,
+    };
+
+    #[test]
+    fn encode_decode() {
+        let req = Request::AddPrefix(Prefix {
+            prefix: Ipv6Addr::UNSPECIFIED,
+            prefix_length: 0,
+            preferred_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+            valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+            deprecate_before: Some(Duration::from_secs(600)),
+            on_link: true,
+            label: Some(String::from("guest-vlan")),
+            extra_addrs: vec![Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)],
+        });
+
+        let mut buf = Vec::new();
+        let len = req.encode(&mut buf);
+        assert_eq!(len, buf.len());
+
+        let output = Request::decode(&buf[..]).unwrap();
+        assert_eq!(req, output);
+    }
+
+    #[test]
+    fn frames_back_to_back() {
+        let resps = [
+            Response::Ok { affected: 1 },
+            Response::Error {
+                message: String::from("no such prefix"),
+            },
+            Response::Added,
+            Response::Hello { server_version: 1 },
+        ];
+
+        let mut writer = FrameWriter::new(Vec::new());
+        for resp in &resps {
+            writer.write_response(resp).unwrap();
+        }
+        let buf = writer.into_inner();
+
+        let output: Vec<Response> = FrameReader::new(&buf[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(output, resps);
+    }
+
+    #[test]
+    fn frames_requests() {
+        let reqs = [Request::Ping, Request::GetStatus, Request::Ping];
+
+        let mut writer = FrameWriter::new(Vec::new());
+        for req in &reqs {
+            writer.write_request(req).unwrap();
+        }
+        let buf = writer.into_inner();
+
+        // Each frame is the 4 byte header followed by the 4 byte tag.
+        assert_eq!(buf.len(), 3 * 8);
+        assert_eq!(&buf[8..16], &[4, 0, 0, 0, 8, 0, 0, 0]);
+
+        let mut reader = FrameReader::new(&buf[..]);
+        for req in reqs {
+            assert_eq!(reader.read_request().unwrap(), req);
+        }
+        assert!(matches!(reader.read_request(), Err(Error::Eof)));
+    }
+
+    #[test]
+    fn frames_too_large() {
+        let mut buf = (MAX_FRAME_LEN + 1).to_le_bytes().to_vec();
+        buf.extend([0; 16]);
+
+        let mut reader = FrameReader::new(&buf[..]);
+        assert!(matches!(
+            reader.read_request(),
+            Err(Error::FrameTooLarge(len)) if len == MAX_FRAME_LEN + 1
+        ));
+        // The body is left unread.
+        assert_eq!(reader.get_ref().len(), 16);
+    }
+
+    #[test]
+    fn frames_truncated() {
+        let mut writer = FrameWriter::new(Vec::new());
+        writer.write_response(&Response::Added).unwrap();
+        writer
+            .write_response(&Response::Ok { affected: 2 })
+            .unwrap();
+        let buf = writer.into_inner();
+
+        // The first frame is complete, the second one is cut off.
+        let mut reader = FrameReader::new(&buf[..buf.len() - 1]);
+        assert_eq!(reader.next().unwrap().unwrap(), Response::Added);
+        assert!(matches!(reader.next(), Some(Err(Error::Eof))));
+    }
+
+    #[test]
+    fn encode_decode_infinite() {
+        let req = Request::AddPrefix(Prefix {
+            prefix: Ipv6Addr::UNSPECIFIED,
+            prefix_length: 64,
+            preferred_lifetime: Lifetime::Infinite,
+            valid_lifetime: Lifetime::Infinite,
+            deprecate_before: None,
+            on_link: true,
+            label: None,
+            extra_addrs: Vec::new(),
+        });
+
+        let mut buf = Vec::new();
+        req.encode(&mut buf);
+
+        let output = Request::decode(&buf[..]).unwrap();
+        assert_eq!(req, output);
+        assert_eq!(Lifetime::Infinite.duration().as_secs(), u64::from(u32::MAX));
+    }
+
+    #[test]
+    fn encode_decode_infinite_sentinel() {
+        assert_eq!(Lifetime::from_secs(u64::from(u32::MAX)), Lifetime::Infinite);
+        assert_eq!(Lifetime::from_secs(u64::MAX), Lifetime::Infinite);
+        assert_eq!(
+            Lifetime::from_secs(3600),
+            Lifetime::Duration(Duration::from_secs(3600))
+        );
+
+        // Durations that do not fit are infinite, not truncated.
+        for secs in [u64::from(u32::MAX), u64::from(u32::MAX) + 1] {
+            let req = Request::RemovePrefix(Prefix {
+                prefix: Ipv6Addr::UNSPECIFIED,
+                prefix_length: 64,
+                preferred_lifetime: Lifetime::Duration(Duration::from_secs(secs)),
+                valid_lifetime: Lifetime::Duration(Duration::from_secs(secs)),
+                deprecate_before: None,
+                on_link: true,
+                label: None,
+                extra_addrs: Vec::new(),
+            });
+
+            let mut buf = Vec::new();
+            req.encode(&mut buf);
+
+            let Request::RemovePrefix(prefix) = Request::decode(&buf[..]).unwrap() else {
+                panic!("decoded a different request");
+            };
+            assert_eq!(prefix.preferred_lifetime, Lifetime::Infinite);
+            assert_eq!(prefix.valid_lifetime, Lifetime::Infinite);
+        }
+
+        let req = Request::RemovePrefix(Prefix {
+            prefix: Ipv6Addr::UNSPECIFIED,
+            prefix_length: 64,
+            preferred_lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+            valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+            deprecate_before: None,
+            on_link: true,
+            label: None,
+            extra_addrs: Vec::new(),
+        });
+
+        let mut buf = Vec::new();
+        req.encode(&mut buf);
+        assert_eq!(Request::decode(&buf[..]).unwrap(), req);
+    }
+
+    #[test]
+    fn prefix_normalize() {
+        let prefix = |prefix: &str, prefix_length| Prefix {
+            prefix: prefix.parse().unwrap(),
+            prefix_length,
+            preferred_lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+            valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+            deprecate_before: None,
+            on_link: true,
+            label: None,
+            extra_addrs: Vec::new(),
+        };
+
+        for (input, prefix_length, output) in [
+            ("2001:db8::1", 128, "2001:db8::1"),
+            ("2001:db8:1:2:3:4:5:6", 64, "2001:db8:1:2::"),
+            ("2001:db8:1:2ff::", 60, "2001:db8:1:2f0::"),
+            ("2001:db8:1:2ff::", 57, "2001:db8:1:280::"),
+            ("2001:db8:1:2ff::", 56, "2001:db8:1:200::"),
+            ("2001:db8:1:2ff::", 52, "2001:db8:1::"),
+            ("2001:dbf::", 29, "2001:db8::"),
+            ("2001:db8::1", 1, "::"),
+            ("ffff::", 1, "8000::"),
+            ("2001:db8::1", 0, "::"),
+        ] {
+            let output: Ipv6Addr = output.parse().unwrap();
+            let normalized = prefix(input, prefix_length).normalize().unwrap();
+            assert_eq!(normalized.prefix, output, "{}/{}", input, prefix_length);
+            assert_eq!(normalized.prefix_length, prefix_length);
+        }
+
+        assert!(matches!(
+            prefix("2001:db8::", 129).normalize(),
+            Err(Error::InvalidPrefixLength(129))
+        ));
+
+        let mut invalid = prefix("2001:db8::", 64);
+        invalid.preferred_lifetime = Lifetime::Infinite;
+        assert!(matches!(
+            invalid.clone().normalize(),
+            Err(Error::PreferredExceedsValid)
+        ));
+
+        invalid.preferred_lifetime = Lifetime::Duration(Duration::from_secs(7200));
+        assert!(matches!(
+            invalid.normalize(),
+            Err(Error::PreferredExceedsValid)
+        ));
+
+        let mut valid = prefix("2001:db8::", 64);
+        valid.valid_lifetime = Lifetime::Infinite;
+        valid.preferred_lifetime = Lifetime::Infinite;
+        assert!(valid.normalize().is_ok());
+
+        let outside: Ipv6Addr = "2001:db8:1::1".parse().unwrap();
+        let mut invalid = prefix("2001:db8::", 64);
+        invalid.extra_addrs = vec!["2001:db8::1".parse().unwrap(), outside];
+        assert!(matches!(
+            invalid.normalize(),
+            Err(Error::AddressOutsidePrefix(addr)) if addr == outside
+        ));
+
+        let mut valid = prefix("2001:db8::1", 64);
+        valid.extra_addrs = vec![
+            "2001:db8::53".parse().unwrap(),
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::53".parse().unwrap(),
+        ];
+        assert_eq!(
+            valid.normalize().unwrap().extra_addrs,
+            [
+                "2001:db8::1".parse::<Ipv6Addr>().unwrap(),
+                "2001:db8::53".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn lifetime_remaining() {
+        let Some(since) = Instant::now().checked_sub(Duration::from_secs(600)) else {
+            // The monotonic clock started less than 10 minutes ago.
+            return;
+        };
+
+        let lifetime = Lifetime::Duration(Duration::from_secs(3600));
+        assert_eq!(lifetime.duration(), Duration::from_secs(3600));
+        assert!(lifetime.remaining(since) <= Duration::from_secs(3000));
+
+        let lifetime = Lifetime::Duration(Duration::from_secs(300));
+        assert_eq!(lifetime.remaining(since), Duration::ZERO);
+
+        assert_eq!(Lifetime::Infinite.remaining(since), Lifetime::INFINITE);
+    }
+
+    #[test]
+    fn response_encode_decode() {
+        let resp = Response::Ok { affected: 1 };
+
+        let mut buf = Vec::new();
+        resp.encode(&mut buf);
+
+        let output = Response::decode(&buf[..]).unwrap();
+        assert_eq!(resp, output);
+
+        for resp in [Response::Added, Response::Updated] {
+            let mut buf = Vec::new();
+            resp.encode(&mut buf);
+
+            let output = Response::decode(&buf[..]).unwrap();
+            assert_eq!(resp, output);
+            assert!(output.is_ok());
+        }
+
+        let resp = Response::Error {
+            message: String::from("failed to load database"),
+        };
+
+        let mut buf = Vec::new();
+        resp.encode(&mut buf);
+
+        let output = Response::decode(&buf[..]).unwrap();
+        assert_eq!(resp, output);
+    }
+
+    #[test]
+    fn log_encode_decode() {
+        let resp = Response::Log(vec![
+            LogEntry {
+                elapsed: Duration::from_secs(60),
+                event: Event::PrefixAdded(Ipv6Addr::UNSPECIFIED, 64),
+            },
+            LogEntry {
+                elapsed: Duration::from_secs(30),
+                event: Event::Reloaded,
+            },
+            LogEntry {
+                elapsed: Duration::from_secs(20),
+                event: Event::Paused,
+            },
+            LogEntry {
+                elapsed: Duration::from_secs(1),
+                event: Event::RouterSolicitationRejected(Ipv6Addr::LOCALHOST),
+            },
+        ]);
+
+        let mut buf = Vec::new();
+        resp.encode(&mut buf);
+
+        let output = Response::decode(&buf[..]).unwrap();
+        assert_eq!(resp, output);
+    }
+
+    #[test]
+    fn addresses_encode_decode() {
+        let resp = Response::Addresses(vec![
+            ConfiguredAddress {
+                addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0x211, 0x22ff, 0xfe33, 0x4455),
+                prefix_length: 64,
+                present: true,
+            },
+            ConfiguredAddress {
+                addr: Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0x211, 0x22ff, 0xfe33, 0x4455),
+                prefix_length: 64,
+                present: false,
+            },
+        ]);
+
+        let mut buf = Vec::new();
+        resp.encode(&mut buf);
+
+        let output = Response::decode(&buf[..]).unwrap();
+        assert_eq!(resp, output);
+    }
+
+    #[test]
+    fn get_prefix_encode_decode() {
+        let req = Request::GetPrefix(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0));
+
+        let mut buf = Vec::new();
+        req.encode(&mut buf);
+        assert_eq!(Request::decode(&buf[..]).unwrap(), req);
+
+        for resp in [
+            Response::Prefix(None),
+            Response::Prefix(Some(Prefix {
+                prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+                prefix_length: 64,
+                preferred_lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+                valid_lifetime: Lifetime::Infinite,
+                deprecate_before: None,
+                on_link: false,
+                label: Some(String::from("guest-vlan")),
+                extra_addrs: vec![Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53)],
+            })),
+        ] {
+            let mut buf = Vec::new();
+            resp.encode(&mut buf);
+            assert_eq!(Response::decode(&buf[..]).unwrap(), resp);
+        }
+    }
+
+    #[test]
+    fn status_encode_decode() {
+        let resp = Response::Status(Status {
+            solicitations: vec![
+                Solicitation {
+                    addr: Ipv6Addr::UNSPECIFIED,
+                    link_layer_addr: None,
+                    elapsed: Duration::from_secs(5),
+                },
+                Solicitation {
+                    addr: Ipv6Addr::LOCALHOST,
+                    link_layer_addr: Some([1, 2, 3, 4, 5, 6]),
+                    elapsed: Duration::from_secs(1),
+                },
+            ],
+            dropped_solicitations: 3,
+            advertisements: AdvertisementStats {
+                size: 120,
+                max_size: 1232,
+                prefix_options: 2,
+                dns_options: 1,
+                other_options: 2,
+            },
+            paused: true,
+            prefixes: vec![Prefix {
+                prefix: "2001:db8::".parse().unwrap(),
+                prefix_length: 64,
+                preferred_lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+                valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+                deprecate_before: None,
+                on_link: true,
+                label: Some(String::from("guest-vlan")),
+                extra_addrs: Vec::new(),
+            }],
+            dns_servers: vec![
+                ConfiguredDnsServer {
+                    addr: "2001:db8::53".parse().unwrap(),
+                    label: Some(String::from("iot")),
+                },
+                ConfiguredDnsServer {
+                    addr: "2001:db8::54".parse().unwrap(),
+                    label: None,
+                },
+            ],
+        });
+
+        let mut buf = Vec::new();
+        resp.encode(&mut buf);
+
+        let output = Response::decode(&buf[..]).unwrap();
+        assert_eq!(resp, output);
+    }
+}
+
+#[cfg(all(test, feature = "unix-socket"))]
+mod connection_tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::{Connection, Error, Request, Response, Stream, PROTOCOL_VERSION};
+
+    /// Spawns a server that reads a single request, answers with `resp` and
+    /// closes the connection.
+    fn mock_server(resp: &'static [u8]) -> (Connection, thread::JoinHandle<()>) {
+        let (client, mut server) = UnixStream::pair().unwrap();
+
+        let handle = thread::spawn(move || {
+            let mut len = [0; 4];
+            server.read_exact(&mut len).unwrap();
+            let mut buf = vec![0; u32::from_le_bytes(len) as usize];
+            server.read_exact(&mut buf).unwrap();
+
+            server.write_all(resp).unwrap();
+        });
+
+        (
+            Connection::from_stream(Stream::Unix(client)).unwrap(),
+            handle,
+        )
+    }
+
+    #[test]
+    fn send_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let (mut server, _) = listener.accept().unwrap();
+
+            let mut hello = vec![8, 0, 0, 0, 9, 0, 0, 0];
+            hello.extend(PROTOCOL_VERSION.to_le_bytes());
+
+            // Hello { server_version }, then Ok { affected: 1 }
+            for resp in [&hello[..], &[8, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0]] {
+                let mut len = [0; 4];
+                server.read_exact(&mut len).unwrap();
+                let mut buf = vec![0; u32::from_le_bytes(len) as usize];
+                server.read_exact(&mut buf).unwrap();
+
+                server.write_all(resp).unwrap();
+            }
+        });
+
+        let mut conn = Connection::connect_tcp(addr).unwrap();
+        let resp = conn.send(Request::Ping).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(resp, Response::Ok { affected: 1 });
+    }
+
+    #[test]
+    fn hello_version_mismatch() {
+        let mut resp = vec![8, 0, 0, 0, 9, 0, 0, 0];
+        resp.extend((PROTOCOL_VERSION + 1).to_le_bytes());
+        let resp: &'static [u8] = resp.leak();
+
+        let (mut conn, handle) = mock_server(resp);
+        let res = conn.hello();
+        handle.join().unwrap();
+
+        assert!(matches!(
+            res,
+            Err(Error::IncompatibleVersion(version)) if version == PROTOCOL_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn send_truncated_length() {
+        let (mut conn, handle) = mock_server(&[8, 0]);
+        let res = conn.send(Request::Ping);
+        handle.join().unwrap();
+
+        assert!(matches!(res, Err(Error::Eof)));
+    }
+
+    #[test]
+    fn send_truncated_body() {
+        // Declares 8 bytes, but only sends 2.
+        let (mut conn, handle) = mock_server(&[8, 0, 0, 0, 1, 0]);
+        let res = conn.send(Request::Ping);
+        handle.join().unwrap();
+
+        assert!(matches!(res, Err(Error::Eof)));
+    }
+
+    #[test]
+    fn send_timeout() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(Stream::Unix(client)).unwrap();
+        conn.set_timeout(Some(Duration::from_millis(10))).unwrap();
+
+        // Keep the connection open without sending the body.
+        server.write_all(&[8, 0, 0, 0]).unwrap();
+        assert!(matches!(conn.send(Request::Ping), Err(Error::Io(_))));
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use std::net::Ipv6Addr;
+    use std::time::{Duration, SystemTime};
+
+    use crate::{DnsServer, Lifetime, Prefix};
+
+    #[test]
+    fn lifetime_json() {
+        let until = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        for lifetime in [
+            Lifetime::Duration(Duration::from_secs(3600)),
+            Lifetime::Until(until),
+            Lifetime::Infinite,
+        ] {
+            let json = serde_json::to_string(&lifetime).unwrap();
+            assert_eq!(serde_json::from_str::<Lifetime>(&json).unwrap(), lifetime);
+        }
+
+        let json = serde_json::to_string(&Lifetime::Until(until)).unwrap();
+        assert_eq!(json, r#"{"until":1700000000}"#);
+    }
+
+    #[test]
+    fn prefix_json() {
+        let prefix = Prefix {
+            prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+            prefix_length: 64,
+            preferred_lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+            valid_lifetime: Lifetime::Infinite,
+            deprecate_before: Some(Duration::from_secs(600)),
+            on_link: false,
+            label: Some(String::from("guest-vlan")),
+            extra_addrs: Vec::new(),
+        };
+
+        let json = serde_json::to_string(&prefix).unwrap();
+        assert_eq!(serde_json::from_str::<Prefix>(&json).unwrap(), prefix);
+    }
+
+    #[test]
+    fn dns_server_json() {
+        let server = DnsServer {
+            addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53),
+            lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+            label: None,
+        };
+
+        let json = serde_json::to_string(&server).unwrap();
+        assert_eq!(serde_json::from_str::<DnsServer>(&json).unwrap(), server);
     }
 }
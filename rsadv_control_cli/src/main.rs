@@ -1,49 +1,140 @@
 use std::env::args;
+use std::io::{ErrorKind, Read, Write};
 use std::net::Ipv6Addr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use rsadv_control::{Connection, DnsServer, Lifetime, Prefix, Request};
+use log::LevelFilter;
+use rsadv_control::{
+    Connection, DnsServer, Error, Event, Lifetime, LogEntry, Prefix, Request, Response, Status,
+    PROTOCOL_VERSION,
+};
 
 fn main() {
     let mut args: Vec<String> = args().collect();
 
-    let mut conn = match Connection::new() {
-        Ok(conn) => conn,
+    // Strip all flags. Each occurrence of `-v`/`--verbose` increases the verbosity.
+    // With `--strict` we exit with an error if the request had no effect.
+    // With `--dry-run` the encoded request is printed instead of sent.
+    // With `--off-link` prefixes are not advertised as on-link.
+    let mut verbose = 0;
+    let mut strict = false;
+    let mut dry_run = false;
+    let mut on_link = true;
+    args.retain(|arg| match arg.as_str() {
+        "--strict" => {
+            strict = true;
+            false
+        }
+        "--dry-run" => {
+            dry_run = true;
+            false
+        }
+        "--off-link" => {
+            on_link = false;
+            false
+        }
+        "--verbose" => {
+            verbose += 1;
+            false
+        }
+        arg if arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| c == 'v') => {
+            verbose += arg.len() - 1;
+            false
+        }
+        _ => true,
+    });
+
+    init_logger(verbose);
+
+    // `--decode <hex>` prints the request encoded in `hex`.
+    if let Some(hex) = take_option(&mut args, "--decode") {
+        decode_request(&hex);
+        return;
+    }
+
+    let preferred_lifetime = take_option(&mut args, "--preferred").map(|s| parse_lifetime(&s));
+    let valid_lifetime = take_option(&mut args, "--valid").map(|s| parse_lifetime(&s));
+    // `--label <label>` annotates added prefixes and DNS servers.
+    let label = take_option(&mut args, "--label").filter(|s| !s.is_empty());
+    // `--extra-addrs <addr>,<addr>` configures more addresses for an added
+    // prefix.
+    let extra_addrs: Vec<Ipv6Addr> = take_option(&mut args, "--extra-addrs")
+        .map(|s| {
+            s.split(',')
+                .filter(|s| !s.is_empty())
+                .map(|addr| match addr.parse() {
+                    Ok(addr) => addr,
+                    Err(err) => {
+                        eprintln!("invalid address {}: {}", addr, err);
+                        std::process::exit(1);
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // With `--tcp <addr>` we connect to the TCP control listener instead of
+    // the Unix socket.
+    let tcp = take_option(&mut args, "--tcp");
+
+    // `--token <token>` authenticates the connection, falling back to
+    // `RSADV_TOKEN` if not given.
+    let token = take_option(&mut args, "--token")
+        .or_else(|| std::env::var("RSADV_TOKEN").ok())
+        .filter(|s| !s.is_empty());
+
+    // With `--wait <secs>` we retry connecting until the daemon is up.
+    let wait = take_option(&mut args, "--wait").map(|s| match s.parse() {
+        Ok(secs) => Duration::from_secs(secs),
         Err(err) => {
-            eprintln!("unable to connect to socket: {}", err);
+            eprintln!("invalid wait timeout {}: {}", s, err);
             std::process::exit(1);
         }
-    };
+    });
 
-    match args.get(1).map(|s| s.as_str()) {
+    let req = match args.get(1).map(|s| s.as_str()) {
         Some("prefix") => match args.get(2).map(|s| s.as_str()) {
             Some("add") => {
-                let prefix = args.get(3).unwrap();
-                let (prefix, prefix_length) = prefix.split_once("/").unwrap();
-                let prefix: Ipv6Addr = prefix.parse().unwrap();
-                let prefix_length: u8 = prefix_length.parse().unwrap();
+                let (prefix, prefix_length) = prefix_arg(args.get(3));
 
-                conn.send(Request::AddPrefix(Prefix {
+                Request::AddPrefix(normalize(Prefix {
                     prefix,
                     prefix_length,
-                    preferred_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
-                    valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+                    preferred_lifetime: preferred_lifetime
+                        .unwrap_or(Lifetime::Duration(Duration::from_secs(3600))),
+                    valid_lifetime: valid_lifetime
+                        .unwrap_or(Lifetime::Duration(Duration::from_secs(3600))),
+                    deprecate_before: None,
+                    on_link,
+                    label,
+                    extra_addrs,
                 }))
-                .unwrap();
+            }
+            Some("flush") => Request::FlushPrefixes,
+            Some("show") => {
+                // The prefix length is optional, prefixes are keyed by address.
+                let prefix = args.get(3).map_or("", |s| s.as_str());
+                match parse_prefix(prefix) {
+                    Ok((addr, _)) => Request::GetPrefix(addr),
+                    Err(err) => {
+                        eprintln!("invalid prefix {:?}: {}", prefix, err);
+                        std::process::exit(1);
+                    }
+                }
             }
             Some("rm") | Some("del") => {
-                let prefix = args.get(3).unwrap();
-                let (prefix, prefix_length) = prefix.split_once("/").unwrap();
-                let prefix: Ipv6Addr = prefix.parse().unwrap();
-                let prefix_length: u8 = prefix_length.parse().unwrap();
+                let (prefix, prefix_length) = prefix_arg(args.get(3));
 
-                conn.send(Request::RemovePrefix(Prefix {
+                Request::RemovePrefix(normalize(Prefix {
                     prefix,
                     prefix_length,
                     preferred_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
                     valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+                    deprecate_before: None,
+                    on_link: true,
+                    label: None,
+                    extra_addrs: Vec::new(),
                 }))
-                .unwrap();
             }
             _ => {
                 eprintln!("Invalid prefix action");
@@ -55,30 +146,557 @@ fn main() {
                 let addr = args.get(3).unwrap();
                 let addr: Ipv6Addr = addr.parse().unwrap();
 
-                conn.send(Request::AddDnsServer(DnsServer {
+                Request::AddDnsServer(DnsServer {
                     addr,
                     lifetime: Lifetime::Duration(Duration::from_secs(3600)),
-                }))
-                .unwrap();
+                    label,
+                })
             }
+            Some("flush") => Request::FlushDnsServers,
             Some("rm") | Some("del") => {
                 let addr = args.get(3).unwrap();
                 let addr: Ipv6Addr = addr.parse().unwrap();
 
-                conn.send(Request::RemoveDnsServer(DnsServer {
+                Request::RemoveDnsServer(DnsServer {
                     addr,
                     lifetime: Lifetime::Duration(Duration::from_secs(3600)),
-                }))
-                .unwrap();
+                    label: None,
+                })
             }
             _ => {
                 eprintln!("invalid dns action");
                 std::process::exit(1);
             }
         },
+        Some("ping") => Request::Ping,
+        Some("status") => Request::GetStatus,
+        Some("reload") => Request::Reload,
+        Some("pause") => Request::Pause,
+        Some("resume") => Request::Resume,
+        Some("log") => {
+            let max = match args.get(2) {
+                Some(max) => match max.parse() {
+                    Ok(max) => max,
+                    Err(err) => {
+                        eprintln!("invalid number of entries {}: {}", max, err);
+                        std::process::exit(1);
+                    }
+                },
+                None => 50,
+            };
+
+            Request::Log { max }
+        }
+        Some("addrs") => Request::ListConfiguredAddresses,
+        Some("export") => Request::Export,
+        Some("import") => {
+            let mut state = Vec::new();
+            if let Err(err) = std::io::stdin().read_to_end(&mut state) {
+                eprintln!("failed to read state from stdin: {}", err);
+                std::process::exit(1);
+            }
+
+            Request::Import(state)
+        }
         Some(_) | None => {
-            eprintln!("No command given; possible are prefix, dns");
+            eprintln!(
+                "No command given; possible are prefix, dns, addrs, ping, status, log, reload, pause, resume, export, import"
+            );
             std::process::exit(1);
         }
+    };
+
+    if dry_run {
+        let mut buf = Vec::new();
+        req.encode(&mut buf);
+        println!("{}", encode_hex(&buf));
+        println!("{:?}", req);
+        return;
+    }
+
+    let mut conn = connect(tcp.as_deref(), wait);
+
+    if let Some(token) = token {
+        match conn.send(Request::Authenticate(token)).unwrap() {
+            Response::Ok { .. } => (),
+            Response::Error { message } => {
+                eprintln!("authentication failed: {}", message);
+                std::process::exit(1);
+            }
+            resp => {
+                eprintln!("authentication failed: unexpected response {:?}", resp);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    log::debug!("sending request: {:?}", req);
+    let resp = conn.send(req.clone()).unwrap();
+    log::debug!("received response: {:?}", resp);
+
+    let affected = match resp {
+        Response::Ok { affected } => affected,
+        Response::Pong { uptime, healthy } => {
+            println!("uptime: {}s, healthy: {}", uptime.as_secs(), healthy);
+            if !healthy {
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Response::Status(status) => {
+            print_status(&status);
+            return;
+        }
+        Response::Prefix(Some(prefix)) => {
+            print_prefix(&prefix);
+            return;
+        }
+        Response::Prefix(None) => {
+            eprintln!("no matching prefix found");
+            std::process::exit(1);
+        }
+        Response::Hello { server_version } => {
+            println!("protocol version {}", server_version);
+            return;
+        }
+        Response::Error { message } => {
+            eprintln!("request failed: {}", message);
+            std::process::exit(1);
+        }
+        Response::Log(entries) => {
+            print_log(&entries);
+            return;
+        }
+        Response::Added => {
+            println!("added prefix");
+            return;
+        }
+        Response::Updated => {
+            // Usually a duplicate add, possibly with changed lifetimes.
+            println!("updated existing prefix");
+            return;
+        }
+        Response::Addresses(addrs) => {
+            for addr in &addrs {
+                if addr.present {
+                    println!("{}/{}", addr.addr, addr.prefix_length);
+                } else {
+                    println!("{}/{} (missing)", addr.addr, addr.prefix_length);
+                }
+            }
+
+            // Missing addresses usually mean DAD failed.
+            if strict && addrs.iter().any(|addr| !addr.present) {
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Response::State(state) => {
+            if let Err(err) = std::io::stdout().write_all(&state) {
+                eprintln!("failed to write state to stdout: {}", err);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+    };
+
+    let (action, kind, kinds) = match req {
+        Request::AddPrefix(_) => ("added", "prefix", "prefixes"),
+        Request::RemovePrefix(_) | Request::FlushPrefixes => ("removed", "prefix", "prefixes"),
+        Request::AddDnsServer(_) => ("added", "dns server", "dns servers"),
+        Request::RemoveDnsServer(_) | Request::FlushDnsServers => {
+            ("removed", "dns server", "dns servers")
+        }
+        Request::Reload => ("loaded", "entry", "entries"),
+        Request::Import(_) => ("imported", "entry", "entries"),
+        Request::Pause | Request::Resume => {
+            let paused = matches!(req, Request::Pause);
+            match (paused, affected) {
+                (true, 0) => println!("advertisements are already paused"),
+                (true, _) => println!("paused advertisements"),
+                (false, 0) => println!("advertisements are not paused"),
+                (false, _) => println!("resumed advertisements"),
+            }
+
+            if affected == 0 && strict {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Request::Authenticate(_) | Request::Hello { .. } => return,
+        Request::Ping
+        | Request::GetStatus
+        | Request::Log { .. }
+        | Request::Export
+        | Request::ListConfiguredAddresses
+        | Request::GetPrefix(_) => return,
+    };
+
+    if affected == 0 {
+        eprintln!("no matching {} found", kind);
+        if strict {
+            std::process::exit(1);
+        }
+    } else {
+        let kind = if affected == 1 { kind } else { kinds };
+        println!("{} {} {}", action, affected, kind);
+    }
+}
+
+fn print_status(status: &Status) {
+    if status.paused {
+        println!("advertisements paused");
+    }
+
+    let ra = &status.advertisements;
+    println!(
+        "last RA: {} bytes (max {}), {} prefix, {} dns, {} other options",
+        ra.size, ra.max_size, ra.prefix_options, ra.dns_options, ra.other_options
+    );
+    println!("dropped solicitations: {}", status.dropped_solicitations);
+    println!("recent solicitations:");
+    for sol in &status.solicitations {
+        let link_layer_addr = match sol.link_layer_addr {
+            Some(addr) => addr
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(":"),
+            None => String::from("-"),
+        };
+
+        println!(
+            "  {} {} {}s ago",
+            sol.addr,
+            link_layer_addr,
+            sol.elapsed.as_secs()
+        );
+    }
+
+    println!("prefixes:");
+    for prefix in &status.prefixes {
+        match &prefix.label {
+            Some(label) => println!("  {}/{} ({})", prefix.prefix, prefix.prefix_length, label),
+            None => println!("  {}/{}", prefix.prefix, prefix.prefix_length),
+        }
+    }
+
+    println!("dns servers:");
+    for server in &status.dns_servers {
+        match &server.label {
+            Some(label) => println!("  {} ({})", server.addr, label),
+            None => println!("  {}", server.addr),
+        }
+    }
+}
+
+fn print_prefix(prefix: &Prefix) {
+    println!("{}/{}", prefix.prefix, prefix.prefix_length);
+    println!(
+        "  preferred: {}",
+        format_lifetime(&prefix.preferred_lifetime)
+    );
+    println!("  valid: {}", format_lifetime(&prefix.valid_lifetime));
+    if let Some(deprecate_before) = prefix.deprecate_before {
+        println!("  deprecated {}s before expiry", deprecate_before.as_secs());
+    }
+    println!("  on-link: {}", prefix.on_link);
+    if let Some(label) = &prefix.label {
+        println!("  label: {}", label);
+    }
+    for addr in &prefix.extra_addrs {
+        println!("  extra address: {}", addr);
+    }
+}
+
+fn format_lifetime(lifetime: &Lifetime) -> String {
+    match lifetime {
+        Lifetime::Duration(dur) => format!("{}s", dur.as_secs()),
+        Lifetime::Until(_) => format!("{}s left", lifetime.duration().as_secs()),
+        Lifetime::Infinite => String::from("infinite"),
+    }
+}
+
+fn decode_request(hex: &str) {
+    let Some(buf) = decode_hex(hex) else {
+        eprintln!("invalid hex string: {}", hex);
+        std::process::exit(1);
+    };
+
+    match Request::decode(&buf[..]) {
+        Ok(req) => println!("{:#?}", req),
+        Err(err) => {
+            eprintln!("failed to decode request: {:?}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn encode_hex(buf: &[u8]) -> String {
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes a hex string, ignoring any whitespace.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+
+    Some(
+        digits
+            .chunks(2)
+            .map(|chunk| (chunk[0] << 4) | chunk[1])
+            .collect(),
+    )
+}
+
+fn print_log(entries: &[LogEntry]) {
+    for entry in entries {
+        let event = match entry.event {
+            Event::PrefixAdded(prefix, len) => format!("added prefix {}/{}", prefix, len),
+            Event::PrefixRemoved(prefix, len) => format!("removed prefix {}/{}", prefix, len),
+            Event::PrefixExpired(prefix, len) => format!("prefix {}/{} expired", prefix, len),
+            Event::DnsServerAdded(addr) => format!("added dns server {}", addr),
+            Event::DnsServerRemoved(addr) => format!("removed dns server {}", addr),
+            Event::Reloaded => String::from("reloaded state"),
+            Event::RouterAdvertisementSent(addr) => format!("sent RA to {}", addr),
+            Event::RouterSolicitationRejected(addr) => format!("rejected RS from {}", addr),
+            Event::Paused => String::from("paused advertisements"),
+            Event::Resumed => String::from("resumed advertisements"),
+        };
+
+        println!("{}s ago: {}", entry.elapsed.as_secs(), event);
+    }
+}
+
+/// Connects to the daemon, retrying with backoff until `wait` has elapsed.
+///
+/// Connects over TCP to `tcp` if given, otherwise to the Unix socket.
+fn connect(tcp: Option<&str>, wait: Option<Duration>) -> Connection {
+    let deadline = wait.map(|wait| Instant::now() + wait);
+    let mut backoff = Duration::from_millis(50);
+
+    loop {
+        let res = match tcp {
+            Some(addr) => Connection::connect_tcp(addr),
+            None => Connection::new(),
+        };
+
+        let err = match res {
+            Ok(conn) => return conn,
+            Err(Error::Io(err)) => err,
+            Err(Error::IncompatibleVersion(version)) => {
+                eprintln!(
+                    "daemon uses protocol version {}, but this client uses version {}; upgrade both",
+                    version, PROTOCOL_VERSION
+                );
+                std::process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("unable to connect to socket: {:?}", err);
+                std::process::exit(1);
+            }
+        };
+
+        // The socket does not exist or no one is listening yet,
+        // both happen while the daemon is starting.
+        let retry = matches!(
+            err.kind(),
+            ErrorKind::NotFound | ErrorKind::ConnectionRefused
+        );
+
+        if let Some(deadline) = deadline {
+            if retry && Instant::now() + backoff < deadline {
+                log::debug!(
+                    "unable to connect to socket: {}; retrying in {:?}",
+                    err,
+                    backoff
+                );
+                std::thread::sleep(backoff);
+                backoff = Duration::min(backoff * 2, Duration::from_secs(1));
+                continue;
+            }
+        }
+
+        match err.kind() {
+            ErrorKind::NotFound => {
+                eprintln!("unable to connect to socket: socket does not exist; is rsadv running?")
+            }
+            ErrorKind::ConnectionRefused => {
+                eprintln!("unable to connect to socket: connection refused; is rsadv running?")
+            }
+            _ => eprintln!("unable to connect to socket: {}", err),
+        }
+
+        std::process::exit(1);
+    }
+}
+
+/// Removes the option `name` and its value from `args`, returning the value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+    if index + 1 >= args.len() {
+        eprintln!("missing value for {}", name);
+        std::process::exit(1);
+    }
+
+    let value = args.remove(index + 1);
+    args.remove(index);
+    Some(value)
+}
+
+/// Parses a prefix like `2001:db8::/64`, ignoring surrounding whitespace.
+///
+/// The prefix length is `None` if `s` is an address without a `/`.
+fn parse_prefix(s: &str) -> Result<(Ipv6Addr, Option<u8>), String> {
+    let s = s.trim();
+    let (addr, prefix_length) = match s.split_once('/') {
+        Some((addr, prefix_length)) => (addr, Some(prefix_length)),
+        None => (s, None),
+    };
+
+    let addr = addr
+        .parse()
+        .map_err(|err| format!("invalid address {:?}: {}", addr, err))?;
+
+    let prefix_length = match prefix_length {
+        None => None,
+        Some("") => return Err(String::from("missing prefix length after /")),
+        // The integer parser would also accept a leading `+`.
+        Some(len) if !len.bytes().all(|b| b.is_ascii_digit()) => {
+            return Err(format!("invalid prefix length {:?}", len));
+        }
+        Some(len) => match len.parse() {
+            Ok(len) if len <= 128 => Some(len),
+            _ => return Err(format!("prefix length {} is longer than 128", len)),
+        },
+    };
+
+    Ok((addr, prefix_length))
+}
+
+/// Returns the prefix given in `arg`, exiting if it is missing, invalid or
+/// has no prefix length.
+fn prefix_arg(arg: Option<&String>) -> (Ipv6Addr, u8) {
+    let Some(arg) = arg else {
+        eprintln!("missing prefix, e.g. 2001:db8::/64");
+        std::process::exit(1);
+    };
+
+    match parse_prefix(arg) {
+        Ok((addr, Some(prefix_length))) => (addr, prefix_length),
+        Ok((addr, None)) => {
+            eprintln!("missing prefix length in {}, e.g. {}/64", arg.trim(), addr);
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("invalid prefix {:?}: {}", arg, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Returns the canonical form of `prefix`, exiting if it is invalid.
+fn normalize(prefix: Prefix) -> Prefix {
+    let input = format!("{}/{}", prefix.prefix, prefix.prefix_length);
+
+    match prefix.normalize() {
+        Ok(prefix) => {
+            if format!("{}/{}", prefix.prefix, prefix.prefix_length) != input {
+                log::warn!(
+                    "prefix {} has host bits set; using {}/{}",
+                    input,
+                    prefix.prefix,
+                    prefix.prefix_length
+                );
+            }
+
+            prefix
+        }
+        Err(err) => {
+            eprintln!("invalid prefix {}: {:?}", input, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parses a lifetime given in seconds or `infinite`.
+fn parse_lifetime(s: &str) -> Lifetime {
+    if s == "infinite" {
+        return Lifetime::Infinite;
+    }
+
+    match s.parse() {
+        Ok(secs) => Lifetime::from_secs(secs),
+        Err(err) => {
+            eprintln!("invalid lifetime {}: {}", s, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn init_logger(verbose: usize) {
+    let mut builder = pretty_env_logger::formatted_builder();
+
+    // Without any `-v` flags we only print errors, unless
+    // `RUST_LOG` is explicitly set.
+    match std::env::var("RUST_LOG") {
+        Ok(filters) if verbose == 0 => {
+            builder.parse_filters(&filters);
+        }
+        _ => {
+            builder.filter_level(match verbose {
+                0 => LevelFilter::Error,
+                1 => LevelFilter::Warn,
+                2 => LevelFilter::Info,
+                3 => LevelFilter::Debug,
+                _ => LevelFilter::Trace,
+            });
+        }
+    }
+
+    builder.init();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::parse_prefix;
+
+    #[test]
+    fn parse_prefix_forms() {
+        let addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0);
+        assert_eq!(parse_prefix("2001:db8::/64"), Ok((addr, Some(64))));
+        assert_eq!(
+            parse_prefix("2001:0db8:0000:0000:0000:0000:0000:0000/64"),
+            Ok((addr, Some(64)))
+        );
+        assert_eq!(parse_prefix(" 2001:db8::/64\n"), Ok((addr, Some(64))));
+        assert_eq!(parse_prefix("::1"), Ok((Ipv6Addr::LOCALHOST, None)));
+        assert_eq!(parse_prefix("::/0"), Ok((Ipv6Addr::UNSPECIFIED, Some(0))));
+
+        for invalid in [
+            "::1/",
+            "2001:db8::/64extra",
+            "2001:db8::/+64",
+            "2001:db8::/64/64",
+            "2001:db8::/129",
+            "2001:db8:: /64",
+            "2001:db8::/ 64",
+            "2001:db8::x/64",
+            "",
+        ] {
+            assert!(parse_prefix(invalid).is_err(), "{:?}", invalid);
+        }
     }
 }
@@ -1,17 +1,407 @@
 use std::fs::File;
 use std::io::{self, Read};
+use std::net::SocketAddr;
 use std::path::Path;
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+/// The current version of the config format.
+///
+/// This must be bumped whenever the meaning of an existing field changes.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// The minimum link MTU of IPv6 (RFC 8200 5).
+const MIN_MTU: u32 = 1280;
+/// The largest MTU possible without jumbograms.
+const MAX_MTU: u32 = 65535;
+
+/// A commented config with the values of [`Config::default`].
+pub const SAMPLE_CONFIG: &str = r#"# The version of the config format.
+version = 1
+
+# The name of the interface, or its index as `if<N>` or `<N>`.
+interface = "eth0"
+# The MTU advertised in RAs, between 1280 and 65535. 0 omits the MTU option.
+mtu = 1500
+# The hop limit hosts should use, advertised in RAs. 0 leaves it to the hosts,
+# "auto" advertises the hop limit the kernel uses on the interface.
+cur_hop_limit = 64
+# The path of the database storing prefixes and DNS servers.
+db = "/var/lib/rsadv/db"
+
+# The bounds of the random interval between unsolicited RAs in seconds.
+# `min_rtr_adv_interval` must be between 3s and .75 * `max_rtr_adv_interval`,
+# `max_rtr_adv_interval` must be between 4s and 1800s.
+min_rtr_adv_interval = 3
+max_rtr_adv_interval = 600
+
+# Whether to configure an address from each announced prefix on the
+# interface. If `false` prefixes are only announced.
+configure_addresses = true
+
+# Whether hosts may use the announced prefixes for SLAAC (the A flag). Set to
+# `false` if addresses are assigned via DHCPv6; this also disables
+# `configure_addresses`.
+autonomous = true
+
+# The router lifetime in seconds for RAs sent in response to a RS. Must be
+# either 0 or between `max_rtr_adv_interval` and 9000s. Defaults to the
+# lifetime of unsolicited RAs.
+# solicited_router_lifetime = 1800
+
+# The lifetime in seconds of the advertised DNS servers. Should be at least
+# `max_rtr_adv_interval`. Defaults to 3 * `max_rtr_adv_interval`.
+# dns_lifetime = 1800
+
+# The size of the buffer for received packets in bytes. Packets larger than
+# the buffer are truncated. Defaults to the MTU of the interface.
+# recv_buffer_size = 9000
+
+# The mode (in octal) and the group (name or ID) of the control socket.
+# Defaults to the mode given by the umask and the group of the daemon.
+# control_socket_mode = "0660"
+# control_socket_group = "rsadv"
+
+# The address of an additional TCP listener for the control protocol.
+# Requires a `control_token`, since every local user can connect to TCP
+# listeners, even on loopback addresses.
+# control_tcp_bind = "[::1]:5400"
+
+# The token clients must authenticate with before changing the state. If
+# unset, every client that can connect may change the state.
+# control_token = "secret"
+
+# Advertise the preferred lifetime of every prefix as this fraction
+# (0.0 to 1.0) of its remaining valid lifetime, instead of the preferred
+# lifetime given when the prefix was added.
+# preferred_ratio = 0.5
+
+# Seeds the random number generators for the RA intervals and random
+# addresses to make them reproducible. Only meant for tests: a known seed
+# makes the generated addresses predictable. Seeded from the OS if unset.
+# rng_seed = 0
+
+# Only advertise this router as a default router while this interface (name
+# or index) has an IPv6 default route. Without one, RAs are sent with a
+# router lifetime of 0 so hosts do not send traffic that would be dropped.
+# upstream_interface = "wan0"
+
+# How often to check `upstream_interface` for a default route in seconds.
+# Must be greater than 0.
+upstream_check_interval = 10
+
+# Whether to install routes from Route Information options in RAs received
+# from other routers.
+accept_route_information = false
+
+# How often a RA in response to a RS is sent.
+solicited_ra_retransmits = 1
+
+# The maximum random delay in milliseconds before a RA in response to a RS.
+# Must be greater than 0.
+max_ra_delay_ms = 500
+
+# How often to retry with a randomized interface identifier if Duplicate
+# Address Detection fails for a configured address.
+dad_retries = 0
+
+# Whether to leave addresses alone that are already configured on the
+# interface by another process (e.g. NetworkManager), instead of overwriting
+# their lifetimes. Addresses from a previous run of rsadv also count as
+# configured by another process.
+respect_foreign_addresses = false
+
+# The maximum number of addresses configured for a single prefix, including
+# the generated one. Prefixes with more extra addresses are rejected.
+# Must be greater than 0.
+max_addresses_per_prefix = 8
+
+# Whether removed prefixes are advertised as withdrawn before they are
+# dropped, and in how many RAs.
+graceful_prefix_withdrawal = false
+withdrawal_advertisements = 3
+
+# Whether to enable IPv6 forwarding on the interface if it is disabled, and
+# whether to exit instead of warning if it stays disabled.
+enable_forwarding = false
+require_forwarding = false
+
+# Whether to exit instead of warning if neither prefixes nor DNS servers are
+# configured at startup, in which case RAs carry no useful information.
+require_prefix = false
+
+# Whether to respond to RSs. If `false` only unsolicited RAs are sent.
+receive_solicitations = true
+
+# How long to wait for further changes in milliseconds before a change is
+# advertised, so a burst of changes results in a single RA.
+config_change_delay = 200
+
+# How often the database is saved at most in milliseconds, so a burst of
+# changes results in a single write. Pending changes are saved on shutdown.
+db_save_interval = 1000
+
+# The number of RAs sent 3s apart after a config change to quickly
+# propagate the change to all hosts.
+fast_readvertise_count = 0
+
+# Whether to set the O flag in RAs, telling hosts to get other configuration
+# (e.g. DNS servers) via stateless DHCPv6.
+other_config = false
+# Whether to answer DHCPv6 Information-Requests with the DNS servers. Only
+# used if `other_config` is set and rsadv was built with the `dhcpv6` feature.
+dhcpv6 = false
+
+# Whether to reject DNS servers that hosts cannot use (unspecified, loopback,
+# multicast or link-local addresses). Otherwise they are added with a warning.
+strict_dns_servers = false
+
+# Whether to include the Source Link-Layer Address option in RAs. Without it
+# hosts must resolve the MAC address of the router with a separate Neighbor
+# Solicitation before sending traffic to it.
+include_source_lladdr = true
+
+# Whether to send a RA with a router lifetime of 0 when advertisements are
+# paused over the control socket, so hosts stop using this router.
+withdraw_on_pause = true
+
+# Announce the RAs as part of this Provisioning Domain (RFC 8801), identified
+# by a FQDN.
+# pvd_id = "pvd.example.com"
+"#;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
+    /// The version of the config format. Defaults to [`CONFIG_VERSION`].
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// The name of the interface, or its index as `if<N>` or `<N>`.
     pub interface: String,
+    /// The MTU advertised in RAs, or 0 to not advertise a MTU.
     pub mtu: u32,
+    /// The Cur Hop Limit advertised in RAs. Defaults to 64.
+    #[serde(default = "default_cur_hop_limit")]
+    pub cur_hop_limit: CurHopLimit,
     pub db: String,
     pub min_rtr_adv_interval: u64,
     pub max_rtr_adv_interval: u64,
+    /// Whether to configure an address from each announced prefix on the
+    /// interface. If `false` prefixes are only announced.
+    #[serde(default = "default_true")]
+    pub configure_addresses: bool,
+    /// Whether to set the A flag of the announced prefixes, allowing hosts
+    /// to autoconfigure addresses from them. If `false` no addresses are
+    /// configured on the interface either.
+    #[serde(default = "default_true")]
+    pub autonomous: bool,
+    /// The router lifetime in seconds for RAs sent in response to a RS.
+    /// Defaults to the lifetime of unsolicited RAs.
+    ///
+    /// Per RFC 4861 this MUST be either 0 or between `max_rtr_adv_interval`
+    /// and 9000s. A shorter lifetime allows hosts to detect a failed router
+    /// faster, but hosts which only received a solicited RA will drop the
+    /// router if it expires before the next unsolicited RA.
+    pub solicited_router_lifetime: Option<u64>,
+    /// The lifetime in seconds of the advertised DNS servers. Defaults to
+    /// 3 * `max_rtr_adv_interval` as recommended by RFC 8106.
+    pub dns_lifetime: Option<u64>,
+    /// The size of the buffer for received packets in bytes. Defaults to the
+    /// MTU of the interface.
+    pub recv_buffer_size: Option<usize>,
+    /// The mode of the control socket in octal, e.g. `0660`.
+    pub control_socket_mode: Option<String>,
+    /// The group of the control socket, as name or ID.
+    pub control_socket_group: Option<String>,
+    /// The address of an additional TCP listener for the control protocol.
+    /// Requires `control_token` to be set.
+    pub control_tcp_bind: Option<SocketAddr>,
+    /// The token clients must authenticate with before changing the state.
+    pub control_token: Option<String>,
+    /// If set, the preferred lifetime of every prefix is this fraction of its
+    /// remaining valid lifetime. Must be between 0.0 and 1.0.
+    pub preferred_ratio: Option<f64>,
+    /// If set, the random number generators are seeded with this value
+    /// instead of from the OS. Only meant for tests, since it makes random
+    /// addresses predictable.
+    pub rng_seed: Option<u64>,
+    /// If set, RAs are sent with a router lifetime of 0 while this interface
+    /// (name or index) has no IPv6 default route.
+    pub upstream_interface: Option<String>,
+    /// How often to check `upstream_interface` for a default route in
+    /// seconds. Defaults to 10s.
+    #[serde(default = "default_upstream_check_interval")]
+    pub upstream_check_interval: u64,
+    /// Whether to install routes from Route Information options (RFC 4191)
+    /// in RAs received from other routers.
+    #[serde(default)]
+    pub accept_route_information: bool,
+    /// How often a RA in response to a RS is sent, to make up for lost
+    /// packets on lossy links. Defaults to 1.
+    #[serde(default = "default_solicited_ra_retransmits")]
+    pub solicited_ra_retransmits: u32,
+    /// The maximum random delay in milliseconds before a RA in response to
+    /// a RS. Defaults to 500ms (`MAX_RA_DELAY_TIME` in RFC 4861).
+    #[serde(default = "default_max_ra_delay_ms")]
+    pub max_ra_delay_ms: u64,
+    /// How often to retry with a randomized interface identifier if
+    /// Duplicate Address Detection fails for a configured address.
+    /// Defaults to 0, i.e. the address is not configured.
+    #[serde(default)]
+    pub dad_retries: u32,
+    /// Whether to skip configuring an address that was already configured on
+    /// the interface by another process, instead of overwriting its lifetimes.
+    #[serde(default)]
+    pub respect_foreign_addresses: bool,
+    /// The maximum number of addresses configured for a single prefix,
+    /// including the generated address. Defaults to 8.
+    #[serde(default = "default_max_addresses_per_prefix")]
+    pub max_addresses_per_prefix: u32,
+    /// Whether removed prefixes are advertised as withdrawn (preferred lifetime
+    /// of 0 and a short valid lifetime) before they are dropped, so hosts stop
+    /// using their addresses from the prefix.
+    #[serde(default)]
+    pub graceful_prefix_withdrawal: bool,
+    /// The number of RAs advertising a withdrawn prefix. Defaults to 3.
+    #[serde(default = "default_withdrawal_advertisements")]
+    pub withdrawal_advertisements: u32,
+    /// Whether to enable IPv6 forwarding on the interface if it is disabled.
+    #[serde(default)]
+    pub enable_forwarding: bool,
+    /// Whether to exit if IPv6 forwarding is disabled on the interface,
+    /// instead of only logging a warning.
+    #[serde(default)]
+    pub require_forwarding: bool,
+    /// Whether to exit if neither prefixes nor DNS servers are configured at
+    /// startup, instead of only logging a warning.
+    #[serde(default)]
+    pub require_prefix: bool,
+    /// Whether to respond to RSs. If `false` only unsolicited RAs are sent.
+    #[serde(default = "default_true")]
+    pub receive_solicitations: bool,
+    /// How long to wait for further changes in milliseconds before a change
+    /// is advertised. Defaults to 200ms.
+    #[serde(default = "default_config_change_delay")]
+    pub config_change_delay: u64,
+    /// How often the database is saved at most in milliseconds. Defaults to
+    /// 1000ms.
+    #[serde(default = "default_db_save_interval")]
+    pub db_save_interval: u64,
+    /// The number of RAs sent `MIN_DELAY_BETWEEN_RAS` apart after a config
+    /// change, before returning to the random interval. Defaults to 0.
+    #[serde(default)]
+    pub fast_readvertise_count: u32,
+    /// Whether to set the O flag in RAs, telling hosts to get other
+    /// configuration via stateless DHCPv6.
+    #[serde(default)]
+    pub other_config: bool,
+    /// Whether to run the built-in stateless DHCPv6 server. Requires
+    /// `other_config` and the `dhcpv6` feature.
+    #[serde(default)]
+    pub dhcpv6: bool,
+    /// Whether to reject DNS servers that hosts cannot use instead of only
+    /// warning about them.
+    #[serde(default)]
+    pub strict_dns_servers: bool,
+    /// Whether to include the Source Link-Layer Address option in RAs.
+    /// Defaults to `true`.
+    #[serde(default = "default_true")]
+    pub include_source_lladdr: bool,
+    /// Whether to send a RA with a router lifetime of 0 when advertisements
+    /// are paused. Defaults to `true`.
+    #[serde(default = "default_true")]
+    pub withdraw_on_pause: bool,
+    /// The FQDN of the Provisioning Domain (RFC 8801) announced in RAs, if
+    /// any.
+    pub pvd_id: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            interface: String::from("eth0"),
+            mtu: 1500,
+            cur_hop_limit: default_cur_hop_limit(),
+            db: String::from("/var/lib/rsadv/db"),
+            min_rtr_adv_interval: 3,
+            max_rtr_adv_interval: 600,
+            configure_addresses: true,
+            autonomous: true,
+            solicited_router_lifetime: None,
+            dns_lifetime: None,
+            recv_buffer_size: None,
+            control_socket_mode: None,
+            control_socket_group: None,
+            control_tcp_bind: None,
+            control_token: None,
+            preferred_ratio: None,
+            rng_seed: None,
+            upstream_interface: None,
+            upstream_check_interval: default_upstream_check_interval(),
+            accept_route_information: false,
+            solicited_ra_retransmits: default_solicited_ra_retransmits(),
+            dad_retries: 0,
+            respect_foreign_addresses: false,
+            max_addresses_per_prefix: default_max_addresses_per_prefix(),
+            graceful_prefix_withdrawal: false,
+            withdrawal_advertisements: default_withdrawal_advertisements(),
+            enable_forwarding: false,
+            require_forwarding: false,
+            require_prefix: false,
+            receive_solicitations: true,
+            config_change_delay: default_config_change_delay(),
+            db_save_interval: default_db_save_interval(),
+            max_ra_delay_ms: default_max_ra_delay_ms(),
+            fast_readvertise_count: 0,
+            other_config: false,
+            dhcpv6: false,
+            strict_dns_servers: false,
+            include_source_lladdr: true,
+            withdraw_on_pause: true,
+            pvd_id: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_solicited_ra_retransmits() -> u32 {
+    1
+}
+
+fn default_withdrawal_advertisements() -> u32 {
+    3
+}
+
+fn default_config_change_delay() -> u64 {
+    200
+}
+
+fn default_db_save_interval() -> u64 {
+    1000
+}
+
+fn default_max_ra_delay_ms() -> u64 {
+    crate::MAX_RA_DELAY_TIME.as_millis() as u64
+}
+
+fn default_upstream_check_interval() -> u64 {
+    10
+}
+
+fn default_max_addresses_per_prefix() -> u32 {
+    8
+}
+
+fn default_cur_hop_limit() -> CurHopLimit {
+    CurHopLimit::Value(64)
+}
+
+fn default_version() -> u32 {
+    CONFIG_VERSION
 }
 
 impl Config {
@@ -25,8 +415,129 @@ impl Config {
         file.read_to_end(&mut buf)?;
 
         let s = std::str::from_utf8(&buf)?;
-        Ok(toml::from_str(s)?)
+        Self::parse(s)
     }
+
+    fn parse(s: &str) -> Result<Self, Error> {
+        let config: Self = toml::from_str(s)?;
+
+        if config.version != CONFIG_VERSION {
+            return Err(Error::UnsupportedVersion {
+                found: config.version,
+                expected: CONFIG_VERSION,
+            });
+        }
+
+        if config.max_ra_delay_ms == 0 {
+            return Err(Error::InvalidRaDelay);
+        }
+
+        if config.upstream_check_interval == 0 {
+            return Err(Error::InvalidUpstreamCheckInterval);
+        }
+
+        if config.max_addresses_per_prefix == 0 {
+            return Err(Error::InvalidMaxAddressesPerPrefix);
+        }
+
+        if config.mtu != 0 && !(MIN_MTU..=MAX_MTU).contains(&config.mtu) {
+            return Err(Error::InvalidMtu(config.mtu));
+        }
+
+        if let Some(mode) = &config.control_socket_mode {
+            if parse_mode(mode).is_none() {
+                return Err(Error::InvalidSocketMode(mode.clone()));
+            }
+        }
+
+        if let Some(ratio) = config.preferred_ratio {
+            if !(0.0..=1.0).contains(&ratio) {
+                return Err(Error::InvalidPreferredRatio(ratio));
+            }
+        }
+
+        if let Some(fqdn) = &config.pvd_id {
+            if !is_valid_fqdn(fqdn) {
+                return Err(Error::InvalidPvdId(fqdn.clone()));
+            }
+        }
+
+        // Without a token anyone who can connect can change the advertised
+        // prefixes. Unlike the control socket, a TCP listener can not be
+        // restricted to some local users, even on a loopback address.
+        if let Some(addr) = config.control_tcp_bind {
+            if config.control_token.is_none() {
+                return Err(Error::InsecureControlBind(addr));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the mode of the control socket, if configured.
+    pub fn control_socket_mode(&self) -> Option<u32> {
+        self.control_socket_mode.as_deref().and_then(parse_mode)
+    }
+}
+
+/// The Cur Hop Limit advertised in RAs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawHopLimit", into = "RawHopLimit")]
+pub enum CurHopLimit {
+    /// The hop limit, 0 if unspecified.
+    Value(u8),
+    /// The `hop_limit` sysctl of the interface.
+    Auto,
+}
+
+/// A [`CurHopLimit`] as written in the config, a number or `"auto"`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawHopLimit {
+    Value(i64),
+    Name(String),
+}
+
+impl TryFrom<RawHopLimit> for CurHopLimit {
+    type Error = String;
+
+    fn try_from(value: RawHopLimit) -> Result<Self, Self::Error> {
+        match value {
+            RawHopLimit::Value(value) => u8::try_from(value).map(Self::Value).map_err(|_| {
+                format!(
+                    "invalid cur_hop_limit {}; expected a value between 0 and 255",
+                    value
+                )
+            }),
+            RawHopLimit::Name(name) if name == "auto" => Ok(Self::Auto),
+            RawHopLimit::Name(name) => Err(format!(
+                "invalid cur_hop_limit {:?}; expected a number or \"auto\"",
+                name
+            )),
+        }
+    }
+}
+
+impl From<CurHopLimit> for RawHopLimit {
+    fn from(value: CurHopLimit) -> Self {
+        match value {
+            CurHopLimit::Value(value) => Self::Value(value.into()),
+            CurHopLimit::Auto => Self::Name(String::from("auto")),
+        }
+    }
+}
+
+/// Returns `true` if `s` is a domain name that fits into DNS labels, with an
+/// optional trailing dot.
+fn is_valid_fqdn(s: &str) -> bool {
+    let s = s.strip_suffix('.').unwrap_or(s);
+    !s.is_empty() && s.len() <= 253 && s.split('.').all(|label| (1..=63).contains(&label.len()))
+}
+
+/// Parses a file mode in octal, e.g. `0660`.
+fn parse_mode(s: &str) -> Option<u32> {
+    let mode = u32::from_str_radix(s.strip_prefix("0o").unwrap_or(s), 8).ok()?;
+    (mode <= 0o7777).then_some(mode)
 }
 
 #[derive(Debug, Error)]
@@ -37,4 +548,222 @@ pub enum Error {
     Str(#[from] std::str::Utf8Error),
     #[error(transparent)]
     Toml(#[from] toml::de::Error),
+    #[error("unsupported config version {found}; expected version {expected}")]
+    UnsupportedVersion { found: u32, expected: u32 },
+    #[error("max_ra_delay_ms must be greater than 0")]
+    InvalidRaDelay,
+    #[error("upstream_check_interval must be greater than 0")]
+    InvalidUpstreamCheckInterval,
+    #[error("max_addresses_per_prefix must be greater than 0")]
+    InvalidMaxAddressesPerPrefix,
+    #[error("invalid mtu {0}; expected 0 or a value between 1280 and 65535")]
+    InvalidMtu(u32),
+    #[error("invalid control socket mode {0:?}; expected an octal mode like 0660")]
+    InvalidSocketMode(String),
+    #[error("control_tcp_bind {0} requires control_token to be set")]
+    InsecureControlBind(SocketAddr),
+    #[error("invalid preferred_ratio {0}; expected a value between 0.0 and 1.0")]
+    InvalidPreferredRatio(f64),
+    #[error("invalid pvd_id {0:?}; expected a FQDN like pvd.example.com")]
+    InvalidPvdId(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_mode, Config, CurHopLimit, Error, CONFIG_VERSION, SAMPLE_CONFIG};
+
+    const CONFIG: &str = r#"
+        interface = "eth0"
+        mtu = 1500
+        db = "db"
+        min_rtr_adv_interval = 3
+        max_rtr_adv_interval = 600
+    "#;
+
+    #[test]
+    fn config_version_default() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+    }
+
+    #[test]
+    fn config_version_unknown() {
+        let config = format!("version = {}\n{}", CONFIG_VERSION + 1, CONFIG);
+        let err = Config::parse(&config).unwrap_err();
+        assert!(
+            matches!(err, Error::UnsupportedVersion { found, .. } if found == CONFIG_VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn mtu_bounds() {
+        for (mtu, valid) in [
+            (0, true),
+            (1279, false),
+            (1280, true),
+            (1500, true),
+            (65535, true),
+            (65536, false),
+            (u32::MAX, false),
+        ] {
+            let config = CONFIG.replace("mtu = 1500", &format!("mtu = {}", mtu));
+            match Config::parse(&config) {
+                Ok(config) => {
+                    assert!(valid, "mtu {} was accepted", mtu);
+                    assert_eq!(config.mtu, mtu);
+                }
+                Err(err) => {
+                    assert!(!valid, "mtu {} was rejected", mtu);
+                    assert!(matches!(err, Error::InvalidMtu(found) if found == mtu));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn max_ra_delay_zero() {
+        let config = format!("max_ra_delay_ms = 0\n{}", CONFIG);
+        let err = Config::parse(&config).unwrap_err();
+        assert!(matches!(err, Error::InvalidRaDelay));
+
+        let config = format!("max_ra_delay_ms = 1\n{}", CONFIG);
+        assert_eq!(Config::parse(&config).unwrap().max_ra_delay_ms, 1);
+    }
+
+    #[test]
+    fn upstream_interface() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.upstream_interface, None);
+        assert_eq!(config.upstream_check_interval, 10);
+
+        let config = format!(
+            "upstream_interface = \"wan0\"\nupstream_check_interval = 5\n{}",
+            CONFIG
+        );
+        let config = Config::parse(&config).unwrap();
+        assert_eq!(config.upstream_interface.as_deref(), Some("wan0"));
+        assert_eq!(config.upstream_check_interval, 5);
+
+        let config = format!("upstream_check_interval = 0\n{}", CONFIG);
+        let err = Config::parse(&config).unwrap_err();
+        assert!(matches!(err, Error::InvalidUpstreamCheckInterval));
+    }
+
+    #[test]
+    fn max_addresses_per_prefix() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.max_addresses_per_prefix, 8);
+
+        let config = format!("max_addresses_per_prefix = 1\n{}", CONFIG);
+        assert_eq!(Config::parse(&config).unwrap().max_addresses_per_prefix, 1);
+
+        let config = format!("max_addresses_per_prefix = 0\n{}", CONFIG);
+        let err = Config::parse(&config).unwrap_err();
+        assert!(matches!(err, Error::InvalidMaxAddressesPerPrefix));
+    }
+
+    #[test]
+    fn control_socket_mode() {
+        assert_eq!(parse_mode("0660"), Some(0o660));
+        assert_eq!(parse_mode("600"), Some(0o600));
+        assert_eq!(parse_mode("0o640"), Some(0o640));
+        assert_eq!(parse_mode("0680"), None);
+        assert_eq!(parse_mode("rw-rw----"), None);
+        assert_eq!(parse_mode("17777"), None);
+
+        let config = format!("control_socket_mode = \"0668\"\n{}", CONFIG);
+        let err = Config::parse(&config).unwrap_err();
+        assert!(matches!(err, Error::InvalidSocketMode(mode) if mode == "0668"));
+    }
+
+    #[test]
+    fn preferred_ratio() {
+        let config = format!("preferred_ratio = 0.5\n{}", CONFIG);
+        assert_eq!(Config::parse(&config).unwrap().preferred_ratio, Some(0.5));
+
+        for ratio in ["1.5", "-0.1", "nan"] {
+            let config = format!("preferred_ratio = {}\n{}", ratio, CONFIG);
+            let err = Config::parse(&config).unwrap_err();
+            assert!(matches!(err, Error::InvalidPreferredRatio(_)));
+        }
+    }
+
+    #[test]
+    fn cur_hop_limit() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.cur_hop_limit, CurHopLimit::Value(64));
+
+        for (value, hop_limit) in [
+            ("0", CurHopLimit::Value(0)),
+            ("255", CurHopLimit::Value(255)),
+            ("\"auto\"", CurHopLimit::Auto),
+        ] {
+            let config = format!("cur_hop_limit = {}\n{}", value, CONFIG);
+            assert_eq!(Config::parse(&config).unwrap().cur_hop_limit, hop_limit);
+        }
+
+        for value in ["256", "-1", "\"system\""] {
+            let config = format!("cur_hop_limit = {}\n{}", value, CONFIG);
+            let err = Config::parse(&config).unwrap_err();
+            assert!(matches!(err, Error::Toml(_)), "{}", value);
+        }
+    }
+
+    #[test]
+    fn pvd_id() {
+        assert_eq!(Config::parse(CONFIG).unwrap().pvd_id, None);
+
+        let config = format!("pvd_id = \"pvd.example.com.\"\n{}", CONFIG);
+        let config = Config::parse(&config).unwrap();
+        assert_eq!(config.pvd_id.as_deref(), Some("pvd.example.com."));
+
+        let long = "a".repeat(64);
+        for fqdn in ["", ".", "pvd..example.com", long.as_str()] {
+            let config = format!("pvd_id = \"{}\"\n{}", fqdn, CONFIG);
+            let err = Config::parse(&config).unwrap_err();
+            assert!(matches!(err, Error::InvalidPvdId(_)));
+        }
+    }
+
+    #[test]
+    fn control_tcp_bind() {
+        let config = format!(
+            "control_tcp_bind = \"[::1]:5400\"\ncontrol_token = \"secret\"\n{}",
+            CONFIG
+        );
+        let config = Config::parse(&config).unwrap();
+        assert_eq!(config.control_tcp_bind, Some("[::1]:5400".parse().unwrap()));
+
+        // A token is required even on loopback addresses.
+        for addr in ["[::1]:5400", "127.0.0.1:5400", "0.0.0.0:5400"] {
+            let config = format!("control_tcp_bind = \"{}\"\n{}", addr, CONFIG);
+            let err = Config::parse(&config).unwrap_err();
+            assert!(matches!(err, Error::InsecureControlBind(_)));
+        }
+
+        let config = format!(
+            "control_tcp_bind = \"0.0.0.0:5400\"\ncontrol_token = \"secret\"\n{}",
+            CONFIG
+        );
+        assert!(Config::parse(&config).is_ok());
+    }
+
+    #[test]
+    fn sample_config_is_default() {
+        let config = Config::parse(SAMPLE_CONFIG).unwrap();
+        assert_eq!(
+            toml::to_string(&config).unwrap(),
+            toml::to_string(&Config::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn sample_config_has_all_fields() {
+        let sample: toml::Table = toml::from_str(SAMPLE_CONFIG).unwrap();
+        let default = toml::Value::try_from(Config::default()).unwrap();
+
+        for key in default.as_table().unwrap().keys() {
+            assert!(sample.contains_key(key), "{} missing in sample config", key);
+        }
+    }
 }
@@ -0,0 +1,379 @@
+//! Consistency checks of RAs sent by other routers on the link.
+//!
+//! Routers SHOULD log inconsistent information advertised by other routers
+//! (RFC 4861 6.2.7): https://www.rfc-editor.org/rfc/rfc4861#section-6.2.7
+
+use std::fmt::{self, Display, Formatter};
+use std::net::Ipv6Addr;
+use std::time::Duration;
+
+use crate::ndp::RouterAdvertisement;
+
+/// A parameter that another router advertises differently than we do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inconsistency {
+    CurHopLimit {
+        theirs: u8,
+        ours: u8,
+    },
+    /// The M (managed) or O (other configuration) flag.
+    Flag {
+        name: &'static str,
+        theirs: bool,
+        ours: bool,
+    },
+    ReachableTime {
+        theirs: Duration,
+        ours: Duration,
+    },
+    RetransTimer {
+        theirs: Duration,
+        ours: Duration,
+    },
+    Mtu {
+        theirs: u32,
+        ours: u32,
+    },
+    PreferredLifetime {
+        prefix: Ipv6Addr,
+        prefix_length: u8,
+        theirs: Duration,
+        ours: Duration,
+    },
+    ValidLifetime {
+        prefix: Ipv6Addr,
+        prefix_length: u8,
+        theirs: Duration,
+        ours: Duration,
+    },
+}
+
+impl Display for Inconsistency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CurHopLimit { theirs, ours } => write!(
+                f,
+                "advertises cur hop limit {} but we advertise {}",
+                theirs, ours
+            ),
+            Self::Flag { name, theirs, ours } => write!(
+                f,
+                "advertises {} flag {} but we advertise {}",
+                name, theirs, ours
+            ),
+            Self::ReachableTime { theirs, ours } => write!(
+                f,
+                "advertises reachable time {:?} but we advertise {:?}",
+                theirs, ours
+            ),
+            Self::RetransTimer { theirs, ours } => write!(
+                f,
+                "advertises retrans timer {:?} but we advertise {:?}",
+                theirs, ours
+            ),
+            Self::Mtu { theirs, ours } => {
+                write!(f, "advertises MTU {} but we advertise {}", theirs, ours)
+            }
+            Self::PreferredLifetime {
+                prefix,
+                prefix_length,
+                theirs,
+                ours,
+            } => write!(
+                f,
+                "advertises preferred lifetime {:?} for {}/{} but we advertise {:?}",
+                theirs, prefix, prefix_length, ours
+            ),
+            Self::ValidLifetime {
+                prefix,
+                prefix_length,
+                theirs,
+                ours,
+            } => write!(
+                f,
+                "advertises valid lifetime {:?} for {}/{} but we advertise {:?}",
+                theirs, prefix, prefix_length, ours
+            ),
+        }
+    }
+}
+
+/// Compares a RA received from another router with `ours`, the last RA we
+/// sent `since` ago.
+///
+/// Unspecified values (0) are not compared. Lifetimes may count down in real
+/// time, so prefix lifetimes only differ if they are more than `since` apart.
+pub fn inconsistencies(
+    theirs: &RouterAdvertisement,
+    ours: &RouterAdvertisement,
+    since: Duration,
+) -> Vec<Inconsistency> {
+    let mut found = Vec::new();
+
+    if theirs.cur_hop_limit != 0
+        && ours.cur_hop_limit != 0
+        && theirs.cur_hop_limit != ours.cur_hop_limit
+    {
+        found.push(Inconsistency::CurHopLimit {
+            theirs: theirs.cur_hop_limit,
+            ours: ours.cur_hop_limit,
+        });
+    }
+
+    for (name, theirs, ours) in [
+        ("M", theirs.managed, ours.managed),
+        ("O", theirs.other, ours.other),
+    ] {
+        if theirs != ours {
+            found.push(Inconsistency::Flag { name, theirs, ours });
+        }
+    }
+
+    if let (Some(theirs), Some(ours)) = (theirs.reachable_timer, ours.reachable_timer) {
+        if theirs != ours {
+            found.push(Inconsistency::ReachableTime { theirs, ours });
+        }
+    }
+
+    if let (Some(theirs), Some(ours)) = (theirs.retrans_timer, ours.retrans_timer) {
+        if theirs != ours {
+            found.push(Inconsistency::RetransTimer { theirs, ours });
+        }
+    }
+
+    if let (Some(theirs), Some(ours)) = (theirs.mtu(), ours.mtu()) {
+        if theirs != ours {
+            found.push(Inconsistency::Mtu { theirs, ours });
+        }
+    }
+
+    for info in theirs.prefixes() {
+        let Some(our_info) = ours.prefixes().find(|our_info| {
+            our_info.prefix == info.prefix && our_info.prefix_length == info.prefix_length
+        }) else {
+            continue;
+        };
+
+        if info
+            .preferred_lifetime
+            .abs_diff(our_info.preferred_lifetime)
+            > since
+        {
+            found.push(Inconsistency::PreferredLifetime {
+                prefix: info.prefix,
+                prefix_length: info.prefix_length,
+                theirs: info.preferred_lifetime,
+                ours: our_info.preferred_lifetime,
+            });
+        }
+
+        if info.valid_lifetime.abs_diff(our_info.valid_lifetime) > since {
+            found.push(Inconsistency::ValidLifetime {
+                prefix: info.prefix,
+                prefix_length: info.prefix_length,
+                theirs: info.valid_lifetime,
+                ours: our_info.valid_lifetime,
+            });
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+    use std::time::Duration;
+
+    use super::{inconsistencies, Inconsistency};
+    use crate::ndp::{IcmpOption, PrefixInformation, RouterAdvertisement};
+
+    const PREFIX: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0);
+
+    fn advertisement() -> RouterAdvertisement {
+        RouterAdvertisement {
+            cur_hop_limit: 64,
+            managed: false,
+            other: false,
+            router_lifetime: Duration::from_secs(1800),
+            reachable_timer: None,
+            retrans_timer: None,
+            options: vec![
+                IcmpOption::Mtu(9000),
+                IcmpOption::PrefixInformation(PrefixInformation {
+                    prefix_length: 64,
+                    on_link: true,
+                    autonomous: true,
+                    valid_lifetime: Duration::from_secs(3600),
+                    preferred_lifetime: Duration::from_secs(1800),
+                    prefix: PREFIX,
+                }),
+            ],
+        }
+    }
+
+    fn set_prefix(adv: &mut RouterAdvertisement, preferred: u64, valid: u64) {
+        for opt in &mut adv.options {
+            if let IcmpOption::PrefixInformation(info) = opt {
+                info.preferred_lifetime = Duration::from_secs(preferred);
+                info.valid_lifetime = Duration::from_secs(valid);
+            }
+        }
+    }
+
+    #[test]
+    fn consistent() {
+        let ours = advertisement();
+        let mut theirs = advertisement();
+        // The router lifetime may differ between routers.
+        theirs.router_lifetime = Duration::ZERO;
+
+        assert_eq!(inconsistencies(&theirs, &ours, Duration::ZERO), []);
+    }
+
+    #[test]
+    fn cur_hop_limit() {
+        let ours = advertisement();
+        let mut theirs = advertisement();
+        theirs.cur_hop_limit = 255;
+
+        assert_eq!(
+            inconsistencies(&theirs, &ours, Duration::ZERO),
+            [Inconsistency::CurHopLimit {
+                theirs: 255,
+                ours: 64
+            }]
+        );
+
+        // Unspecified.
+        theirs.cur_hop_limit = 0;
+        assert_eq!(inconsistencies(&theirs, &ours, Duration::ZERO), []);
+    }
+
+    #[test]
+    fn flags() {
+        let ours = advertisement();
+        let mut theirs = advertisement();
+        theirs.managed = true;
+        theirs.other = true;
+
+        assert_eq!(
+            inconsistencies(&theirs, &ours, Duration::ZERO),
+            [
+                Inconsistency::Flag {
+                    name: "M",
+                    theirs: true,
+                    ours: false
+                },
+                Inconsistency::Flag {
+                    name: "O",
+                    theirs: true,
+                    ours: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn timers() {
+        let mut ours = advertisement();
+        ours.reachable_timer = Some(Duration::from_secs(30));
+        ours.retrans_timer = Some(Duration::from_secs(1));
+
+        let mut theirs = advertisement();
+        assert_eq!(inconsistencies(&theirs, &ours, Duration::ZERO), []);
+
+        theirs.reachable_timer = Some(Duration::from_secs(20));
+        theirs.retrans_timer = Some(Duration::from_secs(2));
+        assert_eq!(
+            inconsistencies(&theirs, &ours, Duration::ZERO),
+            [
+                Inconsistency::ReachableTime {
+                    theirs: Duration::from_secs(20),
+                    ours: Duration::from_secs(30),
+                },
+                Inconsistency::RetransTimer {
+                    theirs: Duration::from_secs(2),
+                    ours: Duration::from_secs(1),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn mtu() {
+        let ours = advertisement();
+        let mut theirs = advertisement();
+        theirs.options[0] = IcmpOption::Mtu(1500);
+
+        let found = inconsistencies(&theirs, &ours, Duration::ZERO);
+        assert_eq!(
+            found,
+            [Inconsistency::Mtu {
+                theirs: 1500,
+                ours: 9000
+            }]
+        );
+        assert_eq!(
+            found[0].to_string(),
+            "advertises MTU 1500 but we advertise 9000"
+        );
+
+        // No MTU option.
+        theirs.options.remove(0);
+        assert_eq!(inconsistencies(&theirs, &ours, Duration::ZERO), []);
+    }
+
+    #[test]
+    fn prefix_lifetimes() {
+        let ours = advertisement();
+        let mut theirs = advertisement();
+        set_prefix(&mut theirs, 600, 7200);
+
+        assert_eq!(
+            inconsistencies(&theirs, &ours, Duration::ZERO),
+            [
+                Inconsistency::PreferredLifetime {
+                    prefix: PREFIX,
+                    prefix_length: 64,
+                    theirs: Duration::from_secs(600),
+                    ours: Duration::from_secs(1800),
+                },
+                Inconsistency::ValidLifetime {
+                    prefix: PREFIX,
+                    prefix_length: 64,
+                    theirs: Duration::from_secs(7200),
+                    ours: Duration::from_secs(3600),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_lifetimes_counting_down() {
+        let ours = advertisement();
+        let mut theirs = advertisement();
+        set_prefix(&mut theirs, 1790, 3590);
+
+        // Our lifetimes may have counted down since our last RA.
+        assert_eq!(inconsistencies(&theirs, &ours, Duration::from_secs(10)), []);
+        assert_eq!(
+            inconsistencies(&theirs, &ours, Duration::from_secs(5)).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn other_prefix() {
+        let ours = advertisement();
+        let mut theirs = advertisement();
+        set_prefix(&mut theirs, 0, 0);
+        for opt in &mut theirs.options {
+            if let IcmpOption::PrefixInformation(info) = opt {
+                info.prefix_length = 48;
+            }
+        }
+
+        assert_eq!(inconsistencies(&theirs, &ours, Duration::ZERO), []);
+    }
+}
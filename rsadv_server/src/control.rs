@@ -1,12 +1,26 @@
+use std::fs::Permissions;
+use std::future::Future;
 use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
-use rsadv_control::{Request, Response};
+use futures::{pin_mut, FutureExt};
+use ragequit::SHUTDOWN;
+use rsadv_control::{
+    ConfiguredAddress, ConfiguredDnsServer, Event, FrameWriter, LogEntry, Prefix, Request,
+    Response, Solicitation, Status, FRAME_HEADER_LEN, MAX_FRAME_LEN, PROTOCOL_VERSION,
+};
 use thiserror::Error;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{UnixListener, UnixStream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tracing::Instrument;
 
-use crate::State;
+use crate::database::Database;
+use crate::linux::Interface;
+use crate::{unusable_dns_server, State};
 
 const CONTROL_SOCKET_ADDR: &str = "/run/rsadv.sock";
 
@@ -16,14 +30,83 @@ pub enum ControlSocketError {
     Io(#[from] io::Error),
     #[error("socket is already in use")]
     SocketInUse,
+    #[error("failed to set mode of control socket: {0}")]
+    Chmod(io::Error),
+    #[error("failed to set group of control socket: {0}")]
+    Chown(io::Error),
+    #[error("unknown group {0}")]
+    UnknownGroup(String),
 }
 
-pub async fn control_loop(state: Arc<State>) -> Result<(), ControlSocketError> {
-    if tokio::fs::try_exists(CONTROL_SOCKET_ADDR).await? {
+/// The permissions of the control socket. `None` keeps the default.
+#[derive(Clone, Debug, Default)]
+pub struct SocketPermissions {
+    pub mode: Option<u32>,
+    /// The name or ID of the group.
+    pub group: Option<String>,
+}
+
+pub async fn control_loop(
+    state: Arc<State>,
+    interface: Interface,
+    permissions: SocketPermissions,
+) -> Result<(), ControlSocketError> {
+    let socket = bind_control_socket(CONTROL_SOCKET_ADDR).await?;
+    set_permissions(CONTROL_SOCKET_ADDR, &permissions)?;
+
+    accept_loop(socket, CONTROL_SOCKET_ADDR, SHUTDOWN.listen(), |stream| {
+        let cred = stream.peer_cred().ok();
+        let span = tracing::info_span!(
+            "control",
+            uid = cred.map(|cred| cred.uid()),
+            pid = cred.and_then(|cred| cred.pid()),
+        );
+
+        tokio::task::spawn(
+            handle_conn(stream, state.clone(), Some(interface.clone())).instrument(span),
+        );
+    })
+    .await
+}
+
+/// Serves the control protocol on a TCP listener bound to `addr` until
+/// shutdown.
+pub async fn tcp_control_loop(
+    state: Arc<State>,
+    interface: Interface,
+    addr: SocketAddr,
+) -> Result<(), ControlSocketError> {
+    let socket = TcpListener::bind(addr).await?;
+
+    let shutdown = SHUTDOWN.listen();
+    pin_mut!(shutdown);
+    loop {
+        futures::select_biased! {
+            _ = shutdown.as_mut().fuse() => return Ok(()),
+            res = socket.accept().fuse() => {
+                let (stream, peer) = res?;
+                let span = tracing::info_span!("control", %peer);
+                tokio::task::spawn(
+                    handle_conn(stream, state.clone(), Some(interface.clone())).instrument(span),
+                );
+            }
+        }
+    }
+}
+
+/// Binds the control socket at `path`, taking over the socket file if it was
+/// left behind by a previous process.
+async fn bind_control_socket<P>(path: P) -> Result<UnixListener, ControlSocketError>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+
+    if tokio::fs::try_exists(path).await? {
         // connect will return ECONNREFUSED if the socket file exists but
         // no one is listening. In that case we take over the socket
         // (e.g. becuase the previous process crashed without removing the socket).
-        match UnixStream::connect(CONTROL_SOCKET_ADDR).await {
+        match UnixStream::connect(path).await {
             Ok(_) => return Err(ControlSocketError::SocketInUse),
             Err(err) if err.kind() != ErrorKind::ConnectionRefused => {
                 return Err(err.into());
@@ -31,21 +114,77 @@ pub async fn control_loop(state: Arc<State>) -> Result<(), ControlSocketError> {
             _ => (),
         }
 
-        tokio::fs::remove_file(CONTROL_SOCKET_ADDR).await?;
+        tokio::fs::remove_file(path).await?;
     }
 
-    let socket = UnixListener::bind(CONTROL_SOCKET_ADDR)?;
+    Ok(UnixListener::bind(path)?)
+}
+
+fn set_permissions<P>(path: P, permissions: &SocketPermissions) -> Result<(), ControlSocketError>
+where
+    P: AsRef<Path>,
+{
+    if let Some(group) = &permissions.group {
+        let gid = match group.parse() {
+            Ok(gid) => gid,
+            Err(_) => crate::linux::group_id(group)
+                .map_err(ControlSocketError::Chown)?
+                .ok_or_else(|| ControlSocketError::UnknownGroup(group.clone()))?,
+        };
 
-    loop {
-        let (stream, _) = socket.accept().await?;
+        std::os::unix::fs::chown(&path, None, Some(gid)).map_err(ControlSocketError::Chown)?;
+    }
+
+    if let Some(mode) = permissions.mode {
+        std::fs::set_permissions(&path, Permissions::from_mode(mode))
+            .map_err(ControlSocketError::Chmod)?;
+    }
+
+    Ok(())
+}
 
-        tokio::task::spawn(handle_conn(stream, state.clone()));
+/// Passes all connections accepted on `socket` to `handle` until `shutdown`
+/// completes. The socket file at `path` is removed on shutdown, so the next
+/// daemon does not need to take over a stale socket.
+async fn accept_loop<P, F, H>(
+    socket: UnixListener,
+    path: P,
+    shutdown: F,
+    mut handle: H,
+) -> Result<(), ControlSocketError>
+where
+    P: AsRef<Path>,
+    F: Future,
+    H: FnMut(UnixStream),
+{
+    pin_mut!(shutdown);
+    loop {
+        futures::select_biased! {
+            _ = shutdown.as_mut().fuse() => break,
+            res = socket.accept().fuse() => {
+                let (stream, _) = res?;
+                handle(stream);
+            }
+        }
     }
+
+    drop(socket);
+    tokio::fs::remove_file(path).await?;
+    Ok(())
 }
 
-async fn handle_conn(mut conn: UnixStream, state: Arc<State>) {
+/// Serves the control protocol on `conn`.
+///
+/// Requests that query the interface fail without an `interface`.
+async fn handle_conn<S>(mut conn: S, state: Arc<State>, interface: Option<Interface>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut authenticated = state.control_token.is_none();
+    let mut first = true;
+
     loop {
-        let mut buf = [0; 4];
+        let mut buf = [0; FRAME_HEADER_LEN];
 
         if let Err(err) = conn.read_exact(&mut buf).await {
             tracing::error!("error serving conn: {:?}", err);
@@ -54,7 +193,22 @@ async fn handle_conn(mut conn: UnixStream, state: Arc<State>) {
 
         let len = u32::from_le_bytes(buf);
 
-        let mut buf = vec![0; std::cmp::min(len as usize, u16::MAX as usize)];
+        // The rest of the frame cannot be skipped reliably, so the connection
+        // is closed after the error.
+        if len > MAX_FRAME_LEN {
+            tracing::error!("control request of {} bytes is too large", len);
+
+            let mut frame = FrameWriter::new(Vec::new());
+            frame
+                .write_response(&Response::Error {
+                    message: format!("request exceeds {} bytes", MAX_FRAME_LEN),
+                })
+                .unwrap();
+            let _ = conn.write_all(frame.get_ref()).await;
+            return;
+        }
+
+        let mut buf = vec![0; len as usize];
         if let Err(err) = conn.read_exact(&mut buf).await {
             tracing::error!("error serving conn: {:?}", err);
             return;
@@ -68,46 +222,763 @@ async fn handle_conn(mut conn: UnixStream, state: Arc<State>) {
             }
         };
 
-        match req {
+        // The handshake does not count as the first request.
+        let is_first = first;
+        if !matches!(req, Request::Hello { .. }) {
+            first = false;
+        }
+
+        let resp = match req {
+            req if !authenticated && is_mutating(&req) => Response::Error {
+                message: String::from("not authenticated"),
+            },
+            Request::Authenticate(token) => match &state.control_token {
+                _ if !is_first => Response::Error {
+                    message: String::from("authentication must be the first request"),
+                },
+                Some(expected) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+                    authenticated = true;
+                    Response::Ok { affected: 1 }
+                }
+                Some(_) => {
+                    tracing::warn!("rejected control connection with invalid token");
+                    Response::Error {
+                        message: String::from("invalid token"),
+                    }
+                }
+                None => Response::Ok { affected: 0 },
+            },
+            Request::Hello { client_version } => {
+                if client_version != PROTOCOL_VERSION {
+                    tracing::warn!(
+                        "control client uses protocol version {}, we use {}",
+                        client_version,
+                        PROTOCOL_VERSION
+                    );
+                }
+
+                Response::Hello {
+                    server_version: PROTOCOL_VERSION,
+                }
+            }
+            Request::AddPrefix(prefix)
+                if prefix.extra_addrs.len() >= state.max_addresses_per_prefix as usize =>
+            {
+                Response::Error {
+                    message: format!(
+                        "prefix {}/{} has {} addresses; at most {} are allowed",
+                        prefix.prefix,
+                        prefix.prefix_length,
+                        prefix.extra_addrs.len() + 1,
+                        state.max_addresses_per_prefix,
+                    ),
+                }
+            }
             Request::AddPrefix(prefix) => {
-                state.prefixes.write().insert(
+                let previous = state.prefixes.write().insert(
                     prefix.prefix,
                     crate::Prefix {
                         prefix: prefix.prefix,
                         prefix_length: prefix.prefix_length,
                         preferred_lifetime: prefix.preferred_lifetime,
                         valid_lifetime: prefix.valid_lifetime,
+                        deprecate_before: prefix.deprecate_before,
+                        on_link: prefix.on_link,
+                        label: prefix.label,
+                        extra_addrs: prefix.extra_addrs,
+                        withdrawal: None,
+                        added: crate::ClockAnchor::now(),
                     },
                 );
 
+                state.record(Event::PrefixAdded(prefix.prefix, prefix.prefix_length));
                 state.config_changed.notify_one();
+
+                // Re-adding a withdrawn prefix announces it again like a new one.
+                match previous {
+                    Some(previous) if previous.withdrawal.is_none() => Response::Updated,
+                    _ => Response::Added,
+                }
             }
             Request::RemovePrefix(prefix) => {
-                state.prefixes.write().remove(&prefix.prefix);
-                state.config_changed.notify_one();
-            }
-            Request::AddDnsServer(server) => {
-                state.dns_servers.write().insert(server.addr);
-                state.config_changed.notify_one();
+                let removed = state.withdraw_prefix(&mut state.prefixes.write(), prefix.prefix);
+                if let Some(prefix) = &removed {
+                    state.record(Event::PrefixRemoved(prefix.prefix, prefix.prefix_length));
+                    state.config_changed.notify_one();
+                }
+
+                Response::Ok {
+                    affected: removed.is_some() as u32,
+                }
             }
+            Request::AddDnsServer(server) => match unusable_dns_server(server.addr) {
+                Some(reason) if state.strict_dns_servers => Response::Error {
+                    message: format!(
+                        "invalid DNS server {}: {} addresses cannot be used by hosts",
+                        server.addr, reason
+                    ),
+                },
+                reason => {
+                    if let Some(reason) = reason {
+                        tracing::warn!(
+                            "adding DNS server {} which is {} and cannot be used by hosts",
+                            server.addr,
+                            reason
+                        );
+                    }
+
+                    let previous = state
+                        .dns_servers
+                        .write()
+                        .insert(server.addr, server.label.clone());
+                    let inserted = previous.is_none();
+                    if inserted {
+                        state.record(Event::DnsServerAdded(server.addr));
+                    }
+
+                    // A changed label is not advertised, but must still be saved.
+                    if inserted {
+                        state.config_changed.notify_one();
+                    } else if previous != Some(server.label) {
+                        state.db_changed.notify_one();
+                    }
+
+                    Response::Ok {
+                        affected: inserted as u32,
+                    }
+                }
+            },
             Request::RemoveDnsServer(server) => {
-                state.dns_servers.write().remove(&server.addr);
-                state.config_changed.notify_one();
+                let removed = state.dns_servers.write().remove(&server.addr).is_some();
+                if removed {
+                    state.record(Event::DnsServerRemoved(server.addr));
+                    state.config_changed.notify_one();
+                }
+
+                Response::Ok {
+                    affected: removed as u32,
+                }
             }
-        }
+            Request::FlushPrefixes => {
+                let removed: Vec<_> = {
+                    let mut prefixes = state.prefixes.write();
+                    let addrs: Vec<_> = prefixes.keys().copied().collect();
+
+                    addrs
+                        .into_iter()
+                        .filter_map(|addr| state.withdraw_prefix(&mut prefixes, addr))
+                        .collect()
+                };
+                for prefix in &removed {
+                    state.record(Event::PrefixRemoved(prefix.prefix, prefix.prefix_length));
+                }
 
-        let resp = Response::Ok;
+                if !removed.is_empty() {
+                    state.config_changed.notify_one();
+                }
 
-        let mut buf = Vec::new();
-        resp.encode(&mut buf);
+                Response::Ok {
+                    affected: removed.len() as u32,
+                }
+            }
+            Request::FlushDnsServers => {
+                let removed = std::mem::take(&mut *state.dns_servers.write());
+                for addr in removed.keys() {
+                    state.record(Event::DnsServerRemoved(*addr));
+                }
 
-        let mut buf_with_len = Vec::new();
-        buf_with_len.extend((buf.len() as u32).to_le_bytes());
-        buf_with_len.extend(&buf);
+                if !removed.is_empty() {
+                    state.config_changed.notify_one();
+                }
+
+                Response::Ok {
+                    affected: removed.len() as u32,
+                }
+            }
+            Request::Ping => Response::Pong {
+                uptime: state.started.elapsed(),
+                healthy: state.health.healthy(),
+            },
+            Request::GetStatus => {
+                let solicitations = state
+                    .solicitations
+                    .lock()
+                    .iter()
+                    .map(|sol| Solicitation {
+                        addr: sol.addr,
+                        link_layer_addr: sol.link_layer_addr,
+                        elapsed: sol.received.elapsed(),
+                    })
+                    .collect();
 
-        if let Err(err) = conn.write_all(&buf_with_len).await {
+                let mut prefixes: Vec<_> = state
+                    .prefixes
+                    .read()
+                    .values()
+                    .filter(|prefix| prefix.withdrawal.is_none())
+                    .map(control_prefix)
+                    .collect();
+                prefixes.sort_by_key(|prefix| prefix.prefix);
+
+                let mut dns_servers: Vec<_> = state
+                    .dns_servers
+                    .read()
+                    .iter()
+                    .map(|(addr, label)| ConfiguredDnsServer {
+                        addr: *addr,
+                        label: label.clone(),
+                    })
+                    .collect();
+                dns_servers.sort_by_key(|server| server.addr);
+
+                Response::Status(Status {
+                    solicitations,
+                    dropped_solicitations: state.solicitation_queue.dropped(),
+                    advertisements: *state.advertisement_stats.lock(),
+                    paused: state.paused.load(Ordering::Relaxed),
+                    prefixes,
+                    dns_servers,
+                })
+            }
+            Request::Log { max } => {
+                let events = state.events.lock();
+                let skip = events.len().saturating_sub(max as usize);
+
+                Response::Log(
+                    events
+                        .iter()
+                        .skip(skip)
+                        .map(|(time, event)| LogEntry {
+                            elapsed: time.elapsed(),
+                            event: *event,
+                        })
+                        .collect(),
+                )
+            }
+            Request::GetPrefix(addr) => {
+                // Withdrawn prefixes are already removed from the point of
+                // view of clients.
+                let prefix = state
+                    .prefixes
+                    .read()
+                    .get(&addr)
+                    .filter(|prefix| prefix.withdrawal.is_none())
+                    .map(control_prefix);
+
+                Response::Prefix(prefix)
+            }
+            Request::Pause => Response::Ok {
+                affected: state.set_paused(true) as u32,
+            },
+            Request::Resume => Response::Ok {
+                affected: state.set_paused(false) as u32,
+            },
+            Request::ListConfiguredAddresses => {
+                let configured = state.configured_addrs.read().clone();
+
+                // Cross-check with the interface to find addresses that
+                // disappeared, e.g. because DAD failed.
+                let addrs = match &interface {
+                    Some(interface) => interface.addrs().await,
+                    None => Err(crate::linux::Error::NoInterface),
+                };
+
+                match addrs {
+                    Ok(addrs) => Response::Addresses(
+                        configured
+                            .into_iter()
+                            .map(|(addr, prefix_length)| ConfiguredAddress {
+                                addr,
+                                prefix_length,
+                                present: addrs.contains(&addr),
+                            })
+                            .collect(),
+                    ),
+                    Err(err) => Response::Error {
+                        message: format!("failed to get interface addresses: {:?}", err),
+                    },
+                }
+            }
+            Request::Export => Response::State(state.to_database().export()),
+            Request::Import(buf) => match Database::import(&buf) {
+                Ok(db) => match state.check_database(&db) {
+                    Ok(()) => {
+                        let loaded = state.load_database(&db);
+                        state.record(Event::Reloaded);
+                        state.config_changed.notify_one();
+
+                        Response::Ok {
+                            affected: loaded as u32,
+                        }
+                    }
+                    Err(message) => Response::Error { message },
+                },
+                Err(err) => Response::Error {
+                    message: format!("invalid state: {:?}", err),
+                },
+            },
+            Request::Reload => match state.db_path.as_deref().map(Database::load) {
+                None => Response::Error {
+                    message: String::from("persistence is disabled"),
+                },
+                Some(Ok(db)) => {
+                    let loaded = state.load_database(&db);
+                    state.record(Event::Reloaded);
+                    state.config_changed.notify_one();
+
+                    Response::Ok {
+                        affected: loaded as u32,
+                    }
+                }
+                Some(Err(err)) => {
+                    tracing::error!("failed to reload database: {:?}", err);
+                    Response::Error {
+                        message: format!("failed to load database: {:?}", err),
+                    }
+                }
+            },
+        };
+
+        let mut frame = FrameWriter::new(Vec::new());
+        // Writing into a `Vec` never fails.
+        frame.write_response(&resp).unwrap();
+
+        if let Err(err) = conn.write_all(frame.get_ref()).await {
             tracing::error!("error serving conn: {:?}", err);
             return;
         }
     }
 }
+
+/// Returns the prefix as reported to clients.
+fn control_prefix(prefix: &crate::Prefix) -> Prefix {
+    Prefix {
+        prefix: prefix.prefix,
+        prefix_length: prefix.prefix_length,
+        preferred_lifetime: prefix.preferred_lifetime,
+        valid_lifetime: prefix.valid_lifetime,
+        deprecate_before: prefix.deprecate_before,
+        on_link: prefix.on_link,
+        label: prefix.label.clone(),
+        extra_addrs: prefix.extra_addrs.clone(),
+    }
+}
+
+/// Returns `true` if `req` changes the state of the daemon.
+fn is_mutating(req: &Request) -> bool {
+    match req {
+        Request::AddPrefix(_)
+        | Request::RemovePrefix(_)
+        | Request::AddDnsServer(_)
+        | Request::RemoveDnsServer(_)
+        | Request::FlushPrefixes
+        | Request::FlushDnsServers
+        | Request::Reload
+        | Request::Import(_)
+        | Request::Pause
+        | Request::Resume => true,
+        Request::Ping
+        | Request::GetStatus
+        | Request::Export
+        | Request::Log { .. }
+        | Request::ListConfiguredAddresses
+        | Request::GetPrefix(_)
+        | Request::Authenticate(_)
+        | Request::Hello { .. } => false,
+    }
+}
+
+/// Compares `a` and `b` in constant time, so the time taken does not reveal
+/// how much of a guessed token was correct. Only the length may leak.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use rsadv_control::{
+        ConfiguredDnsServer, DnsServer, FrameWriter, Lifetime, Prefix, Request, Response,
+        FRAME_HEADER_LEN, MAX_FRAME_LEN, PROTOCOL_VERSION,
+    };
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+    use tokio::net::{UnixListener, UnixStream};
+    use tokio::sync::{mpsc, oneshot};
+    use tokio::task::JoinHandle;
+
+    use super::{
+        accept_loop, bind_control_socket, constant_time_eq, handle_conn, ControlSocketError,
+    };
+    use crate::config::Config;
+    use crate::database::Database;
+    use crate::{Command, State};
+
+    /// Returns the state of a daemon with `config` and the receiver of its
+    /// commands, which must be kept alive while the state is used.
+    fn new_state(config: &Config) -> (Arc<State>, mpsc::Receiver<Command>) {
+        let (tx, rx) = mpsc::channel(1);
+        (Arc::new(State::new(config, tx)), rx)
+    }
+
+    /// Serves a connection to `state` over an in-memory transport.
+    fn connect(state: &Arc<State>) -> (DuplexStream, JoinHandle<()>) {
+        let (conn, server) = tokio::io::duplex(1024);
+        let handler = tokio::task::spawn(handle_conn(server, state.clone(), None));
+        (conn, handler)
+    }
+
+    async fn request<S>(conn: &mut S, req: Request) -> Response
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut frame = FrameWriter::new(Vec::new());
+        frame.write_request(&req).unwrap();
+        conn.write_all(frame.get_ref()).await.unwrap();
+
+        let mut len = [0; FRAME_HEADER_LEN];
+        conn.read_exact(&mut len).await.unwrap();
+        let mut buf = vec![0; u32::from_le_bytes(len) as usize];
+        conn.read_exact(&mut buf).await.unwrap();
+
+        Response::decode(&buf[..]).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_clients() {
+        const CLIENTS: u16 = 32;
+        const ROUNDS: u16 = 50;
+
+        let (state, _rx) = new_state(&Config::default());
+
+        let clients: Vec<_> = (0..CLIENTS)
+            .map(|client| {
+                let (mut conn, _) = connect(&state);
+
+                tokio::task::spawn(async move {
+                    let prefix = Prefix {
+                        prefix: Ipv6Addr::new(0x2001, 0xdb8, client, 0, 0, 0, 0, 0),
+                        prefix_length: 64,
+                        preferred_lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+                        valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+                        deprecate_before: None,
+                        on_link: true,
+                        label: None,
+                        extra_addrs: Vec::new(),
+                    };
+                    let server = DnsServer {
+                        addr: Ipv6Addr::new(0x2001, 0xdb8, client, 0, 0, 0, 0, 0x53),
+                        lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+                        label: None,
+                    };
+
+                    for round in 0..ROUNDS {
+                        let resp = request(&mut conn, Request::AddPrefix(prefix.clone())).await;
+                        assert_eq!(resp, Response::Added);
+                        let resp = request(&mut conn, Request::AddDnsServer(server.clone())).await;
+                        assert_eq!(resp, Response::Ok { affected: 1 });
+
+                        let resp = request(&mut conn, Request::Export).await;
+                        assert!(matches!(resp, Response::State(_)));
+                        let resp = request(&mut conn, Request::GetStatus).await;
+                        assert!(matches!(resp, Response::Status(_)));
+
+                        // Odd clients keep their entries after the last round.
+                        if round + 1 < ROUNDS || client % 2 == 0 {
+                            let resp =
+                                request(&mut conn, Request::RemovePrefix(prefix.clone())).await;
+                            assert_eq!(resp, Response::Ok { affected: 1 });
+                            let resp =
+                                request(&mut conn, Request::RemoveDnsServer(server.clone())).await;
+                            assert_eq!(resp, Response::Ok { affected: 1 });
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        tokio::time::timeout(Duration::from_secs(30), async {
+            for client in clients {
+                client.await.unwrap();
+            }
+        })
+        .await
+        .expect("control handlers deadlocked");
+
+        let mut prefixes: Vec<_> = state.prefixes.read().keys().copied().collect();
+        prefixes.sort();
+        let mut dns_servers: Vec<_> = state.dns_servers.read().keys().copied().collect();
+        dns_servers.sort();
+
+        let kept = (0..CLIENTS).filter(|client| client % 2 == 1);
+        assert_eq!(
+            prefixes,
+            kept.clone()
+                .map(|client| Ipv6Addr::new(0x2001, 0xdb8, client, 0, 0, 0, 0, 0))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            dns_servers,
+            kept.map(|client| Ipv6Addr::new(0x2001, 0xdb8, client, 0, 0, 0, 0, 0x53))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn dns_server_label_change() {
+        let (state, _rx) = new_state(&Config::default());
+        let (mut conn, _) = connect(&state);
+
+        let mut server = DnsServer {
+            addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53),
+            lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+            label: None,
+        };
+        let resp = request(&mut conn, Request::AddDnsServer(server.clone())).await;
+        assert_eq!(resp, Response::Ok { affected: 1 });
+        state.config_changed.notified().await;
+
+        // The label is saved, but the advertised configuration is unchanged.
+        server.label = Some(String::from("iot"));
+        let resp = request(&mut conn, Request::AddDnsServer(server.clone())).await;
+        assert_eq!(resp, Response::Ok { affected: 0 });
+        tokio::time::timeout(Duration::from_secs(5), state.db_changed.notified())
+            .await
+            .expect("label change was not saved");
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), state.config_changed.notified())
+                .await
+                .is_err()
+        );
+
+        let Response::Status(status) = request(&mut conn, Request::GetStatus).await else {
+            panic!("expected status");
+        };
+        assert_eq!(
+            status.dns_servers,
+            [ConfiguredDnsServer {
+                addr: server.addr,
+                label: server.label,
+            }]
+        );
+    }
+
+    /// Returns the state of a daemon that requires the token `secret`.
+    fn authenticated_state() -> (Arc<State>, mpsc::Receiver<Command>) {
+        new_state(&Config {
+            control_token: Some(String::from("secret")),
+            ..Default::default()
+        })
+    }
+
+    fn dns_server() -> DnsServer {
+        DnsServer {
+            addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53),
+            lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+            label: None,
+        }
+    }
+
+    fn error(message: &str) -> Response {
+        Response::Error {
+            message: String::from(message),
+        }
+    }
+
+    #[tokio::test]
+    async fn auth_required_for_mutating_requests() {
+        let (state, _rx) = authenticated_state();
+        let (mut conn, _) = connect(&state);
+
+        let resp = request(&mut conn, Request::AddDnsServer(dns_server())).await;
+        assert_eq!(resp, error("not authenticated"));
+        assert!(state.dns_servers.read().is_empty());
+
+        // Reads do not require authentication.
+        let resp = request(&mut conn, Request::GetStatus).await;
+        assert!(matches!(resp, Response::Status(_)));
+        let resp = request(&mut conn, Request::Ping).await;
+        assert!(matches!(resp, Response::Pong { .. }));
+    }
+
+    #[tokio::test]
+    async fn auth_rejects_invalid_token() {
+        let (state, _rx) = authenticated_state();
+        let (mut conn, _) = connect(&state);
+
+        let resp = request(&mut conn, Request::Authenticate(String::from("guess"))).await;
+        assert_eq!(resp, error("invalid token"));
+
+        let resp = request(&mut conn, Request::AddDnsServer(dns_server())).await;
+        assert_eq!(resp, error("not authenticated"));
+    }
+
+    #[tokio::test]
+    async fn auth_must_be_first_request() {
+        let (state, _rx) = authenticated_state();
+        let (mut conn, _) = connect(&state);
+
+        let resp = request(&mut conn, Request::Ping).await;
+        assert!(matches!(resp, Response::Pong { .. }));
+
+        let resp = request(&mut conn, Request::Authenticate(String::from("secret"))).await;
+        assert_eq!(resp, error("authentication must be the first request"));
+
+        let resp = request(&mut conn, Request::AddDnsServer(dns_server())).await;
+        assert_eq!(resp, error("not authenticated"));
+    }
+
+    #[tokio::test]
+    async fn auth_after_hello() {
+        let (state, _rx) = authenticated_state();
+        let (mut conn, _) = connect(&state);
+
+        let resp = request(
+            &mut conn,
+            Request::Hello {
+                client_version: PROTOCOL_VERSION,
+            },
+        )
+        .await;
+        assert_eq!(
+            resp,
+            Response::Hello {
+                server_version: PROTOCOL_VERSION
+            }
+        );
+
+        let resp = request(&mut conn, Request::Authenticate(String::from("secret"))).await;
+        assert_eq!(resp, Response::Ok { affected: 1 });
+
+        let resp = request(&mut conn, Request::AddDnsServer(dns_server())).await;
+        assert_eq!(resp, Response::Ok { affected: 1 });
+    }
+
+    #[tokio::test]
+    async fn import_rejects_invalid_prefixes() {
+        let (state, _rx) = new_state(&Config::default());
+        let (mut conn, _) = connect(&state);
+
+        let db = Database {
+            prefixes: vec![crate::database::Prefix {
+                prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+                prefix_length: 64,
+                preferred: crate::database::Lifetime::Infinite,
+                valid: crate::database::Lifetime::Infinite,
+                deprecate_before: None,
+                on_link: true,
+                label: None,
+                extra_addrs: vec![Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 1)],
+            }],
+            dns_servers: vec![crate::database::DnsServer {
+                addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53),
+                label: None,
+            }],
+        };
+
+        let resp = request(&mut conn, Request::Import(db.export())).await;
+        assert!(matches!(resp, Response::Error { .. }));
+        assert!(state.prefixes.read().is_empty());
+        assert!(state.dns_servers.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_large_frames() {
+        let (state, _rx) = new_state(&Config::default());
+        let (mut conn, handler) = connect(&state);
+
+        // Only the header is sent, the body is never read.
+        conn.write_all(&(MAX_FRAME_LEN + 1).to_le_bytes())
+            .await
+            .unwrap();
+
+        let mut len = [0; FRAME_HEADER_LEN];
+        conn.read_exact(&mut len).await.unwrap();
+        let mut buf = vec![0; u32::from_le_bytes(len) as usize];
+        conn.read_exact(&mut buf).await.unwrap();
+        assert!(matches!(
+            Response::decode(&buf[..]).unwrap(),
+            Response::Error { .. }
+        ));
+
+        tokio::time::timeout(Duration::from_secs(5), handler)
+            .await
+            .expect("connection was not closed")
+            .unwrap();
+    }
+
+    #[test]
+    fn token_comparison() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+
+    #[tokio::test]
+    async fn accept_loop_shutdown() {
+        let path = std::env::temp_dir().join(format!("rsadv-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let socket = UnixListener::bind(&path).unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+        let (conn_tx, mut conn_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let task = tokio::task::spawn(accept_loop(socket, path.clone(), rx, move |stream| {
+            let _ = conn_tx.send(stream);
+        }));
+
+        // Connections are accepted until shutdown.
+        let _conn = UnixStream::connect(&path).await.unwrap();
+        assert!(conn_rx.recv().await.is_some());
+
+        tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn bind_control_socket_takes_over_stale_socket() {
+        let path =
+            std::env::temp_dir().join(format!("rsadv-test-stale-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // Dropping the listener leaves the socket file behind, like a crashed
+        // daemon.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let socket = bind_control_socket(&path).await.unwrap();
+        let _conn = UnixStream::connect(&path).await.unwrap();
+        socket.accept().await.unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bind_control_socket_in_use() {
+        let path =
+            std::env::temp_dir().join(format!("rsadv-test-in-use-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let _socket = UnixListener::bind(&path).unwrap();
+
+        let err = bind_control_socket(&path).await.unwrap_err();
+        assert!(matches!(err, ControlSocketError::SocketInUse));
+        // The socket of the running daemon must be left alone.
+        assert!(path.exists());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -6,10 +6,23 @@ use std::time::{Duration, SystemTime};
 
 use serde::{Deserialize, Serialize};
 
+/// The version of the format produced by [`Database::export`] and
+/// [`Database::save`].
+///
+/// This must be bumped whenever the layout of [`Database`] changes.
+const EXPORT_VERSION: u32 = 4;
+
+/// The bytes at the start of a database file, followed by the exported
+/// database.
+///
+/// Files written before the database was versioned have no header and are
+/// read as [`v0::Database`].
+const MAGIC: [u8; 4] = *b"RSDB";
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Database {
     pub prefixes: Vec<Prefix>,
-    pub dns_servers: Vec<Ipv6Addr>,
+    pub dns_servers: Vec<DnsServer>,
 }
 
 impl Database {
@@ -17,43 +30,271 @@ impl Database {
     where
         P: AsRef<Path>,
     {
-        let mut file = File::open(path).map_err(Error::Io)?;
+        let mut file = File::open(path)?;
 
         let mut buf = Vec::new();
-        file.read_to_end(&mut buf).map_err(Error::Io)?;
+        file.read_to_end(&mut buf)?;
 
-        bincode::deserialize(&buf).map_err(Error::Bincode)
+        match buf.strip_prefix(&MAGIC) {
+            Some(buf) => Self::import(buf),
+            None => {
+                let db: v0::Database = bincode::deserialize(&buf)?;
+                Ok(db.into())
+            }
+        }
     }
 
     pub fn save<P>(&self, path: P) -> Result<(), Error>
     where
         P: AsRef<Path>,
     {
-        let mut file = File::create(path).map_err(Error::Io)?;
+        let mut file = File::create(path)?;
 
-        let buf = bincode::serialize(self).unwrap();
-        file.write_all(&buf).map_err(Error::Io)?;
+        file.write_all(&MAGIC)?;
+        file.write_all(&self.export())?;
 
         Ok(())
     }
+
+    /// Serializes the database for transfer to another daemon.
+    ///
+    /// The data is prefixed with its format version.
+    pub fn export(&self) -> Vec<u8> {
+        let mut buf = EXPORT_VERSION.to_le_bytes().to_vec();
+        buf.extend(bincode::serialize(self).unwrap());
+        buf
+    }
+
+    /// Deserializes a database previously serialized with [`export`].
+    ///
+    /// [`export`]: Self::export
+    pub fn import(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() < 4 {
+            return Err(Error::Eof);
+        }
+
+        let (version, buf) = buf.split_at(4);
+        let version = u32::from_le_bytes(version.try_into().unwrap());
+        if version != EXPORT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        Ok(bincode::deserialize(buf)?)
+    }
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Prefix {
     pub prefix: Ipv6Addr,
     pub prefix_length: u8,
     pub preferred: Lifetime,
     pub valid: Lifetime,
+    pub deprecate_before: Option<Duration>,
+    pub on_link: bool,
+    pub label: Option<String>,
+    pub extra_addrs: Vec<Ipv6Addr>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsServer {
+    pub addr: Ipv6Addr,
+    pub label: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum Lifetime {
     Duration(Duration),
     Until(SystemTime),
+    Infinite,
 }
 
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     Bincode(bincode::Error),
+    Eof,
+    UnsupportedVersion(u32),
+}
+
+/// The headerless layout written before the database was versioned.
+mod v0 {
+    use std::net::Ipv6Addr;
+    use std::time::{Duration, SystemTime};
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Database {
+        pub prefixes: Vec<Prefix>,
+        pub dns_servers: Vec<Ipv6Addr>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct Prefix {
+        pub prefix: Ipv6Addr,
+        pub prefix_length: u8,
+        pub preferred: Lifetime,
+        pub valid: Lifetime,
+    }
+
+    #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+    pub enum Lifetime {
+        Duration(Duration),
+        Until(SystemTime),
+    }
+
+    impl From<Database> for super::Database {
+        fn from(db: Database) -> Self {
+            Self {
+                prefixes: db.prefixes.into_iter().map(Into::into).collect(),
+                dns_servers: db
+                    .dns_servers
+                    .into_iter()
+                    .map(|addr| super::DnsServer { addr, label: None })
+                    .collect(),
+            }
+        }
+    }
+
+    impl From<Prefix> for super::Prefix {
+        fn from(prefix: Prefix) -> Self {
+            Self {
+                prefix: prefix.prefix,
+                prefix_length: prefix.prefix_length,
+                preferred: prefix.preferred.into(),
+                valid: prefix.valid.into(),
+                deprecate_before: None,
+                on_link: true,
+                label: None,
+                extra_addrs: Vec::new(),
+            }
+        }
+    }
+
+    impl From<Lifetime> for super::Lifetime {
+        fn from(lifetime: Lifetime) -> Self {
+            match lifetime {
+                Lifetime::Duration(dur) => Self::Duration(dur),
+                Lifetime::Until(time) => Self::Until(time),
+            }
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        Self::Bincode(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+    use std::time::Duration;
+
+    use super::{v0, Database, DnsServer, Error, Lifetime, Prefix, EXPORT_VERSION};
+
+    #[test]
+    fn export_import() {
+        let db = Database {
+            prefixes: vec![Prefix {
+                prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+                prefix_length: 64,
+                preferred: Lifetime::Duration(Duration::from_secs(1800)),
+                valid: Lifetime::Infinite,
+                deprecate_before: None,
+                on_link: true,
+                label: Some(String::from("guest-vlan")),
+                extra_addrs: vec![Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)],
+            }],
+            dns_servers: vec![DnsServer {
+                addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53),
+                label: None,
+            }],
+        };
+
+        let output = Database::import(&db.export()).unwrap();
+        assert_eq!(output.prefixes.len(), 1);
+        assert_eq!(output.prefixes[0].prefix, db.prefixes[0].prefix);
+        assert_eq!(output.prefixes[0].label, db.prefixes[0].label);
+        assert_eq!(output.prefixes[0].extra_addrs, db.prefixes[0].extra_addrs);
+        assert_eq!(output.dns_servers, db.dns_servers);
+    }
+
+    #[test]
+    fn import_unsupported_version() {
+        let mut buf = Database::default().export();
+        buf[..4].copy_from_slice(&(EXPORT_VERSION + 1).to_le_bytes());
+
+        let err = Database::import(&buf).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedVersion(version) if version == EXPORT_VERSION + 1));
+        assert!(matches!(Database::import(&[1]), Err(Error::Eof)));
+    }
+
+    #[test]
+    fn save_load() {
+        let path = std::env::temp_dir().join(format!("rsadv-test-{}.db", std::process::id()));
+        let db = Database {
+            prefixes: vec![Prefix {
+                prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+                prefix_length: 64,
+                preferred: Lifetime::Duration(Duration::from_secs(1800)),
+                valid: Lifetime::Infinite,
+                deprecate_before: Some(Duration::from_secs(60)),
+                on_link: false,
+                label: Some(String::from("guest-vlan")),
+                extra_addrs: Vec::new(),
+            }],
+            dns_servers: Vec::new(),
+        };
+
+        db.save(&path).unwrap();
+        let output = Database::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let output = output.unwrap();
+        assert_eq!(output.prefixes.len(), 1);
+        assert_eq!(
+            output.prefixes[0].deprecate_before,
+            Some(Duration::from_secs(60))
+        );
+        assert!(!output.prefixes[0].on_link);
+        assert_eq!(output.prefixes[0].label, db.prefixes[0].label);
+    }
+
+    #[test]
+    fn load_unversioned() {
+        let path = std::env::temp_dir().join(format!("rsadv-test-v0-{}.db", std::process::id()));
+        let db = v0::Database {
+            prefixes: vec![v0::Prefix {
+                prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+                prefix_length: 64,
+                preferred: v0::Lifetime::Duration(Duration::from_secs(1800)),
+                valid: v0::Lifetime::Duration(Duration::from_secs(3600)),
+            }],
+            dns_servers: vec![Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53)],
+        };
+
+        std::fs::write(&path, bincode::serialize(&db).unwrap()).unwrap();
+        let output = Database::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let output = output.unwrap();
+        assert_eq!(output.prefixes.len(), 1);
+        assert_eq!(output.prefixes[0].prefix, db.prefixes[0].prefix);
+        assert!(output.prefixes[0].on_link);
+        assert_eq!(
+            output.dns_servers,
+            vec![DnsServer {
+                addr: db.dns_servers[0],
+                label: None,
+            }]
+        );
+    }
 }
@@ -0,0 +1,277 @@
+//! Stateless DHCPv6 server answering Information-Requests with the DNS
+//! servers, for hosts that use the O flag of RAs.
+//!
+//! DHCPv6: https://www.rfc-editor.org/rfc/rfc8415
+//! DHCPv6 DNS: https://www.rfc-editor.org/rfc/rfc3646
+
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut};
+use tokio::net::UdpSocket;
+
+use crate::State;
+
+const SERVER_PORT: u16 = 547;
+
+/// All_DHCP_Relay_Agents_and_Servers (ff02::1:2).
+const ALL_DHCP_RELAY_AGENTS_AND_SERVERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+
+const REPLY: u8 = 7;
+const INFORMATION_REQUEST: u8 = 11;
+
+const OPTION_CLIENTID: u16 = 1;
+const OPTION_SERVERID: u16 = 2;
+const OPTION_IA_NA: u16 = 3;
+const OPTION_IA_TA: u16 = 4;
+const OPTION_DNS_SERVERS: u16 = 23;
+const OPTION_IA_PD: u16 = 25;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    Eof,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub msg_type: u8,
+    pub transaction_id: [u8; 3],
+    pub options: Vec<DhcpOption>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DhcpOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl Message {
+    pub fn encode<B>(&self, mut buf: B)
+    where
+        B: BufMut,
+    {
+        buf.put_u8(self.msg_type);
+        buf.put_slice(&self.transaction_id);
+
+        for opt in &self.options {
+            buf.put_u16(opt.code);
+            buf.put_u16(opt.data.len() as u16);
+            buf.put_slice(&opt.data);
+        }
+    }
+
+    pub fn decode<B>(mut buf: B) -> Result<Self, Error>
+    where
+        B: Buf,
+    {
+        if buf.remaining() < 4 {
+            return Err(Error::Eof);
+        }
+
+        let msg_type = buf.get_u8();
+        let mut transaction_id = [0; 3];
+        buf.copy_to_slice(&mut transaction_id);
+
+        let mut options = Vec::new();
+        while buf.has_remaining() {
+            if buf.remaining() < 4 {
+                return Err(Error::Eof);
+            }
+
+            let code = buf.get_u16();
+            let len = buf.get_u16() as usize;
+            if buf.remaining() < len {
+                return Err(Error::Eof);
+            }
+
+            let mut data = vec![0; len];
+            buf.copy_to_slice(&mut data);
+            options.push(DhcpOption { code, data });
+        }
+
+        Ok(Self {
+            msg_type,
+            transaction_id,
+            options,
+        })
+    }
+
+    /// Returns the data of the first option with the given code.
+    pub fn option(&self, code: u16) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|opt| opt.code == code)
+            .map(|opt| opt.data.as_slice())
+    }
+}
+
+/// Returns the DUID-LL (RFC 8415 11.4) of the server with the given MAC.
+fn duid(mac: [u8; 6]) -> Vec<u8> {
+    // DUID type 3 (DUID-LL), hardware type 1 (Ethernet).
+    let mut duid = vec![0, 3, 0, 1];
+    duid.extend_from_slice(&mac);
+    duid
+}
+
+/// Returns the reply to `request`, or `None` if the request must be discarded.
+fn reply(request: &Message, server_id: &[u8], dns_servers: &[Ipv6Addr]) -> Option<Message> {
+    if request.msg_type != INFORMATION_REQUEST {
+        return None;
+    }
+
+    // Information-Requests for another server or with IA options MUST be
+    // discarded (RFC 8415 16.12).
+    if request
+        .option(OPTION_SERVERID)
+        .is_some_and(|id| id != server_id)
+    {
+        return None;
+    }
+
+    if [OPTION_IA_NA, OPTION_IA_TA, OPTION_IA_PD]
+        .iter()
+        .any(|code| request.option(*code).is_some())
+    {
+        return None;
+    }
+
+    let mut options = Vec::new();
+    if let Some(client_id) = request.option(OPTION_CLIENTID) {
+        options.push(DhcpOption {
+            code: OPTION_CLIENTID,
+            data: client_id.to_vec(),
+        });
+    }
+
+    options.push(DhcpOption {
+        code: OPTION_SERVERID,
+        data: server_id.to_vec(),
+    });
+
+    if !dns_servers.is_empty() {
+        options.push(DhcpOption {
+            code: OPTION_DNS_SERVERS,
+            data: dns_servers.iter().flat_map(|addr| addr.octets()).collect(),
+        });
+    }
+
+    Some(Message {
+        msg_type: REPLY,
+        transaction_id: request.transaction_id,
+        options,
+    })
+}
+
+/// Answers Information-Requests on the interface with the given scope id
+/// with the DNS servers in `state`.
+pub async fn serve(state: Arc<State>, scope_id: u32, mac: [u8; 6]) -> Result<(), io::Error> {
+    let socket =
+        UdpSocket::bind(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, SERVER_PORT, 0, 0)).await?;
+    socket.join_multicast_v6(&ALL_DHCP_RELAY_AGENTS_AND_SERVERS, scope_id)?;
+
+    let server_id = duid(mac);
+
+    let mut buf = vec![0; 1500];
+    loop {
+        let (len, addr) = socket.recv_from(&mut buf).await?;
+
+        // Only serve clients on our link.
+        match addr {
+            SocketAddr::V6(addr) if addr.scope_id() == scope_id => (),
+            _ => continue,
+        }
+
+        let request = match Message::decode(&buf[..len]) {
+            Ok(request) => request,
+            Err(err) => {
+                tracing::debug!("failed to decode DHCPv6 message from {}: {:?}", addr, err);
+                continue;
+            }
+        };
+
+        let mut dns_servers: Vec<_> = state.dns_servers.read().keys().copied().collect();
+        dns_servers.sort();
+
+        let Some(reply) = reply(&request, &server_id, &dns_servers) else {
+            continue;
+        };
+
+        let mut out = Vec::new();
+        reply.encode(&mut out);
+
+        if let Err(err) = socket.send_to(&out, addr).await {
+            tracing::error!("failed to send DHCPv6 reply to {}: {}", addr, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::{duid, reply, Message, INFORMATION_REQUEST, OPTION_DNS_SERVERS, REPLY};
+
+    const MAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    #[test]
+    fn information_request_reply() {
+        #[rustfmt::skip]
+        let request: &[u8] = &[
+            INFORMATION_REQUEST, 0x12, 0x34, 0x56,
+            // Client Identifier
+            0, 1, 0, 10, 0, 3, 0, 1, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            // Option Request: DNS servers
+            0, 6, 0, 2, 0, 23,
+        ];
+
+        let request = Message::decode(request).unwrap();
+        let dns = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53);
+        let reply = reply(&request, &duid(MAC), &[dns]).unwrap();
+
+        let mut buf = Vec::new();
+        reply.encode(&mut buf);
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            REPLY, 0x12, 0x34, 0x56,
+            // Client Identifier
+            0, 1, 0, 10, 0, 3, 0, 1, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            // Server Identifier
+            0, 2, 0, 10, 0, 3, 0, 1, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55,
+            // DNS servers
+            0, 23, 0, 16,
+            0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x53,
+        ];
+        assert_eq!(buf, expected);
+        assert_eq!(
+            Message::decode(&buf[..])
+                .unwrap()
+                .option(OPTION_DNS_SERVERS),
+            Some(&dns.octets()[..])
+        );
+    }
+
+    #[test]
+    fn information_request_discarded() {
+        // Addressed to another server.
+        #[rustfmt::skip]
+        let request: &[u8] = &[
+            INFORMATION_REQUEST, 0, 0, 1,
+            0, 2, 0, 10, 0, 3, 0, 1, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+        ];
+        let request = Message::decode(request).unwrap();
+        assert_eq!(reply(&request, &duid(MAC), &[]), None);
+
+        // Contains an IA_NA option.
+        let request = Message::decode(&[INFORMATION_REQUEST, 0, 0, 1, 0, 3, 0, 0][..]).unwrap();
+        assert_eq!(reply(&request, &duid(MAC), &[]), None);
+
+        // Not an Information-Request (Solicit).
+        let request = Message::decode(&[1, 0, 0, 1][..]).unwrap();
+        assert_eq!(reply(&request, &duid(MAC), &[]), None);
+
+        // Truncated option.
+        assert!(Message::decode(&[INFORMATION_REQUEST, 0, 0, 1, 0, 1, 0, 4, 0][..]).is_err());
+    }
+}
@@ -1,27 +1,53 @@
-use std::ffi::{c_int, c_void};
+use std::ffi::{c_int, c_void, CString};
 use std::io;
-use std::mem;
-use std::net::{IpAddr, Ipv6Addr};
+use std::mem::{self, MaybeUninit};
+use std::net::{IpAddr, Ipv6Addr, SocketAddrV6};
 use std::os::fd::{AsFd, AsRawFd};
 use std::time::Duration;
 
 use futures::TryStreamExt;
-use libc::{setsockopt, socklen_t, IPPROTO_IPV6, IPV6_MULTICAST_HOPS, IPV6_UNICAST_HOPS};
-use netlink_packet_route::address::{AddressAttribute, CacheInfo};
-use netlink_packet_route::link::LinkAttribute;
-use rtnetlink::Handle;
+use libc::{
+    setsockopt, socklen_t, IPPROTO_IPV6, IPV6_HOPLIMIT, IPV6_MULTICAST_HOPS, IPV6_PKTINFO,
+    IPV6_RECVHOPLIMIT, IPV6_RECVPKTINFO, IPV6_UNICAST_HOPS,
+};
+use netlink_packet_route::address::{AddressAttribute, AddressFlag, CacheInfo};
+use netlink_packet_route::link::{LinkAttribute, LinkFlag, LinkMessage};
+use netlink_packet_route::route::{RouteAttribute, RouteMessage, RouteType};
+use rtnetlink::{Handle, IpVersion};
 use socket2::Socket;
 
 #[derive(Debug)]
 pub enum Error {
     Rt(rtnetlink::Error),
     NoInterface,
+    /// There is no interface with the given name.
+    NoInterfaceWithName(String),
+    /// There is no interface with the given index.
+    NoInterfaceWithIndex(u32),
     NoMac,
 }
 
+impl From<rtnetlink::Error> for Error {
+    fn from(err: rtnetlink::Error) -> Self {
+        Self::Rt(err)
+    }
+}
+
+/// The state of Duplicate Address Detection of an address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DadState {
+    /// DAD is still in progress.
+    Tentative,
+    /// Another node on the link is using the address.
+    Failed,
+    /// The address is unique on the link.
+    Done,
+}
+
 #[derive(Clone, Debug)]
 pub struct Interface {
     index: u32,
+    name: String,
     handle: Handle,
 }
 
@@ -29,13 +55,37 @@ impl Interface {
     pub async fn new(handle: &Handle, name: &str) -> Result<Self, Error> {
         let mut links = handle.link().get().match_name(name.to_owned()).execute();
 
-        if let Some(link) = links.try_next().await.map_err(Error::Rt)? {
-            Ok(Self {
-                index: link.header.index,
-                handle: handle.clone(),
-            })
+        if let Some(link) = links.try_next().await? {
+            Ok(Self::from_link(handle, link))
         } else {
-            Err(Error::NoInterface)
+            Err(Error::NoInterfaceWithName(name.to_owned()))
+        }
+    }
+
+    pub async fn from_index(handle: &Handle, index: u32) -> Result<Self, Error> {
+        let mut links = handle.link().get().match_index(index).execute();
+
+        if let Some(link) = links.try_next().await? {
+            Ok(Self::from_link(handle, link))
+        } else {
+            Err(Error::NoInterfaceWithIndex(index))
+        }
+    }
+
+    fn from_link(handle: &Handle, link: LinkMessage) -> Self {
+        let name = link
+            .attributes
+            .iter()
+            .find_map(|attr| match attr {
+                LinkAttribute::IfName(name) => Some(name.clone()),
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        Self {
+            index: link.header.index,
+            name,
+            handle: handle.clone(),
         }
     }
 
@@ -43,9 +93,13 @@ impl Interface {
         self.index
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub async fn mac(&self) -> Result<[u8; 6], Error> {
         let mut links = self.handle.link().get().match_index(self.index).execute();
-        if let Some(link) = links.try_next().await.map_err(Error::Rt)? {
+        if let Some(link) = links.try_next().await? {
             for attr in &link.attributes {
                 match attr {
                     LinkAttribute::Address(addr) => {
@@ -62,6 +116,42 @@ impl Interface {
         Err(Error::NoInterface)
     }
 
+    pub async fn mtu(&self) -> Result<u32, Error> {
+        let mut links = self.handle.link().get().match_index(self.index).execute();
+        if let Some(link) = links.try_next().await? {
+            for attr in &link.attributes {
+                if let LinkAttribute::Mtu(mtu) = attr {
+                    return Ok(*mtu);
+                }
+            }
+        }
+
+        Err(Error::NoInterface)
+    }
+
+    /// Returns `true` if the interface is up and running, i.e. has a carrier.
+    pub async fn is_up(&self) -> Result<bool, Error> {
+        let mut links = self.handle.link().get().match_index(self.index).execute();
+        if let Some(link) = links.try_next().await? {
+            let flags = &link.header.flags;
+            return Ok(flags.contains(&LinkFlag::Up) && flags.contains(&LinkFlag::Running));
+        }
+
+        Err(Error::NoInterface)
+    }
+
+    /// Returns `true` if there is an IPv6 default route via this interface.
+    pub async fn has_default_route(&self) -> Result<bool, Error> {
+        let mut routes = self.handle.route().get(IpVersion::V6).execute();
+        while let Some(route) = routes.try_next().await? {
+            if is_default_route(&route, self.index) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
     pub async fn addrs(&self) -> Result<Vec<Ipv6Addr>, Error> {
         let mut links = self
             .handle
@@ -71,7 +161,7 @@ impl Interface {
             .execute();
 
         let mut addrs = Vec::new();
-        while let Some(link) = links.try_next().await.map_err(Error::Rt)? {
+        while let Some(link) = links.try_next().await? {
             for attr in &link.attributes {
                 match attr {
                     AddressAttribute::Address(addr) => match addr {
@@ -88,6 +178,70 @@ impl Interface {
         Ok(addrs)
     }
 
+    /// Returns the addresses that are usable as a stable source address, see
+    /// [`is_stable_addr`].
+    pub async fn stable_addrs(&self) -> Result<Vec<Ipv6Addr>, Error> {
+        let mut links = self
+            .handle
+            .address()
+            .get()
+            .set_link_index_filter(self.index)
+            .execute();
+
+        let mut addrs = Vec::new();
+        while let Some(link) = links.try_next().await? {
+            let flags = link.attributes.iter().find_map(|attr| match attr {
+                AddressAttribute::Flags(flags) => Some(flags.as_slice()),
+                _ => None,
+            });
+            if flags.is_some_and(|flags| !is_stable_addr(flags)) {
+                continue;
+            }
+
+            for attr in &link.attributes {
+                if let AddressAttribute::Address(IpAddr::V6(addr)) = attr {
+                    addrs.push(*addr);
+                }
+            }
+        }
+
+        Ok(addrs)
+    }
+
+    /// Returns the DAD state of `addr` or `None` if the address is not
+    /// configured on the interface.
+    pub async fn dad_state(&self, addr: Ipv6Addr) -> Result<Option<DadState>, Error> {
+        let mut addrs = self
+            .handle
+            .address()
+            .get()
+            .set_link_index_filter(self.index)
+            .execute();
+
+        while let Some(resp) = addrs.try_next().await? {
+            if !resp
+                .attributes
+                .contains(&AddressAttribute::Address(IpAddr::V6(addr)))
+            {
+                continue;
+            }
+
+            for attr in &resp.attributes {
+                if let AddressAttribute::Flags(flags) = attr {
+                    if flags.contains(&AddressFlag::Dadfailed) {
+                        return Ok(Some(DadState::Failed));
+                    } else if flags.contains(&AddressFlag::Tentative) {
+                        return Ok(Some(DadState::Tentative));
+                    }
+                }
+            }
+
+            return Ok(Some(DadState::Done));
+        }
+
+        Ok(None)
+    }
+
     pub async fn add_addr(
         &self,
         addr: IpAddr,
@@ -104,20 +258,74 @@ impl Interface {
             .replace();
 
         let mut cache_info = CacheInfo::default();
-        cache_info.ifa_preferred = preferred
-            .map(|dur| dur.as_secs().try_into().unwrap_or(u32::MAX))
-            .unwrap_or(u32::MAX);
-        cache_info.ifa_valid = valid
-            .map(|dur| dur.as_secs().try_into().unwrap_or(u32::MAX))
-            .unwrap_or(u32::MAX);
+        cache_info.ifa_preferred = netlink_lifetime(preferred);
+        cache_info.ifa_valid = netlink_lifetime(valid);
         msg.message_mut()
             .attributes
             .push(AddressAttribute::CacheInfo(cache_info));
 
-        match msg.execute().await {
-            Ok(()) => Ok(()),
-            Err(err) => return Err(Error::Rt(err)),
+        msg.execute().await?;
+        Ok(())
+    }
+
+    /// Adds a route via `gateway`.
+    ///
+    /// The kernel identifies IPv6 routes by their destination and metric
+    /// only, so `replace` must only be set to refresh a route this was
+    /// previously called for with the same `gateway`. Otherwise the route of
+    /// another gateway would be replaced.
+    pub async fn add_route(
+        &self,
+        dest: Ipv6Addr,
+        prefix_len: u8,
+        gateway: Ipv6Addr,
+        metric: u32,
+        replace: bool,
+    ) -> Result<(), Error> {
+        let msg = self.route_message(dest, prefix_len, gateway, metric);
+
+        let mut req = self.handle.route().add().v6();
+        if replace {
+            req = req.replace();
         }
+        *req.message_mut() = msg;
+
+        req.execute().await?;
+        Ok(())
+    }
+
+    pub async fn del_route(
+        &self,
+        dest: Ipv6Addr,
+        prefix_len: u8,
+        gateway: Ipv6Addr,
+        metric: u32,
+    ) -> Result<(), Error> {
+        let msg = self.route_message(dest, prefix_len, gateway, metric);
+
+        self.handle.route().del(msg).execute().await?;
+        Ok(())
+    }
+
+    fn route_message(
+        &self,
+        dest: Ipv6Addr,
+        prefix_len: u8,
+        gateway: Ipv6Addr,
+        metric: u32,
+    ) -> RouteMessage {
+        let mut req = self
+            .handle
+            .route()
+            .add()
+            .v6()
+            .destination_prefix(dest, prefix_len)
+            .gateway(gateway)
+            .output_interface(self.index);
+
+        let msg = req.message_mut();
+        msg.attributes.push(RouteAttribute::Priority(metric));
+        msg.clone()
     }
 
     pub async fn del_addr(&self, addr: IpAddr) -> Result<(), Error> {
@@ -128,17 +336,12 @@ impl Interface {
             .set_link_index_filter(self.index)
             .execute();
 
-        while let Some(resp) = addrs.try_next().await.map_err(Error::Rt)? {
+        while let Some(resp) = addrs.try_next().await? {
             for attr in &resp.attributes {
                 match attr {
                     AddressAttribute::Address(a) => {
                         if *a == addr {
-                            self.handle
-                                .address()
-                                .del(resp)
-                                .execute()
-                                .await
-                                .map_err(Error::Rt)?;
+                            self.handle.address().del(resp).execute().await?;
                             return Ok(());
                         }
                     }
@@ -150,3 +353,346 @@ impl Interface {
         Ok(())
     }
 }
+
+/// Returns whether IPv6 forwarding is enabled on the interface with the given name.
+pub fn ipv6_forwarding(interface: &str) -> Result<bool, io::Error> {
+    let value = std::fs::read_to_string(forwarding_sysctl(interface))?;
+    Ok(value.trim() != "0")
+}
+
+/// Enables or disables IPv6 forwarding on the interface with the given name.
+pub fn set_ipv6_forwarding(interface: &str, enabled: bool) -> Result<(), io::Error> {
+    std::fs::write(
+        forwarding_sysctl(interface),
+        if enabled { "1\n" } else { "0\n" },
+    )
+}
+
+fn forwarding_sysctl(interface: &str) -> String {
+    format!("/proc/sys/net/ipv6/conf/{}/forwarding", interface)
+}
+
+/// Returns the `accept_ra` sysctl of the interface with the given name.
+pub fn ipv6_accept_ra(interface: &str) -> Result<u32, io::Error> {
+    read_sysctl(&format!("/proc/sys/net/ipv6/conf/{}/accept_ra", interface))
+}
+
+/// Returns the `accept_ra_rt_info_max_plen` sysctl of the interface with the
+/// given name. The kernel only installs routes from Route Information options
+/// with a prefix length up to this value.
+pub fn ipv6_accept_ra_rt_info_max_plen(interface: &str) -> Result<u32, io::Error> {
+    read_sysctl(&format!(
+        "/proc/sys/net/ipv6/conf/{}/accept_ra_rt_info_max_plen",
+        interface
+    ))
+}
+
+/// Returns the `hop_limit` sysctl of the interface with the given name, the
+/// hop limit the kernel uses for packets sent on it.
+pub fn ipv6_hop_limit(interface: &str) -> Result<u8, io::Error> {
+    let path = format!("/proc/sys/net/ipv6/conf/{}/hop_limit", interface);
+    let value = read_sysctl(&path)?;
+    hop_limit(value).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid hop limit {} in {}", value, path),
+        )
+    })
+}
+
+/// Returns the sysctl `value` as a hop limit, `None` if it is not between 1
+/// and 255.
+fn hop_limit(value: u32) -> Option<u8> {
+    u8::try_from(value).ok().filter(|value| *value != 0)
+}
+
+/// Returns whether the kernel processes received RAs with the given
+/// `accept_ra` and `forwarding` sysctls.
+///
+/// With forwarding enabled RAs are only processed if `accept_ra` is 2.
+pub fn accepts_ra(accept_ra: u32, forwarding: bool) -> bool {
+    match accept_ra {
+        0 => false,
+        1 => !forwarding,
+        _ => true,
+    }
+}
+
+fn read_sysctl(path: &str) -> Result<u32, io::Error> {
+    let value = std::fs::read_to_string(path)?;
+    parse_sysctl(&value).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid value {:?} in {}", value, path),
+        )
+    })
+}
+
+fn parse_sysctl(value: &str) -> Option<u32> {
+    value.trim().parse().ok()
+}
+
+/// Returns the ID of the group with the given name, or `None` if there is no
+/// such group.
+pub fn group_id(name: &str) -> Result<Option<u32>, io::Error> {
+    let name = CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "group name contains NUL"))?;
+
+    let mut group: libc::group = unsafe { mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buf = vec![0; 1024];
+
+    loop {
+        let res = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut group,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+
+        match res {
+            0 if result.is_null() => return Ok(None),
+            0 => return Ok(Some(group.gr_gid)),
+            // The buffer is too small for the group entry.
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            err => return Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+/// Enables receiving the `IPV6_PKTINFO` control message with every packet.
+pub fn set_recv_pktinfo(socket: &Socket) -> Result<(), io::Error> {
+    let enable: c_int = 1;
+
+    let res = unsafe {
+        setsockopt(
+            socket.as_raw_fd(),
+            IPPROTO_IPV6,
+            IPV6_RECVPKTINFO,
+            &enable as *const c_int as *const c_void,
+            mem::size_of::<c_int>() as socklen_t,
+        )
+    };
+
+    if res == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Enables receiving the `IPV6_HOPLIMIT` control message with every packet.
+pub fn set_recv_hoplimit(socket: &Socket) -> Result<(), io::Error> {
+    let enable: c_int = 1;
+
+    let res = unsafe {
+        setsockopt(
+            socket.as_raw_fd(),
+            IPPROTO_IPV6,
+            IPV6_RECVHOPLIMIT,
+            &enable as *const c_int as *const c_void,
+            mem::size_of::<c_int>() as socklen_t,
+        )
+    };
+
+    if res == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// A packet received with [`recv_from_with_dst`].
+#[derive(Copy, Clone, Debug)]
+pub struct Received {
+    /// The length of the packet.
+    pub len: usize,
+    /// The source address, `None` if it is not an IPv6 address.
+    pub src: Option<SocketAddrV6>,
+    /// The destination address, only available if [`set_recv_pktinfo`] was
+    /// enabled on the socket.
+    pub dst: Option<Ipv6Addr>,
+    /// The hop limit, only available if [`set_recv_hoplimit`] was enabled on
+    /// the socket.
+    pub hop_limit: Option<u8>,
+}
+
+/// Receives a packet from `socket` using `recvmsg`.
+pub fn recv_from_with_dst(
+    socket: &Socket,
+    buf: &mut [MaybeUninit<u8>],
+) -> Result<Received, io::Error> {
+    let mut src: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+
+    // Large enough for the `in6_pktinfo` and hop limit control messages.
+    // Using `u64` guarantees the alignment required for `cmsghdr`.
+    let mut control = [0u64; 16];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = (&mut src as *mut libc::sockaddr_in6).cast();
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_in6>() as socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if len == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut dst = None;
+    let mut hop_limit = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == IPPROTO_IPV6 && (*cmsg).cmsg_type == IPV6_PKTINFO {
+                let info = libc::CMSG_DATA(cmsg)
+                    .cast::<libc::in6_pktinfo>()
+                    .read_unaligned();
+                dst = Some(Ipv6Addr::from(info.ipi6_addr.s6_addr));
+            }
+
+            if (*cmsg).cmsg_level == IPPROTO_IPV6 && (*cmsg).cmsg_type == IPV6_HOPLIMIT {
+                let value = libc::CMSG_DATA(cmsg).cast::<c_int>().read_unaligned();
+                hop_limit = u8::try_from(value).ok();
+            }
+
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    let src = (c_int::from(src.sin6_family) == libc::AF_INET6).then(|| {
+        SocketAddrV6::new(
+            Ipv6Addr::from(src.sin6_addr.s6_addr),
+            u16::from_be(src.sin6_port),
+            src.sin6_flowinfo,
+            src.sin6_scope_id,
+        )
+    });
+
+    Ok(Received {
+        len: len as usize,
+        src,
+        dst,
+        hop_limit,
+    })
+}
+
+/// Returns `true` if an address with `flags` stays usable as a source
+/// address: it has no finite lifetime, which rules out temporary and other
+/// dynamic addresses, and is neither tentative, deprecated nor failed DAD.
+fn is_stable_addr(flags: &[AddressFlag]) -> bool {
+    flags.contains(&AddressFlag::Permanent)
+        && !flags.iter().any(|flag| {
+            matches!(
+                flag,
+                AddressFlag::Tentative | AddressFlag::Deprecated | AddressFlag::Dadfailed
+            )
+        })
+}
+
+/// Returns `true` if `route` is a unicast default route with a next hop via
+/// the interface `index`.
+fn is_default_route(route: &RouteMessage, index: u32) -> bool {
+    if route.header.destination_prefix_length != 0 || route.header.kind != RouteType::Unicast {
+        return false;
+    }
+
+    route.attributes.iter().any(|attr| match attr {
+        RouteAttribute::Oif(oif) => *oif == index,
+        RouteAttribute::MultiPath(hops) => hops.iter().any(|hop| hop.interface_index == index),
+        _ => false,
+    })
+}
+
+/// Returns the lifetime in seconds for `IFA_CACHEINFO`.
+///
+/// `None` and durations that do not fit are `u32::MAX` (infinity), the same
+/// sentinel as in prefix information options.
+pub fn netlink_lifetime(lifetime: Option<Duration>) -> u32 {
+    lifetime
+        .map(|dur| dur.as_secs().try_into().unwrap_or(u32::MAX))
+        .unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use netlink_packet_route::address::AddressFlag;
+    use netlink_packet_route::route::{RouteAttribute, RouteMessage, RouteType};
+
+    use super::{accepts_ra, hop_limit, is_default_route, is_stable_addr, parse_sysctl};
+
+    #[test]
+    fn sysctl_values() {
+        assert_eq!(parse_sysctl("1\n"), Some(1));
+        assert_eq!(parse_sysctl("2"), Some(2));
+        assert_eq!(parse_sysctl(""), None);
+        assert_eq!(parse_sysctl("-1\n"), None);
+    }
+
+    #[test]
+    fn hop_limit_values() {
+        assert_eq!(hop_limit(1), Some(1));
+        assert_eq!(hop_limit(64), Some(64));
+        assert_eq!(hop_limit(255), Some(255));
+        assert_eq!(hop_limit(0), None);
+        assert_eq!(hop_limit(256), None);
+    }
+
+    #[test]
+    fn accept_ra_with_forwarding() {
+        assert!(!accepts_ra(0, false));
+        assert!(!accepts_ra(0, true));
+        assert!(accepts_ra(1, false));
+        assert!(!accepts_ra(1, true));
+        assert!(accepts_ra(2, false));
+        assert!(accepts_ra(2, true));
+    }
+
+    #[test]
+    fn stable_addr() {
+        assert!(is_stable_addr(&[AddressFlag::Permanent]));
+        assert!(is_stable_addr(&[
+            AddressFlag::Permanent,
+            AddressFlag::Noprefixroute
+        ]));
+
+        // Addresses with a finite lifetime, e.g. temporary addresses.
+        assert!(!is_stable_addr(&[]));
+        assert!(!is_stable_addr(&[AddressFlag::Managetempaddr]));
+
+        for flag in [
+            AddressFlag::Tentative,
+            AddressFlag::Deprecated,
+            AddressFlag::Dadfailed,
+        ] {
+            assert!(!is_stable_addr(&[AddressFlag::Permanent, flag]));
+        }
+    }
+
+    #[test]
+    fn default_route() {
+        let mut route = RouteMessage::default();
+        route.header.kind = RouteType::Unicast;
+        route.attributes.push(RouteAttribute::Oif(2));
+
+        assert!(is_default_route(&route, 2));
+        assert!(!is_default_route(&route, 3));
+
+        // A route to a prefix, e.g. of the upstream link.
+        route.header.destination_prefix_length = 64;
+        assert!(!is_default_route(&route, 2));
+
+        route.header.destination_prefix_length = 0;
+        route.header.kind = RouteType::Unreachable;
+        assert!(!is_default_route(&route, 2));
+    }
+}
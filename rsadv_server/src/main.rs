@@ -2,22 +2,31 @@
 //! NDP DNS: https://www.rfc-editor.org/rfc/rfc8106
 
 mod config;
+mod consistency;
 mod control;
 mod database;
+#[cfg(feature = "dhcpv6")]
+mod dhcpv6;
 mod linux;
 mod ndp;
+mod routes;
+mod selftest;
 
-use std::collections::{HashMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::future::Future;
 use std::io;
 use std::net::{IpAddr, Ipv6Addr, SocketAddrV6};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use config::Config;
-use control::control_loop;
+use config::{Config, CurHopLimit};
+use consistency::inconsistencies;
+use control::{control_loop, tcp_control_loop, SocketPermissions};
 use database::Database;
 use futures::{pin_mut, FutureExt};
-use linux::Interface;
+use linux::{DadState, Interface};
 use ndp::{
     Encode, IcmpContent, IcmpOption, IcmpType, LinkLayerAddress, PrefixInformation,
     RecursiveDnsServer, RouterAdvertisement, RouterSolicitation,
@@ -26,17 +35,37 @@ use ragequit::SHUTDOWN;
 use rand::distributions::Uniform;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
-use rsadv_control::Lifetime;
+use rsadv_control::{AdvertisementStats, Event, Lifetime};
 use rtnetlink::new_connection;
 use socket2::{Domain, Protocol, Socket, Type};
 use tokio::io::unix::AsyncFd;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::{mpsc, Notify};
+use tracing::Instrument;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter};
 
-use crate::ndp::{Decode, IcmpPacket};
+use crate::ndp::{decode_with_offset, IcmpPacket};
 
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
+    if std::env::args().any(|arg| arg == "--print-default-config") {
+        print!("{}", config::SAMPLE_CONFIG);
+        return;
+    }
+
+    if std::env::args().any(|arg| arg == "--selftest") {
+        let passed = selftest::run();
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    // Runs without reading or writing the database, e.g. for testing.
+    let persist = !std::env::args().any(|arg| arg == "--no-persist");
+
+    init_logger();
     ragequit::init();
 
     let config = match Config::from_file("config.toml") {
@@ -73,10 +102,57 @@ async fn main() {
         v => v,
     };
 
+    let router_lifetime = 3 * max_rtr_adv_interval;
+
+    // AdvDefaultLifetime MUST be either 0 or between MaxRtrAdvInterval and 9000s.
+    // This also applies to solicited RAs, otherwise hosts may drop the router
+    // before the next unsolicited RA arrives.
+    let solicited_router_lifetime = match config.solicited_router_lifetime {
+        None => router_lifetime,
+        Some(0) => Duration::ZERO,
+        Some(v) if Duration::from_secs(v) < max_rtr_adv_interval => {
+            tracing::warn!("solicited_router_lifetime is < max_rtr_adv_interval; defaulting to max_rtr_adv_interval");
+            max_rtr_adv_interval
+        }
+        Some(v) if v > 9000 => {
+            tracing::warn!("solicited_router_lifetime is > 9000s; defaulting to 9000s");
+            Duration::from_secs(9000)
+        }
+        Some(v) => Duration::from_secs(v),
+    };
+
+    let dns_lifetime = match config.dns_lifetime {
+        None => 3 * max_rtr_adv_interval,
+        Some(v) => {
+            let v = Duration::from_secs(v);
+            // Hosts would drop the DNS servers between two RAs.
+            if v < max_rtr_adv_interval {
+                tracing::warn!(
+                    "dns_lifetime is < max_rtr_adv_interval; DNS servers may expire between RAs"
+                );
+            }
+
+            v
+        }
+    };
+
     let (conn, handle, _) = new_connection().unwrap();
-    tokio::task::spawn(conn);
+    tokio::task::spawn(async move {
+        conn.await;
 
-    let interface = match Interface::new(&handle, &config.interface).await {
+        // All operations on the interface fail without the connection and
+        // every `Interface` shares its handle, so we can't reconnect. Shut
+        // down cleanly instead of continuing without addresses and routes.
+        tracing::error!("netlink connection closed unexpectedly; shutting down");
+        SHUTDOWN.quit();
+    });
+
+    let interface = match parse_interface_index(&config.interface) {
+        Some(index) => Interface::from_index(&handle, index).await,
+        None => Interface::new(&handle, &config.interface).await,
+    };
+
+    let interface = match interface {
         Ok(interface) => interface,
         Err(err) => {
             tracing::error!("failed to open interface {}: {:?}", config.interface, err);
@@ -86,23 +162,141 @@ async fn main() {
 
     let mac = interface.mac().await.unwrap();
     let addrs = interface.addrs().await.unwrap();
+    let stable_addrs = interface.stable_addrs().await.unwrap();
     let scope_id = interface.scope_id();
 
-    let Some(link_local) = addrs.into_iter().find(is_link_local) else {
+    // Only fall back to addresses that may disappear (e.g. because they are
+    // still tentative at boot) if there is no stable one, since the socket
+    // stays bound to the selected address.
+    let link_local = match select_link_local(&stable_addrs, mac) {
+        Some(addr) => Some(addr),
+        None => {
+            let addr = select_link_local(&addrs, mac);
+            if let Some(addr) = addr {
+                tracing::warn!(
+                    "no stable link local address; sending RAs from {}, which may be removed",
+                    addr
+                );
+            }
+            addr
+        }
+    };
+    let Some(link_local) = link_local else {
         tracing::error!("no link local address");
         std::process::exit(1);
     };
 
-    let local_addr = SocketAddrV6::new(link_local, 0, 0, scope_id);
+    let mtu = match interface.mtu().await {
+        Ok(mtu) => Some(mtu),
+        Err(err) => {
+            tracing::warn!("failed to get interface MTU: {:?}", err);
+            None
+        }
+    };
+
+    // Hosts would send packets we are unable to receive.
+    if let Some(mtu) = mtu {
+        if config.mtu > mtu {
+            tracing::warn!(
+                "advertised MTU {} is larger than the interface MTU {}",
+                config.mtu,
+                mtu
+            );
+        }
+    }
+
+    // Without receiving RSs there is no need to join the all-routers group.
+    let opts = SocketOptions {
+        join_all_routers: config.receive_solicitations,
+        recv_buffer_size: recv_buffer_size(config.recv_buffer_size, mtu),
+        ..Default::default()
+    };
 
-    let socket = match IcmpSocket::new(local_addr) {
+    let socket = match IcmpSocket::bind(link_local, scope_id, opts) {
         Ok(socket) => Arc::new(socket),
         Err(err) => {
-            tracing::error!("failed to bind ICMP: {}", err);
+            match err.raw_os_error() {
+                Some(libc::EADDRINUSE) => tracing::error!(
+                    "failed to bind ICMP: {}; is another RA daemon (e.g. radvd) running?",
+                    err
+                ),
+                Some(libc::EADDRNOTAVAIL) => tracing::error!(
+                    "failed to bind ICMP: {}; is {} configured on {} and has completed DAD?",
+                    err,
+                    link_local,
+                    interface.name(),
+                ),
+                _ => tracing::error!("failed to bind ICMP: {}", err),
+            }
+
             std::process::exit(1);
         }
     };
 
+    // Hosts use us as their default router, which is pointless if we
+    // do not forward their packets.
+    let mut forwarding = linux::ipv6_forwarding(interface.name());
+    if config.enable_forwarding && matches!(forwarding, Ok(false)) {
+        match linux::set_ipv6_forwarding(interface.name(), true) {
+            Ok(()) => {
+                tracing::info!("enabled IPv6 forwarding on {}", interface.name());
+                forwarding = Ok(true);
+            }
+            Err(err) => {
+                tracing::error!("failed to enable IPv6 forwarding: {}", err);
+            }
+        }
+    }
+
+    let forwarding_enabled = matches!(forwarding, Ok(true));
+    match forwarding {
+        Ok(true) => (),
+        Ok(false) if !router_lifetime.is_zero() => {
+            if config.require_forwarding {
+                tracing::error!(
+                    "IPv6 forwarding is disabled on {}; refusing to advertise as a default router",
+                    interface.name()
+                );
+                std::process::exit(1);
+            }
+
+            tracing::warn!(
+                "IPv6 forwarding is disabled on {}; hosts will not be able to use this router",
+                interface.name()
+            );
+        }
+        Ok(false) => (),
+        Err(err) => {
+            tracing::warn!("failed to read IPv6 forwarding state: {}", err);
+        }
+    }
+
+    // A router should not autoconfigure itself from RAs of other routers on
+    // the link it advertises on, and routes from Route Information options
+    // should only be installed once.
+    match linux::ipv6_accept_ra(interface.name()) {
+        Ok(accept_ra) if linux::accepts_ra(accept_ra, forwarding_enabled) => {
+            tracing::warn!(
+                "the kernel accepts RAs on {} (accept_ra = {}); it may use default routes and prefixes of other routers",
+                interface.name(),
+                accept_ra
+            );
+
+            if config.accept_route_information
+                && matches!(linux::ipv6_accept_ra_rt_info_max_plen(interface.name()), Ok(plen) if plen > 0)
+            {
+                tracing::warn!(
+                    "both the kernel and rsadv install routes from Route Information options on {}",
+                    interface.name()
+                );
+            }
+        }
+        Ok(_) => (),
+        Err(err) => {
+            tracing::warn!("failed to read accept_ra: {}", err);
+        }
+    }
+
     let packet = IcmpPacket {
         typ: IcmpType::RouterSolicitation,
         code: 0,
@@ -112,304 +306,655 @@ async fn main() {
         }),
     };
 
-    let state = Arc::new(State {
-        prefixes: Default::default(),
-        mtu: config.mtu,
-        config_changed: Default::default(),
-        dns_servers: Default::default(),
-    });
-
-    let mut db = match Database::load(&config.db) {
-        Ok(db) => {
-            let mut prefixes = state.prefixes.write();
+    let (cmd_tx, mut cmd_rx) = mpsc::channel(512);
 
-            for prefix in &db.prefixes {
-                prefixes.insert(
-                    prefix.prefix,
-                    Prefix {
-                        prefix: prefix.prefix,
-                        prefix_length: prefix.prefix_length,
-                        preferred_lifetime: match prefix.preferred {
-                            crate::database::Lifetime::Duration(dur) => Lifetime::Duration(dur),
-                            crate::database::Lifetime::Until(ts) => Lifetime::Until(ts),
-                        },
-                        valid_lifetime: match prefix.valid {
-                            crate::database::Lifetime::Duration(dur) => Lifetime::Duration(dur),
-                            crate::database::Lifetime::Until(ts) => Lifetime::Until(ts),
-                        },
-                    },
+    let mut state = State::new(&config, cmd_tx.clone());
+    if config.cur_hop_limit == CurHopLimit::Auto {
+        match linux::ipv6_hop_limit(interface.name()) {
+            Ok(hop_limit) => {
+                tracing::info!(
+                    "advertising hop limit {} of {}",
+                    hop_limit,
+                    interface.name()
                 );
+                state.cur_hop_limit = hop_limit;
+            }
+            Err(err) => {
+                tracing::warn!("failed to read hop limit; leaving it unspecified: {}", err);
             }
-
-            db
         }
-        Err(err) => {
-            tracing::error!("failed to load database: {:?}", err);
-            Database::default()
+    }
+    if persist {
+        match Database::load(&config.db) {
+            Ok(db) => {
+                state.load_database(&db);
+            }
+            Err(database::Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+                tracing::info!("database {} does not exist; starting empty", config.db);
+            }
+            Err(err) => {
+                // Move the file aside so the next save cannot overwrite
+                // whatever it still contains.
+                let backup = format!("{}.unreadable", config.db);
+                tracing::error!(
+                    "failed to load database: {:?}; moving it to {}",
+                    err,
+                    backup
+                );
+                if let Err(err) = std::fs::rename(&config.db, &backup) {
+                    tracing::error!("failed to move database aside: {}; refusing to start", err);
+                    std::process::exit(1);
+                }
+            }
         }
-    };
+    } else {
+        tracing::info!("persistence is disabled; ignoring database {}", config.db);
+        state.db_path = None;
+    }
+
+    if state.is_unconfigured() {
+        if config.require_prefix {
+            tracing::error!("no prefixes or DNS servers are configured; refusing to start");
+            std::process::exit(1);
+        }
+
+        tracing::warn!("no prefixes or DNS servers are configured; RAs only announce the router");
+    }
+
+    let state = Arc::new(state);
 
     let mut buf = Vec::new();
     packet.encode(&mut buf);
 
     {
         let state = state.clone();
+        let interface = interface.clone();
+        let permissions = SocketPermissions {
+            mode: config.control_socket_mode(),
+            group: config.control_socket_group.clone(),
+        };
         tokio::task::spawn(async move {
-            if let Err(err) = control_loop(state).await {
+            if let Err(err) = control_loop(state, interface, permissions).await {
                 tracing::error!("failed to run control loop: {}", err);
                 SHUTDOWN.quit();
             }
         });
     }
 
-    let (cmd_tx, mut cmd_rx) = mpsc::channel(512);
+    if let Some(addr) = config.control_tcp_bind {
+        let state = state.clone();
+        let interface = interface.clone();
+        tokio::task::spawn(async move {
+            if let Err(err) = tcp_control_loop(state, interface, addr).await {
+                tracing::error!("failed to run TCP control loop on {}: {}", addr, err);
+                SHUTDOWN.quit();
+            }
+        });
+    }
 
-    {
+    tokio::task::spawn(link_loop(state.clone(), interface.clone()));
+
+    if let Some(name) = &config.upstream_interface {
+        let upstream = match parse_interface_index(name) {
+            Some(index) => Interface::from_index(&handle, index).await,
+            None => Interface::new(&handle, name).await,
+        };
+
+        match upstream {
+            Ok(upstream) => {
+                let interval = Duration::from_secs(config.upstream_check_interval);
+                tokio::task::spawn(upstream_loop(state.clone(), upstream, interval));
+            }
+            Err(err) => {
+                tracing::error!("failed to open upstream interface {}: {:?}", name, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if !config.include_source_lladdr {
+        tracing::warn!("include_source_lladdr is disabled; hosts must resolve our MAC address with a separate Neighbor Solicitation");
+    }
+
+    if config.dhcpv6 && !config.other_config {
+        tracing::warn!("dhcpv6 is enabled, but other_config is not; not starting DHCPv6 server");
+    }
+
+    #[cfg(feature = "dhcpv6")]
+    if config.dhcpv6 && config.other_config {
+        let state = state.clone();
+        tokio::task::spawn(async move {
+            if let Err(err) = dhcpv6::serve(state, scope_id, mac).await {
+                tracing::error!("failed to run DHCPv6 server: {}", err);
+            }
+        });
+    }
+
+    #[cfg(not(feature = "dhcpv6"))]
+    if config.dhcpv6 {
+        tracing::warn!("dhcpv6 is enabled, but rsadv was built without the dhcpv6 feature");
+    }
+
+    let max_ra_delay = Duration::from_millis(config.max_ra_delay_ms);
+    if max_ra_delay > min_rtr_adv_interval {
+        tracing::warn!(
+            "max_ra_delay_ms of {:?} is longer than min_rtr_adv_interval of {:?}; solicited RAs are delayed more than unsolicited RAs are apart",
+            max_ra_delay,
+            min_rtr_adv_interval
+        );
+    }
+
+    let scheduler = {
         let socket = socket.clone();
         let state = state.clone();
+        let cmd_tx = cmd_tx.clone();
         let shutdown = SHUTDOWN.listen();
         tokio::task::spawn(async move {
-            let mut last_multicast_ra = Instant::now();
-            let mut next_multicast_ra = Instant::now();
+            let mut schedule = MulticastSchedule::new(Instant::now());
 
             // The interval between unsolicited RAs is chosen by a uniformly
             // distributed random value between MinRtrAdvInterval and
             // MaxRtrAdvInterval.
             debug_assert!(min_rtr_adv_interval <= MIN_DELAY_BETWEEN_RAS);
             let uniform = Uniform::new(min_rtr_adv_interval, max_rtr_adv_interval);
-            let mut rng = SmallRng::from_entropy();
+            let mut rng = new_rng(config.rng_seed);
 
-            let mut initial_ras_sent = 0;
+            let mut initial_ras = InitialAdvertisements::default();
+            let mut fast_ras = FastAdvertisements::new(config.fast_readvertise_count);
 
             pin_mut!(shutdown);
             loop {
-                tracing::info!(
-                    "next multicast RA in {:?}",
-                    next_multicast_ra - Instant::now()
-                );
+                // Stay silent until resumed. The final RA is still sent on shutdown.
+                if state.paused.load(Ordering::Relaxed) && !shutdown.is_in_progress() {
+                    futures::select_biased! {
+                        _ = shutdown.as_mut().fuse() => (),
+                        _ = state.pause_changed.notified().fuse() => {
+                            if !state.paused.load(Ordering::Relaxed) {
+                                // Hosts may have dropped us as a router, so start over as if
+                                // we had just started.
+                                schedule.next = Instant::now();
+                                initial_ras.reset();
+                                fast_ras.reset();
+                            }
+                        }
+                        res = cmd_rx.recv().fuse() => {
+                            if let Command::SendRouterAdvertisement(addr, _) = res.unwrap() {
+                                state.solicitation_queue.pop(&addr);
+                            }
+                        }
+                    }
+
+                    continue;
+                }
 
-                let addr = futures::select_biased! {
+                tracing::info!("next multicast RA in {:?}", schedule.next - Instant::now());
+
+                // `solicited` is `true` if this RA is a unicast response to a RS.
+                let (addr, solicited) = futures::select_biased! {
                     _ = shutdown.as_mut().fuse() => {
-                        SocketAddrV6::new(Ipv6Addr::MULTICAST_ALL_NODES, 0, 0, scope_id)
+                        (SocketAddrV6::new(Ipv6Addr::MULTICAST_ALL_NODES, 0, 0, scope_id), false)
                     },
-                    _ = tokio::time::sleep_until(next_multicast_ra.into()).fuse() => {
-                        SocketAddrV6::new(Ipv6Addr::MULTICAST_ALL_NODES, 0, 0, scope_id)
+                    _ = tokio::time::sleep_until(schedule.next.into()).fuse() => {
+                        (SocketAddrV6::new(Ipv6Addr::MULTICAST_ALL_NODES, 0, 0, scope_id), false)
+                    }
+                    _ = state.pause_changed.notified().fuse() => {
+                        if !state.paused.load(Ordering::Relaxed) || !state.withdraw_on_pause {
+                            continue;
+                        }
+
+                        // Tell hosts to stop using us before going silent.
+                        (SocketAddrV6::new(Ipv6Addr::MULTICAST_ALL_NODES, 0, 0, scope_id), false)
                     }
                     res = cmd_rx.recv().fuse() => {
                         match res.unwrap() {
-                            Command::SendRouterAdvertisement(addr) => {
-                                // All RAs in response to RSs MUST be delayed between 0 and `MAX_RA_DELAY_TIME`.
-                                let delay = rng.gen_range(Duration::ZERO..MAX_RA_DELAY_TIME);
-                                let ts = Instant::now() + delay;
-
-                                // If delaying would take longer then until the next multicast RA is scheduled
-                                // we discard the RS. The host will receive a multicast RA in time instead.
-                                if ts > next_multicast_ra {
-                                    continue;
+                            Command::SendRouterAdvertisement(addr, remaining) => {
+                                state.solicitation_queue.pop(&addr);
+
+                                // The retransmission is scheduled independently of whether this
+                                // transmission is answered by a multicast RA.
+                                if let Some((interval, remaining)) = next_retransmission(&addr, remaining, max_ra_delay) {
+                                    let cmd_tx = cmd_tx.clone();
+                                    tokio::task::spawn(async move {
+                                        tokio::time::sleep(interval).await;
+                                        let _ = cmd_tx.send(Command::SendRouterAdvertisement(addr, remaining)).await;
+                                    });
                                 }
 
+                                // All RAs in response to RSs MUST be delayed between 0 and `MAX_RA_DELAY_TIME`.
+                                let delay = solicited_delay(&mut rng, max_ra_delay);
+
                                 // If the source address is UNSPECIFIED we MUST send a multicast RA instead,
                                 // otherwise we can send it directly to the host as a unicast.
-                                if addr.ip().is_unspecified() {
-                                    // Multicast RAs MUST be sent no faster than `MIN_DELAY_BETWEEN_RAS`.
-                                    let ra_delay = MIN_DELAY_BETWEEN_RAS.checked_sub(last_multicast_ra.elapsed()).unwrap_or_default();
-                                    next_multicast_ra += ra_delay + delay;
+                                let multicast = addr.ip().is_unspecified();
+                                let Some(ts) = schedule.solicit(Instant::now(), delay, multicast) else {
                                     continue;
-                                }
+                                };
 
-                                // Note that since ts < next_multicast_ra this sleep will never block
+                                // Note that since ts < schedule.next this sleep will never block
                                 // for longer than the other branch.
                                 tokio::time::sleep_until(ts.into()).await;
-                                addr
+                                (addr, true)
                             },
                             Command::NewConfig => {
-                                next_multicast_ra = Instant::now();
-                                initial_ras_sent = 0;
-                                SocketAddrV6::new(Ipv6Addr::MULTICAST_ALL_NODES, 0, 0, scope_id)
+                                schedule.next = Instant::now();
+                                initial_ras.reset();
+                                fast_ras.reset();
+                                (SocketAddrV6::new(Ipv6Addr::MULTICAST_ALL_NODES, 0, 0, scope_id), false)
                             }
                         }
                     }
                 };
 
+                // A RS answered while shutdown began gets the final multicast RA instead.
+                let (addr, solicited) = if shutdown.is_in_progress() {
+                    (
+                        SocketAddrV6::new(Ipv6Addr::MULTICAST_ALL_NODES, 0, 0, scope_id),
+                        false,
+                    )
+                } else {
+                    (addr, solicited)
+                };
+
                 // On shutdown we should send a RA with the `router_liftime` field set to 0.
                 let router_lifetime = if shutdown.is_in_progress() {
                     Duration::ZERO
+                } else if !state.upstream.load(Ordering::Relaxed) {
+                    // Hosts would send their traffic to us only for it to be dropped.
+                    Duration::ZERO
+                } else if state.paused.load(Ordering::Relaxed) {
+                    Duration::ZERO
+                } else if solicited {
+                    solicited_router_lifetime
                 } else {
-                    3 * max_rtr_adv_interval
+                    router_lifetime
                 };
 
-                let mut options = vec![
-                    IcmpOption::Mtu(config.mtu),
-                    IcmpOption::SourceLinkLayerAddress(LinkLayerAddress(mac)),
-                ];
-
-                {
-                    let dns = state.dns_servers.read();
-
-                    if !dns.is_empty() {
-                        let addrs = dns.iter().copied().collect();
-
-                        options.push(IcmpOption::RecursiveDnsServer(RecursiveDnsServer {
-                            addrs,
-                            lifetime: Duration::from_secs(3600),
-                        }));
-                    }
-                }
-
-                for prefix in state.prefixes.read().values() {
-                    // We only announce prefixes that are still valid.
-                    // Expired prefixes are removed by another task, but it is possible
-                    // for a prefix to just have gone invalid and we are running before
-                    // the other task has removed it.
-                    if prefix.valid_lifetime.duration().is_zero() {
-                        continue;
-                    }
-
-                    options.push(IcmpOption::PrefixInformation(PrefixInformation {
-                        prefix: prefix.prefix,
-                        prefix_length: prefix.prefix_length,
-                        on_link: true,
-                        autonomous: true,
-                        preferred_lifetime: prefix.preferred_lifetime.duration(),
-                        valid_lifetime: prefix.valid_lifetime.duration(),
-                    }));
-                }
+                let adv = build_advertisement(&state, mac, dns_lifetime, router_lifetime);
+                initial_ras.update(&adv.options);
+                let options = adv.options.clone();
 
                 let packet = IcmpPacket {
                     typ: IcmpType::RouterAdvertisement,
                     code: 0,
                     checksum: 0,
-                    content: IcmpContent::RouterAdvertisement(RouterAdvertisement {
-                        cur_hop_limit: 64,
-                        managed: false,
-                        other: false,
-                        router_lifetime,
-                        reachable_timer: None,
-                        retrans_timer: None,
-                        options,
-                    }),
+                    content: IcmpContent::RouterAdvertisement(adv),
                 };
 
-                if let Err(err) = socket.send_to(&packet, addr).await {
-                    tracing::error!("failed to send RA: {}", err);
+                let trigger = if shutdown.is_in_progress() {
+                    "shutdown"
+                } else if state.paused.load(Ordering::Relaxed) {
+                    "pause"
+                } else if solicited {
+                    "solicited"
+                } else {
+                    "multicast"
+                };
+                let span = tracing::debug_span!("send_ra", dst = %addr.ip(), trigger);
+
+                async {
+                    let res = socket.send_to(&packet, addr).await;
+                    state
+                        .health
+                        .last_send_ok
+                        .store(res.is_ok(), Ordering::Relaxed);
+                    match res {
+                        Ok(size) => {
+                            state.record_advertisement(&options, size);
+                            state.record(Event::RouterAdvertisementSent(*addr.ip()));
+
+                            if let IcmpContent::RouterAdvertisement(adv) = packet.content {
+                                tracing::debug!(size, "sent RA: {}", adv);
+                                *state.last_advertisement.lock() = Some((Instant::now(), adv));
+                            }
+                        }
+                        Err(err) => tracing::error!("failed to send RA: {}", err),
+                    }
                 }
+                .instrument(span)
+                .await;
 
                 if shutdown.is_in_progress() {
                     break;
                 }
 
-                let mut interval = rng.sample(uniform);
+                // Unicast responses do not replace the next multicast RA. Hosts whose RS
+                // was answered by the next multicast RA rely on it being sent.
+                if !solicited {
+                    let interval = fast_ras.clamp(initial_ras.clamp(rng.sample(uniform)));
+                    schedule.sent(Instant::now(), interval);
 
-                // For the first MAX_INITIAL_RTR_ADVERTISEMENTS we should clamp the random
-                // interval to `MAX_INITIAL_RTR_ADVERT_INTERVAL`.
-                // TODO: We MAY repeat this procedure if the advertised information changes.
-                if initial_ras_sent < MAX_INITIAL_RTR_ADVERTISEMENTS {
-                    initial_ras_sent += 1;
-                    interval = Duration::min(interval, MAX_INITIAL_RTR_ADVERT_INTERVAL);
+                    if state.advance_withdrawals() {
+                        state.config_changed.notify_one();
+                    }
                 }
-
-                last_multicast_ra = next_multicast_ra;
-                next_multicast_ra += interval;
             }
 
             if let Err(err) = socket.close().await {
                 tracing::error!("failed to close socket: {}", err);
             }
+        })
+    };
+
+    {
+        let state = state.clone();
+        tokio::task::spawn(async move {
+            if let Err(err) = scheduler.await {
+                tracing::error!("RA scheduler failed: {}", err);
+                state.health.scheduler_failed.store(true, Ordering::Relaxed);
+            }
         });
     }
 
-    {
-        let cmd_tx = cmd_tx.clone();
+    // Accepting routes from other routers is a trust decision and
+    // disabled by default.
+    let route_tx = if config.accept_route_information {
+        let (route_tx, route_rx) = mpsc::channel(512);
+        tokio::task::spawn(routes::route_loop(
+            interface.clone(),
+            route_rx,
+            SHUTDOWN.listen(),
+        ));
+        Some(route_tx)
+    } else {
+        None
+    };
+
+    // The socket only needs to be read if we respond to RSs or accept
+    // information from the RAs of other routers.
+    if config.receive_solicitations || route_tx.is_some() {
+        let state = state.clone();
+        let receive_solicitations = config.receive_solicitations;
+        let solicited_ra_retransmits = config.solicited_ra_retransmits;
+        let shutdown = SHUTDOWN.listen();
         tokio::task::spawn(async move {
+            pin_mut!(shutdown);
             loop {
-                let (packet, addr) = match socket.recv_from().await {
+                let res = futures::select_biased! {
+                    _ = shutdown.as_mut().fuse() => {
+                        state.solicitation_queue.close();
+                        return;
+                    }
+                    res = socket.recv_from().fuse() => res,
+                };
+
+                let (packet, addr, dst, hop_limit) = match res {
                     Ok(res) => res,
                     Err(err) => {
                         tracing::error!("failed to read from socket: {}", err);
+                        state
+                            .health
+                            .rs_listener_failed
+                            .store(true, Ordering::Relaxed);
                         return;
                     }
                 };
 
+                if let IcmpContent::RouterAdvertisement(adv) = &packet.content {
+                    // Ignore our own RAs.
+                    if *addr.ip() == link_local {
+                        continue;
+                    }
+
+                    if !router_advert_is_valid(*addr.ip(), hop_limit, &packet) {
+                        tracing::debug!("dropping invalid RA from {}", addr);
+                        continue;
+                    }
+
+                    tracing::debug!(
+                        "RA from {}: prefixes {:?}, dns servers {:?}",
+                        addr,
+                        adv.prefixes()
+                            .map(|info| (info.prefix, info.prefix_length))
+                            .collect::<Vec<_>>(),
+                        adv.rdnss()
+                            .flat_map(|rdnss| &rdnss.addrs)
+                            .collect::<Vec<_>>(),
+                    );
+
+                    // Routers SHOULD log inconsistent information advertised
+                    // by other routers on the link (RFC 4861 6.2.7).
+                    if let Some((sent, ours)) = &*state.last_advertisement.lock() {
+                        for inconsistency in inconsistencies(adv, ours, sent.elapsed()) {
+                            tracing::warn!("router {} {}", addr.ip(), inconsistency);
+                        }
+                    }
+
+                    if let Some(route_tx) = &route_tx {
+                        for info in adv.routes() {
+                            let _ = route_tx.send((*info, *addr.ip())).await;
+                        }
+                    }
+
+                    continue;
+                }
+
+                if !receive_solicitations {
+                    continue;
+                }
+
+                // Entered guards must not be held across an await point. Nothing below
+                // awaits until the next iteration.
+                let _span = tracing::debug_span!("rs", src = %addr.ip()).entered();
+
                 if !router_solicit_is_valid(*addr.ip(), &packet) {
+                    if let IcmpContent::RouterSolicitation(_) = &packet.content {
+                        state.record(Event::RouterSolicitationRejected(*addr.ip()));
+                    }
+
+                    continue;
+                }
+
+                // RSs must be sent to the all-routers group or to our address.
+                if let Some(dst) = dst {
+                    if dst != Ipv6Addr::MULTICAST_ALL_ROUTERS && dst != link_local {
+                        tracing::debug!("ignoring RS from {} to {}", addr, dst);
+                        state.record(Event::RouterSolicitationRejected(*addr.ip()));
+                        continue;
+                    }
+                }
+
+                if let IcmpContent::RouterSolicitation(sol) = &packet.content {
+                    let mut solicitations = state.solicitations.lock();
+                    if solicitations.len() >= MAX_RECENT_SOLICITATIONS {
+                        solicitations.pop_front();
+                    }
+
+                    solicitations.push_back(Solicitation {
+                        received: Instant::now(),
+                        addr: *addr.ip(),
+                        link_layer_addr: sol.source_link_layer_addr.map(|addr| addr.0),
+                    });
+                }
+
+                // RSs are not answered while paused.
+                if state.paused.load(Ordering::Relaxed) {
                     continue;
                 }
 
-                let _ = cmd_tx.send(Command::SendRouterAdvertisement(addr)).await;
+                // Never block on a full queue, the socket must keep being drained.
+                state
+                    .solicitation_queue
+                    .push(addr, solicited_ra_retransmits);
             }
         });
     }
 
+    if config.rng_seed.is_some() {
+        tracing::warn!("rng_seed is set; random addresses are predictable");
+    }
+
+    if persist {
+        let state = state.clone();
+        let path = config.db.clone();
+        let interval = Duration::from_millis(config.db_save_interval);
+        let shutdown = SHUTDOWN.listen();
+        tokio::task::spawn(async move {
+            save_loop(&state.db_changed, interval, shutdown, || {
+                if let Err(err) = state.to_database().save(&path) {
+                    tracing::error!("failed to save db: {:?}", err);
+                }
+            })
+            .await;
+        });
+    }
+
+    let config_change_delay = Duration::from_millis(config.config_change_delay);
     tokio::task::spawn(async move {
-        let mut next_prefix_lifetime = None;
+        let mut rng = new_rng(config.rng_seed);
+        let mut expiries = ExpiryQueue::default();
+        // The configured address of each prefix.
+        let mut configured_addrs: HashMap<Ipv6Addr, Ipv6Addr> = HashMap::new();
+        // The configured extra addresses of each prefix.
+        let mut configured_extra_addrs: HashMap<Ipv6Addr, Vec<Ipv6Addr>> = HashMap::new();
 
         loop {
-            // Wait until we get a new prefix or an existing prefix expires.
-            if let Some(next_prefix_lifetime) = next_prefix_lifetime {
-                futures::select_biased! {
-                    _ = state.config_changed.notified().fuse() => (),
-                    _ = tokio::time::sleep(next_prefix_lifetime).fuse() => (),
+            // Wait until we get a new prefix or an existing prefix is
+            // deprecated or expires.
+            let changed = match expiries.next() {
+                Some(next) => futures::select_biased! {
+                    _ = state.config_changed.notified().fuse() => true,
+                    _ = tokio::time::sleep_until(next.into()).fuse() => false,
+                },
+                None => {
+                    state.config_changed.notified().await;
+                    true
                 }
-            } else {
-                state.config_changed.notified().await;
+            };
+
+            // Any prefix may have changed, so all events are rescheduled.
+            if changed {
+                settle(&state.config_changed, config_change_delay).await;
+                expiries.rebuild(Instant::now(), state.prefixes.read().values());
             }
 
-            state.prefixes.write().retain(|_, prefix| {
-                if prefix.valid_lifetime.duration().is_zero() {
-                    false
-                } else {
-                    let lifetime =
-                        next_prefix_lifetime.get_or_insert(prefix.valid_lifetime.duration());
+            {
+                let now = Instant::now();
+                let mut prefixes = state.prefixes.write();
+                for addr in expiries.pop_due(now) {
+                    let Some(prefix) = prefixes.get(&addr) else {
+                        continue;
+                    };
 
-                    if prefix.valid_lifetime.duration() < *lifetime {
-                        *lifetime = prefix.valid_lifetime.duration();
+                    if prefix.valid_remaining().is_zero() {
+                        state.record(Event::PrefixExpired(prefix.prefix, prefix.prefix_length));
+                        prefixes.remove(&addr);
+                    } else {
+                        expiries.schedule(now, prefix);
                     }
-
-                    true
                 }
-            });
+            }
 
             // Config has changed and we should send a new multicast RA.
             let _ = cmd_tx.send(Command::NewConfig).await;
 
-            db.prefixes.clear();
-            db.dns_servers.clear();
-
+            let mut addrs = HashMap::new();
+            let mut extra_addrs = HashMap::new();
             let prefixes = state.prefixes.read().clone();
-            for prefix in prefixes.values() {
-                let addr = generate_addr(prefix.prefix, mac);
-
-                if let Err(err) = interface
-                    .add_addr(
-                        IpAddr::V6(addr),
-                        prefix.prefix_length,
-                        Some(prefix.preferred_lifetime.duration()),
-                        Some(prefix.valid_lifetime.duration()),
-                    )
-                    .await
-                {
-                    tracing::error!("failed to add addr to interface: {:?}", err);
-                }
 
-                db.prefixes.push(database::Prefix {
-                    prefix: prefix.prefix,
-                    prefix_length: prefix.prefix_length,
-                    preferred: match prefix.preferred_lifetime {
-                        Lifetime::Duration(dur) => crate::database::Lifetime::Duration(dur),
-                        Lifetime::Until(ts) => crate::database::Lifetime::Until(ts),
-                    },
-                    valid: match prefix.valid_lifetime {
-                        Lifetime::Duration(dur) => crate::database::Lifetime::Duration(dur),
-                        Lifetime::Until(ts) => crate::database::Lifetime::Until(ts),
-                    },
-                });
+            // Without SLAAC there is no address for us to configure.
+            let configure_addresses = config.configure_addresses && config.autonomous;
+            let interface_addrs = if configure_addresses && !prefixes.is_empty() {
+                interface.addrs().await.unwrap_or_else(|err| {
+                    tracing::error!("failed to get interface addresses: {:?}", err);
+                    Vec::new()
+                })
+            } else {
+                Vec::new()
+            };
+
+            // Prefixes are configured concurrently, so waiting for DAD on a
+            // new address does not hold up the others.
+            if configure_addresses {
+                let configured = futures::future::join_all(prefixes.values().map(|prefix| {
+                    let existing = configured_addrs.get(&prefix.prefix).copied();
+                    let ours = configured_extra_addrs
+                        .get(&prefix.prefix)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default();
+                    let mut rng = SmallRng::seed_from_u64(rng.gen());
+                    let (interface, interface_addrs, config) =
+                        (&interface, &interface_addrs, &config);
+
+                    async move {
+                        let addr = configure_addr(
+                            interface,
+                            prefix,
+                            mac,
+                            existing,
+                            interface_addrs,
+                            config,
+                            &mut rng,
+                        )
+                        .await;
+                        let extra = configure_extra_addrs(
+                            interface,
+                            prefix,
+                            addr,
+                            ours,
+                            interface_addrs,
+                            config,
+                        )
+                        .await;
+                        (prefix.prefix, addr, extra)
+                    }
+                }))
+                .await;
+
+                for (prefix, addr, extra) in configured {
+                    if let Some(addr) = addr {
+                        addrs.insert(prefix, addr);
+                    }
+
+                    if !extra.is_empty() {
+                        extra_addrs.insert(prefix, extra);
+                    }
+                }
             }
 
-            for dns in state.dns_servers.read().clone() {
-                db.dns_servers.push(dns);
+            // Remove the addresses of all prefixes that are no longer announced.
+            for (prefix, addr) in &configured_addrs {
+                if addrs.get(prefix) == Some(addr) {
+                    continue;
+                }
+
+                if let Err(err) = interface.del_addr(IpAddr::V6(*addr)).await {
+                    tracing::error!("failed to remove addr from interface: {:?}", err);
+                }
             }
+            configured_addrs = addrs;
+
+            for (prefix, addrs) in &configured_extra_addrs {
+                let kept = extra_addrs
+                    .get(prefix)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                for addr in addrs {
+                    // The address may now be the generated address of a prefix.
+                    if kept.contains(addr) || configured_addrs.values().any(|ours| ours == addr) {
+                        continue;
+                    }
 
-            if let Err(err) = db.save(&config.db) {
-                tracing::error!("failed to save db: {:?}", err);
+                    if let Err(err) = interface.del_addr(IpAddr::V6(*addr)).await {
+                        tracing::error!("failed to remove addr from interface: {:?}", err);
+                    }
+                }
+            }
+            configured_extra_addrs = extra_addrs;
+
+            let mut published: Vec<_> = configured_addrs
+                .iter()
+                .chain(
+                    configured_extra_addrs
+                        .iter()
+                        .flat_map(|(prefix, addrs)| addrs.iter().map(move |addr| (prefix, addr))),
+                )
+                .filter_map(|(prefix, addr)| Some((*addr, prefixes.get(prefix)?.prefix_length)))
+                .collect();
+            published.sort();
+            *state.configured_addrs.write() = published;
+
+            if persist {
+                state.db_changed.notify_one();
             }
         }
     });
@@ -417,56 +962,599 @@ async fn main() {
     SHUTDOWN.wait().await;
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct State {
     prefixes: parking_lot::RwLock<HashMap<Ipv6Addr, Prefix>>,
     mtu: u32,
+    /// The number of RAs that advertise a removed prefix as withdrawn before
+    /// it is dropped. Removed prefixes are dropped immediately if `0`.
+    prefix_withdrawal: u32,
     config_changed: Notify,
-    dns_servers: parking_lot::RwLock<HashSet<Ipv6Addr>>,
+    /// Notified when the database needs to be saved, see [`save_loop`].
+    db_changed: Notify,
+    /// Whether sending RAs and answering RSs is paused.
+    paused: AtomicBool,
+    /// Wakes the RA scheduler when `paused` changes.
+    pause_changed: Notify,
+    /// Whether a RA with a router lifetime of 0 is sent when pausing.
+    withdraw_on_pause: bool,
+    /// The advertised DNS servers and their labels.
+    ///
+    /// If both are locked at once, `prefixes` must be locked first.
+    dns_servers: parking_lot::RwLock<HashMap<Ipv6Addr, Option<String>>>,
+    started: Instant,
+    /// Whether the daemon is able to send RAs and answer RSs.
+    health: Health,
+    /// The last `MAX_RECENT_SOLICITATIONS` received RSs, oldest first.
+    solicitations: parking_lot::Mutex<VecDeque<Solicitation>>,
+    /// RSs waiting to be answered by the RA scheduler.
+    solicitation_queue: SolicitationQueue,
+    /// The last `MAX_RECENT_EVENTS` recorded events, oldest first.
+    events: parking_lot::Mutex<VecDeque<(Instant, Event)>>,
+    /// The path of the database, used to reload the state. `None` if
+    /// persistence is disabled with `--no-persist`.
+    db_path: Option<String>,
+    /// Whether `upstream_interface` has a default route. RAs are sent with a
+    /// router lifetime of 0 otherwise. Always `true` without an upstream
+    /// interface.
+    upstream: AtomicBool,
+    /// The token required to change the state over the control socket.
+    control_token: Option<String>,
+    /// The preferred lifetime of prefixes as a fraction of their valid
+    /// lifetime, if configured.
+    preferred_ratio: Option<f64>,
+    /// The size and contents of the sent RAs.
+    advertisement_stats: parking_lot::Mutex<AdvertisementStats>,
+    /// The last sent RA and when it was sent, compared with the RAs of other
+    /// routers.
+    last_advertisement: parking_lot::Mutex<Option<(Instant, RouterAdvertisement)>>,
+    /// The Cur Hop Limit of RAs, 0 if unspecified.
+    cur_hop_limit: u8,
+    /// Whether the O flag is set in RAs.
+    other_config: bool,
+    /// Whether the Source Link-Layer Address option is included in RAs.
+    include_source_lladdr: bool,
+    /// The Provisioning Domain announced in RAs, if any.
+    pvd_id: Option<String>,
+    /// Whether the A flag is set for all prefixes.
+    autonomous: bool,
+    /// Whether DNS servers that hosts cannot use are rejected.
+    strict_dns_servers: bool,
+    /// The maximum number of addresses configured for a prefix, including
+    /// the generated address.
+    max_addresses_per_prefix: u32,
+    /// The addresses configured on the interface for advertised prefixes
+    /// and their prefix lengths.
+    configured_addrs: parking_lot::RwLock<Vec<(Ipv6Addr, u8)>>,
 }
 
-#[derive(Clone, Debug)]
-pub struct Prefix {
-    pub prefix: Ipv6Addr,
-    pub prefix_length: u8,
-    pub preferred_lifetime: Lifetime,
-    pub valid_lifetime: Lifetime,
-}
+impl State {
+    fn new(config: &Config, cmd_tx: mpsc::Sender<Command>) -> Self {
+        Self {
+            prefixes: Default::default(),
+            mtu: config.mtu,
+            prefix_withdrawal: if config.graceful_prefix_withdrawal {
+                config.withdrawal_advertisements
+            } else {
+                0
+            },
+            config_changed: Default::default(),
+            db_changed: Default::default(),
+            paused: AtomicBool::new(false),
+            pause_changed: Default::default(),
+            withdraw_on_pause: config.withdraw_on_pause,
+            dns_servers: Default::default(),
+            started: Instant::now(),
+            health: Health::new(),
+            solicitations: Default::default(),
+            solicitation_queue: SolicitationQueue::new(cmd_tx),
+            events: Default::default(),
+            db_path: Some(config.db.clone()),
+            upstream: AtomicBool::new(true),
+            control_token: config.control_token.clone(),
+            preferred_ratio: config.preferred_ratio,
+            advertisement_stats: Default::default(),
+            last_advertisement: Default::default(),
+            // `auto` is resolved once the interface is known.
+            cur_hop_limit: match config.cur_hop_limit {
+                CurHopLimit::Value(hop_limit) => hop_limit,
+                CurHopLimit::Auto => 0,
+            },
+            other_config: config.other_config,
+            include_source_lladdr: config.include_source_lladdr,
+            pvd_id: config.pvd_id.clone(),
+            autonomous: config.autonomous,
+            strict_dns_servers: config.strict_dns_servers,
+            max_addresses_per_prefix: config.max_addresses_per_prefix,
+            configured_addrs: Default::default(),
+        }
+    }
 
-pub struct IcmpSocket {
+    /// Records `event` in the event log, dropping the oldest event if it is full.
+    fn record(&self, event: Event) {
+        let mut events = self.events.lock();
+        if events.len() >= MAX_RECENT_EVENTS {
+            events.pop_front();
+        }
+
+        events.push_back((Instant::now(), event));
+    }
+
+    /// Records the size and option counts of a sent RA.
+    fn record_advertisement(&self, options: &[IcmpOption], size: usize) {
+        let mut stats = self.advertisement_stats.lock();
+        stats.size = size as u32;
+        stats.max_size = u32::max(stats.max_size, stats.size);
+        stats.prefix_options = 0;
+        stats.dns_options = 0;
+        stats.other_options = 0;
+
+        for option in options {
+            match option {
+                IcmpOption::PrefixInformation(_) => stats.prefix_options += 1,
+                IcmpOption::RecursiveDnsServer(_) => stats.dns_options += 1,
+                _ => stats.other_options += 1,
+            }
+        }
+
+        // The size does not include the 40 byte IPv6 header.
+        if self.mtu != 0 && size + 40 > self.mtu as usize {
+            tracing::warn!(
+                "sent RA of {} bytes exceeds the MTU of {} bytes",
+                size,
+                self.mtu
+            );
+        }
+    }
+
+    /// Replaces all prefixes and DNS servers with the ones from `db`.
+    ///
+    /// Prefixes that fail [`check_database`] are skipped with a warning.
+    ///
+    /// Returns the number of loaded entries.
+    ///
+    /// [`check_database`]: Self::check_database
+    fn load_database(&self, db: &Database) -> usize {
+        let mut pruned = 0;
+        let prefixes: HashMap<_, _> = db
+            .prefixes
+            .iter()
+            .filter_map(|prefix| match self.check_prefix(prefix) {
+                Ok(prefix) => Some(prefix),
+                Err(err) => {
+                    tracing::warn!("skipping prefix from the database: {}", err);
+                    None
+                }
+            })
+            .map(|prefix| {
+                (
+                    prefix.prefix,
+                    Prefix {
+                        prefix: prefix.prefix,
+                        prefix_length: prefix.prefix_length,
+                        preferred_lifetime: prefix.preferred_lifetime,
+                        valid_lifetime: prefix.valid_lifetime,
+                        deprecate_before: prefix.deprecate_before,
+                        on_link: prefix.on_link,
+                        label: prefix.label,
+                        extra_addrs: prefix.extra_addrs,
+                        withdrawal: None,
+                        added: ClockAnchor::now(),
+                    },
+                )
+            })
+            // Prefixes whose valid lifetime ended while we were not running
+            // must not be advertised, not even until the next expiry.
+            .filter(|(_, prefix)| {
+                let expired = prefix.valid_remaining().is_zero();
+                pruned += expired as usize;
+                !expired
+            })
+            .collect();
+        if pruned != 0 {
+            tracing::info!("pruned {} expired prefixes from the database", pruned);
+        }
+
+        let dns_servers: HashMap<_, _> = db
+            .dns_servers
+            .iter()
+            .map(|server| {
+                if let Some(reason) = unusable_dns_server(server.addr) {
+                    tracing::warn!(
+                        "loaded DNS server {} is {} and cannot be used by hosts",
+                        server.addr,
+                        reason
+                    );
+                }
+
+                (server.addr, server.label.clone())
+            })
+            .collect();
+
+        // Replace both at once so that no RA is sent with only one of them
+        // replaced.
+        let mut prefixes_guard = self.prefixes.write();
+        let mut dns_servers_guard = self.dns_servers.write();
+
+        let loaded = prefixes.len() + dns_servers.len();
+        *prefixes_guard = prefixes;
+        *dns_servers_guard = dns_servers;
+        loaded
+    }
+
+    /// Checks all prefixes in `db` like [`Request::AddPrefix`].
+    ///
+    /// [`Request::AddPrefix`]: rsadv_control::Request::AddPrefix
+    fn check_database(&self, db: &Database) -> Result<(), String> {
+        for prefix in &db.prefixes {
+            self.check_prefix(prefix)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks a prefix from a database, returning the normalized prefix.
+    fn check_prefix(&self, prefix: &database::Prefix) -> Result<rsadv_control::Prefix, String> {
+        let lifetime = |lifetime| match lifetime {
+            database::Lifetime::Duration(dur) => Lifetime::Duration(dur),
+            database::Lifetime::Until(ts) => Lifetime::Until(ts),
+            database::Lifetime::Infinite => Lifetime::Infinite,
+        };
+
+        let checked = rsadv_control::Prefix {
+            prefix: prefix.prefix,
+            prefix_length: prefix.prefix_length,
+            preferred_lifetime: lifetime(prefix.preferred),
+            valid_lifetime: lifetime(prefix.valid),
+            deprecate_before: prefix.deprecate_before,
+            on_link: prefix.on_link,
+            label: prefix.label.clone(),
+            extra_addrs: prefix.extra_addrs.clone(),
+        }
+        .normalize()
+        .map_err(|err| {
+            format!(
+                "invalid prefix {}/{}: {:?}",
+                prefix.prefix, prefix.prefix_length, err
+            )
+        })?;
+
+        // The generated address counts towards the limit.
+        if checked.extra_addrs.len() >= self.max_addresses_per_prefix as usize {
+            return Err(format!(
+                "prefix {}/{} has {} addresses; at most {} are allowed",
+                checked.prefix,
+                checked.prefix_length,
+                checked.extra_addrs.len() + 1,
+                self.max_addresses_per_prefix,
+            ));
+        }
+
+        Ok(checked)
+    }
+
+    /// Returns `true` if neither prefixes nor DNS servers are advertised.
+    fn is_unconfigured(&self) -> bool {
+        self.prefixes.read().is_empty() && self.dns_servers.read().is_empty()
+    }
+
+    /// Removes the prefix `addr` from `prefixes`, or starts its withdrawal if
+    /// graceful prefix withdrawal is enabled.
+    ///
+    /// Returns the removed prefix, or `None` if there is no such prefix or it
+    /// is already withdrawn.
+    fn withdraw_prefix(
+        &self,
+        prefixes: &mut HashMap<Ipv6Addr, Prefix>,
+        addr: Ipv6Addr,
+    ) -> Option<Prefix> {
+        let prefix = prefixes.get_mut(&addr)?;
+        if prefix.withdrawal.is_some() {
+            return None;
+        }
+
+        if self.prefix_withdrawal == 0 {
+            return prefixes.remove(&addr);
+        }
+
+        prefix.withdrawal = Some(self.prefix_withdrawal);
+        Some(prefix.clone())
+    }
+
+    /// Pauses or resumes sending RAs. Returns `true` if the state changed.
+    fn set_paused(&self, paused: bool) -> bool {
+        if self.paused.swap(paused, Ordering::Relaxed) == paused {
+            return false;
+        }
+
+        self.record(if paused {
+            Event::Paused
+        } else {
+            Event::Resumed
+        });
+        self.pause_changed.notify_one();
+        true
+    }
+
+    /// Counts a multicast RA for all withdrawn prefixes, dropping those that
+    /// have been advertised as withdrawn often enough.
+    ///
+    /// Returns `true` if any prefix was dropped.
+    fn advance_withdrawals(&self) -> bool {
+        let mut dropped = false;
+        self.prefixes.write().retain(|_, prefix| {
+            let keep = advance_withdrawal(prefix);
+            dropped |= !keep;
+            keep
+        });
+
+        dropped
+    }
+
+    /// Returns the prefixes and DNS servers as a [`Database`].
+    ///
+    /// Withdrawn prefixes are already considered removed and not included.
+    fn to_database(&self) -> Database {
+        let prefixes = self
+            .prefixes
+            .read()
+            .values()
+            .filter(|prefix| prefix.withdrawal.is_none())
+            .map(|prefix| database::Prefix {
+                prefix: prefix.prefix,
+                prefix_length: prefix.prefix_length,
+                preferred: match prefix.preferred_lifetime {
+                    Lifetime::Duration(dur) => crate::database::Lifetime::Duration(dur),
+                    Lifetime::Until(ts) => crate::database::Lifetime::Until(ts),
+                    Lifetime::Infinite => crate::database::Lifetime::Infinite,
+                },
+                valid: match prefix.valid_lifetime {
+                    Lifetime::Duration(dur) => crate::database::Lifetime::Duration(dur),
+                    Lifetime::Until(ts) => crate::database::Lifetime::Until(ts),
+                    Lifetime::Infinite => crate::database::Lifetime::Infinite,
+                },
+                deprecate_before: prefix.deprecate_before,
+                on_link: prefix.on_link,
+                label: prefix.label.clone(),
+                extra_addrs: prefix.extra_addrs.clone(),
+            })
+            .collect();
+
+        let dns_servers = self
+            .dns_servers
+            .read()
+            .iter()
+            .map(|(addr, label)| database::DnsServer {
+                addr: *addr,
+                label: label.clone(),
+            })
+            .collect();
+
+        Database {
+            prefixes,
+            dns_servers,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Solicitation {
+    pub received: Instant,
+    pub addr: Ipv6Addr,
+    pub link_layer_addr: Option<[u8; 6]>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Prefix {
+    pub prefix: Ipv6Addr,
+    pub prefix_length: u8,
+    pub preferred_lifetime: Lifetime,
+    pub valid_lifetime: Lifetime,
+    pub deprecate_before: Option<Duration>,
+    pub on_link: bool,
+    pub label: Option<String>,
+    /// Addresses configured for the prefix in addition to the generated one.
+    pub extra_addrs: Vec<Ipv6Addr>,
+    /// The number of RAs left that advertise the removed prefix as withdrawn.
+    /// `None` if the prefix was not removed.
+    pub withdrawal: Option<u32>,
+    /// When the prefix was added or loaded, see [`ClockAnchor`].
+    pub added: ClockAnchor,
+}
+
+impl Prefix {
+    /// Returns the Prefix Information option advertising the prefix with the
+    /// A flag set to `autonomous`.
+    ///
+    /// See [`advertised_preferred_lifetime`] for `preferred_ratio`.
+    ///
+    /// [`advertised_preferred_lifetime`]: Self::advertised_preferred_lifetime
+    fn information(&self, preferred_ratio: Option<f64>, autonomous: bool) -> PrefixInformation {
+        PrefixInformation {
+            prefix: self.prefix,
+            prefix_length: self.prefix_length,
+            on_link: self.on_link,
+            autonomous,
+            preferred_lifetime: self.advertised_preferred_lifetime(preferred_ratio),
+            valid_lifetime: self.advertised_valid_lifetime(),
+        }
+    }
+
+    /// Returns the preferred lifetime to advertise.
+    ///
+    /// This is zero if the prefix is withdrawn or deprecated because its valid
+    /// lifetime is within `deprecate_before` of expiring. With a
+    /// `preferred_ratio` it is that fraction of the remaining valid lifetime
+    /// instead of the configured preferred lifetime.
+    pub fn advertised_preferred_lifetime(&self, preferred_ratio: Option<f64>) -> Duration {
+        if self.withdrawal.is_some() {
+            return Duration::ZERO;
+        }
+
+        match (self.deprecation(), preferred_ratio) {
+            (Some(dur), _) if dur.is_zero() => Duration::ZERO,
+            (_, Some(ratio)) => {
+                let valid_lifetime = self.valid_remaining();
+                if valid_lifetime == Lifetime::INFINITE {
+                    Lifetime::INFINITE
+                } else {
+                    valid_lifetime.mul_f64(ratio)
+                }
+            }
+            (_, None) => self.added.remaining(self.preferred_lifetime),
+        }
+    }
+
+    /// Returns the valid lifetime to advertise.
+    ///
+    /// Withdrawn prefixes are advertised with at most
+    /// `WITHDRAWN_VALID_LIFETIME`, the shortest valid lifetime hosts accept
+    /// from unauthenticated RAs (RFC 4862 5.5.3(e)).
+    fn advertised_valid_lifetime(&self) -> Duration {
+        let valid_lifetime = self.valid_remaining();
+
+        if self.withdrawal.is_some() {
+            Duration::min(valid_lifetime, WITHDRAWN_VALID_LIFETIME)
+        } else {
+            valid_lifetime
+        }
+    }
+
+    /// Returns the time until the prefix is deprecated, if it will be.
+    fn deprecation(&self) -> Option<Duration> {
+        match (self.valid_lifetime, self.deprecate_before) {
+            (Lifetime::Until(_), Some(deprecate_before)) => {
+                Some(self.valid_remaining().saturating_sub(deprecate_before))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the time until the next change of the prefix, i.e. its
+    /// deprecation or expiry. Returns `None` if the prefix never expires.
+    fn next_event(&self) -> Option<Duration> {
+        match self.deprecation() {
+            Some(dur) if !dur.is_zero() => Some(dur),
+            _ if self.valid_lifetime.is_infinite() => None,
+            _ => Some(self.valid_remaining()),
+        }
+    }
+
+    /// Returns the remaining valid lifetime, see [`ClockAnchor::remaining`].
+    fn valid_remaining(&self) -> Duration {
+        self.added.remaining(self.valid_lifetime)
+    }
+}
+
+/// The monotonic and wall clock time at which a prefix was added.
+///
+/// A [`Lifetime::Until`] is compared with the wall clock only once, when the
+/// prefix is added or loaded from the database, and counts down on the
+/// monotonic clock from then on. Stepping the system clock (e.g. NTP or a VM
+/// resume) therefore neither resurrects expired prefixes nor extends their
+/// lifetimes. The database keeps the absolute time, which is compared with
+/// the wall clock again when it is loaded after a restart.
+#[derive(Copy, Clone, Debug)]
+pub struct ClockAnchor {
+    instant: Instant,
+    system: SystemTime,
+}
+
+impl ClockAnchor {
+    pub fn now() -> Self {
+        Self {
+            instant: Instant::now(),
+            system: SystemTime::now(),
+        }
+    }
+
+    /// Returns the remaining time of `lifetime`.
+    ///
+    /// Like [`Lifetime::duration`], [`Lifetime::Duration`] does not count down.
+    /// A [`Lifetime::Until`] counts down like [`Lifetime::remaining`] from the
+    /// time left at the anchor.
+    pub fn remaining(&self, lifetime: Lifetime) -> Duration {
+        match lifetime {
+            Lifetime::Until(ts) => {
+                let left = ts.duration_since(self.system).unwrap_or(Duration::ZERO);
+                Lifetime::Duration(left).remaining(self.instant)
+            }
+            Lifetime::Duration(_) | Lifetime::Infinite => lifetime.duration(),
+        }
+    }
+}
+
+pub struct IcmpSocket {
     socket: AsyncFd<Socket>,
+    joined_all_routers: bool,
+    recv_buffer_size: usize,
 }
 
 impl IcmpSocket {
-    fn new(addr: SocketAddrV6) -> Result<Self, io::Error> {
+    fn bind(addr: Ipv6Addr, scope_id: u32, opts: SocketOptions) -> Result<Self, io::Error> {
         let socket = Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?;
-        socket.bind(&(addr.into()))?;
+        socket.bind(&(SocketAddrV6::new(addr, 0, 0, scope_id).into()))?;
         socket.set_nonblocking(true)?;
-        socket.join_multicast_v6(&Ipv6Addr::MULTICAST_ALL_ROUTERS, addr.scope_id())?;
-        socket.set_multicast_hops_v6(255)?;
-        socket.set_unicast_hops_v6(255)?;
+        linux::set_recv_pktinfo(&socket)?;
+        linux::set_recv_hoplimit(&socket)?;
+
+        if opts.join_all_routers {
+            socket.join_multicast_v6(&Ipv6Addr::MULTICAST_ALL_ROUTERS, scope_id)?;
+        }
+
+        if opts.set_hop_limits {
+            socket.set_multicast_hops_v6(255)?;
+            socket.set_unicast_hops_v6(255)?;
+        }
 
         Ok(Self {
             socket: AsyncFd::new(socket)?,
+            joined_all_routers: opts.join_all_routers,
+            recv_buffer_size: opts.recv_buffer_size,
         })
     }
 
-    async fn recv_from(&self) -> Result<(IcmpPacket, SocketAddrV6), io::Error> {
+    /// Receives the next packet, returning the packet, the source address, the
+    /// destination address and the hop limit of the packet.
+    async fn recv_from(
+        &self,
+    ) -> Result<(IcmpPacket, SocketAddrV6, Option<Ipv6Addr>, Option<u8>), io::Error> {
         loop {
             let mut guard = self.socket.readable().await?;
 
-            let mut buf = Vec::with_capacity(1500);
-            match guard.try_io(|socket| socket.get_ref().recv_from(buf.spare_capacity_mut())) {
-                Ok(Ok((len, addr))) => {
+            let mut buf = Vec::with_capacity(self.recv_buffer_size);
+            match guard.try_io(|socket| {
+                linux::recv_from_with_dst(socket.get_ref(), buf.spare_capacity_mut())
+            }) {
+                Ok(Ok(linux::Received {
+                    len,
+                    src: addr,
+                    dst,
+                    hop_limit,
+                })) => {
                     unsafe {
                         buf.set_len(len);
                     }
 
-                    let addr = addr.as_socket_ipv6().unwrap();
-                    match IcmpPacket::decode(&buf[..]) {
-                        Ok(packet) => return Ok((packet, addr)),
+                    // This should never happen on an IPv6 socket, but must not
+                    // stop the receive loop if it does.
+                    let Some(addr) = addr else {
+                        tracing::warn!("dropping packet from non-IPv6 address");
+                        continue;
+                    };
+
+                    if len >= self.recv_buffer_size {
+                        tracing::warn!(
+                            "packet from {:?} filled the receive buffer of {} bytes and may be truncated",
+                            addr,
+                            self.recv_buffer_size
+                        );
+                    }
+
+                    match decode_with_offset::<IcmpPacket>(&buf[..]) {
+                        Ok(packet) => return Ok((packet, addr, dst, hop_limit)),
                         Err(err) => {
-                            tracing::debug!("failed to decode packet from {:?}: {:?}", addr, err);
+                            tracing::debug!("failed to decode packet from {:?}: {}", addr, err);
                         }
                     }
                 }
@@ -476,7 +1564,8 @@ impl IcmpSocket {
         }
     }
 
-    async fn send_to(&self, packet: &IcmpPacket, addr: SocketAddrV6) -> Result<(), io::Error> {
+    /// Sends `packet` to `addr`, returning the size of the packet in bytes.
+    async fn send_to(&self, packet: &IcmpPacket, addr: SocketAddrV6) -> Result<usize, io::Error> {
         let mut buf = Vec::new();
         packet.encode(&mut buf);
 
@@ -484,7 +1573,7 @@ impl IcmpSocket {
             let mut guard = self.socket.writable().await?;
 
             match guard.try_io(|socket| socket.get_ref().send_to(&buf, &(addr.into()))) {
-                Ok(Ok(_)) => return Ok(()),
+                Ok(Ok(_)) => return Ok(buf.len()),
                 Ok(Err(err)) => return Err(err),
                 Err(_) => continue,
             }
@@ -492,19 +1581,212 @@ impl IcmpSocket {
     }
 
     async fn close(&self) -> Result<(), io::Error> {
+        if !self.joined_all_routers {
+            return Ok(());
+        }
+
         let socket = self.socket.get_ref();
 
-        let addr = socket.local_addr()?.as_socket_ipv6().unwrap();
+        let Some(addr) = socket.local_addr()?.as_socket_ipv6() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "socket is not bound to an IPv6 address",
+            ));
+        };
         socket.leave_multicast_v6(&Ipv6Addr::MULTICAST_ALL_ROUTERS, addr.scope_id())?;
 
         Ok(())
     }
 }
 
+/// Options for binding an [`IcmpSocket`].
+#[derive(Copy, Clone, Debug)]
+pub struct SocketOptions {
+    /// Join the all-routers multicast group to receive RSs.
+    pub join_all_routers: bool,
+    /// Set the unicast and multicast hop limits to 255 as required for NDP.
+    pub set_hop_limits: bool,
+    /// The size of the buffer for received packets in bytes.
+    pub recv_buffer_size: usize,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            join_all_routers: true,
+            set_hop_limits: true,
+            recv_buffer_size: 1500,
+        }
+    }
+}
+
+/// Returns the size of the receive buffer, which is the `configured` size or
+/// the `mtu` of the interface.
+fn recv_buffer_size(configured: Option<usize>, mtu: Option<u32>) -> usize {
+    let size = configured
+        .or(mtu.map(|mtu| mtu as usize))
+        .unwrap_or(SocketOptions::default().recv_buffer_size);
+
+    size.clamp(MIN_RECV_BUFFER_SIZE, MAX_RECV_BUFFER_SIZE)
+}
+
+fn init_logger() {
+    // `RUST_LOG` takes the usual filter directives, e.g.
+    // `RUST_LOG=rsadv_server=debug`. Unlike with pretty_env_logger, which
+    // only logged errors by default, INFO is logged if it is unset.
+    let directives = std::env::var("RUST_LOG").unwrap_or_else(|_| String::from("info"));
+    let filter = EnvFilter::try_new(&directives).unwrap_or_else(|err| {
+        eprintln!(
+            "invalid RUST_LOG {:?}: {}; logging at INFO",
+            directives, err
+        );
+        EnvFilter::new("info")
+    });
+
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .init();
+
+    // Cycle the filter on SIGUSR1: RUST_LOG -> debug -> trace -> RUST_LOG.
+    tokio::task::spawn(async move {
+        let mut sigusr1 = match signal(SignalKind::user_defined1()) {
+            Ok(sig) => sig,
+            Err(err) => {
+                tracing::error!("failed to install SIGUSR1 handler: {}", err);
+                return;
+            }
+        };
+
+        let levels = [None, Some(LevelFilter::DEBUG), Some(LevelFilter::TRACE)];
+        let mut index = 0;
+        while sigusr1.recv().await.is_some() {
+            index = (index + 1) % levels.len();
+            let filter = match levels[index] {
+                Some(level) => EnvFilter::default().add_directive(level.into()),
+                None => EnvFilter::try_new(&directives).unwrap_or_else(|_| EnvFilter::new("info")),
+            };
+
+            let new_filter = filter.to_string();
+            match handle.reload(filter) {
+                Ok(()) => tracing::info!("log filter changed to {}", new_filter),
+                Err(err) => tracing::error!("failed to change log filter: {}", err),
+            }
+        }
+    });
+}
+
 fn is_link_local(addr: &Ipv6Addr) -> bool {
     addr.octets().starts_with(&[0xfe, 0x80])
 }
 
+/// Builds the RA advertising the prefixes and DNS servers in `state`.
+///
+/// Prefixes and DNS servers are ordered by address, so the same state always
+/// results in the same RA.
+fn build_advertisement(
+    state: &State,
+    mac: [u8; 6],
+    dns_lifetime: Duration,
+    router_lifetime: Duration,
+) -> RouterAdvertisement {
+    let mut options = Vec::new();
+
+    // A MTU of 0 means the MTU is not advertised.
+    if state.mtu != 0 {
+        options.push(IcmpOption::Mtu(state.mtu));
+    }
+
+    if state.include_source_lladdr {
+        options.push(IcmpOption::SourceLinkLayerAddress(LinkLayerAddress(mac)));
+    }
+
+    {
+        let dns = state.dns_servers.read();
+
+        if !dns.is_empty() {
+            let mut addrs: Vec<_> = dns.keys().copied().collect();
+            addrs.sort();
+
+            options.push(IcmpOption::RecursiveDnsServer(RecursiveDnsServer {
+                addrs,
+                lifetime: dns_lifetime,
+            }));
+        }
+    }
+
+    let prefixes = state.prefixes.read();
+    let mut prefixes: Vec<_> = prefixes.values().collect();
+    prefixes.sort_by_key(|prefix| prefix.prefix);
+
+    for prefix in prefixes {
+        // We only announce prefixes that are still valid.
+        // Expired prefixes are removed by another task, but it is possible
+        // for a prefix to just have gone invalid and we are running before
+        // the other task has removed it.
+        if prefix.valid_remaining().is_zero() {
+            continue;
+        }
+
+        options.push(IcmpOption::PrefixInformation(
+            prefix.information(state.preferred_ratio, state.autonomous),
+        ));
+    }
+
+    if let Some(fqdn) = &state.pvd_id {
+        options.push(IcmpOption::ProvisioningDomain {
+            fqdn: fqdn.clone(),
+            sequence: 0,
+            http: false,
+            legacy: false,
+            delay: 0,
+            header: None,
+            options: Vec::new(),
+        });
+    }
+
+    RouterAdvertisement {
+        cur_hop_limit: state.cur_hop_limit,
+        managed: false,
+        other: state.other_config,
+        router_lifetime,
+        reachable_timer: None,
+        retrans_timer: None,
+        options,
+    }
+}
+
+/// Selects the link-local address RAs are sent from.
+///
+/// The address derived from `mac` is preferred since it is stable across
+/// restarts. Otherwise the lowest link-local address is chosen.
+fn select_link_local(addrs: &[Ipv6Addr], mac: [u8; 6]) -> Option<Ipv6Addr> {
+    let eui64 = generate_addr(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 64, mac);
+
+    match addrs.iter().find(|addr| Some(**addr) == eui64) {
+        Some(addr) => Some(*addr),
+        None => addrs.iter().copied().filter(is_link_local).min(),
+    }
+}
+
+fn router_advert_is_valid(src: Ipv6Addr, hop_limit: Option<u8>, packet: &IcmpPacket) -> bool {
+    // https://www.rfc-editor.org/rfc/rfc4861#section-6.1.2
+    // Requirements for valid RA:
+    // - IP src is link-local
+    // - IP hop limit is set to 255
+    // - ICMP checksum is valid
+    // - ICMP code is 0
+    // - ICMP length is >= 16
+    // - All included options have length > 0
+
+    if !is_link_local(&src) || hop_limit != Some(255) || packet.code != 0 {
+        return false;
+    }
+
+    matches!(packet.content, IcmpContent::RouterAdvertisement(_))
+}
+
 fn router_solicit_is_valid(src: Ipv6Addr, packet: &IcmpPacket) -> bool {
     // https://www.rfc-editor.org/rfc/rfc4861#section-7.1.1
     // Requirements for valid RS:
@@ -541,18 +1823,314 @@ impl Ipv6AddrExt for Ipv6Addr {
     const MULTICAST_ALL_ROUTERS: Self = Self::new(0xff02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02);
 }
 
-fn generate_addr(prefix: Ipv6Addr, mac: [u8; 6]) -> Ipv6Addr {
-    let prefix = &prefix.octets()[0..8];
+/// Returns the interface index if `interface` is given as `if<N>` or `<N>`
+/// instead of a name.
+fn parse_interface_index(interface: &str) -> Option<u32> {
+    interface
+        .strip_prefix("if")
+        .unwrap_or(interface)
+        .parse()
+        .ok()
+}
+
+/// Returns why hosts cannot use `addr` as a DNS server, or `None` if it is
+/// usable.
+///
+/// Link-local addresses are rejected because the RDNSS option carries no
+/// scope, so hosts with more than one interface cannot reach them.
+fn unusable_dns_server(addr: Ipv6Addr) -> Option<&'static str> {
+    if addr.is_unspecified() {
+        Some("unspecified")
+    } else if addr.is_loopback() {
+        Some("a loopback address")
+    } else if addr.is_multicast() {
+        Some("a multicast address")
+    } else if addr.segments()[0] & 0xffc0 == 0xfe80 {
+        Some("link-local")
+    } else {
+        None
+    }
+}
+
+/// Counts a multicast RA advertising the withdrawn `prefix`.
+///
+/// Returns `false` if the prefix has been advertised as withdrawn often
+/// enough and should be dropped.
+fn advance_withdrawal(prefix: &mut Prefix) -> bool {
+    match &mut prefix.withdrawal {
+        None => true,
+        Some(0 | 1) => false,
+        Some(remaining) => {
+            *remaining -= 1;
+            true
+        }
+    }
+}
+
+/// Configures an address from `prefix` on the interface.
+///
+/// If `existing` is given only the lifetimes of the already configured address
+/// are updated and it is returned even if that fails. Otherwise the EUI-64 address is configured and checked for
+/// duplicates. If DAD fails, up to `dad_retries` addresses with an interface
+/// identifier from `rng` are tried instead.
+///
+/// If the EUI-64 address is already in `interface_addrs`, another process
+/// configured it. It is skipped with `respect_foreign_addresses`, otherwise
+/// its lifetimes are overwritten.
+///
+/// Returns the configured address.
+async fn configure_addr(
+    interface: &Interface,
+    prefix: &Prefix,
+    mac: [u8; 6],
+    existing: Option<Ipv6Addr>,
+    interface_addrs: &[Ipv6Addr],
+    config: &Config,
+    rng: &mut SmallRng,
+) -> Option<Ipv6Addr> {
+    let preferred_ratio = config.preferred_ratio;
+
+    // Hosts must not assume that off-link prefixes are reachable on
+    // this link, so neither do we.
+    if !prefix.on_link {
+        return None;
+    }
+
+    // The address keeps working with its previous lifetimes if refreshing
+    // them fails, so it must not be removed as no longer configured.
+    if let Some(addr) = existing {
+        add_prefix_addr(interface, prefix, addr, preferred_ratio).await;
+        return Some(addr);
+    }
+
+    let Some(mut addr) = generate_addr(prefix.prefix, prefix.prefix_length, mac) else {
+        tracing::warn!(
+            "not configuring address for {}/{}: prefix is longer than /64",
+            prefix.prefix,
+            prefix.prefix_length,
+        );
+        return None;
+    };
+
+    if is_foreign_addr(addr, existing, interface_addrs) {
+        if config.respect_foreign_addresses {
+            tracing::warn!(
+                "{} is already configured on {} by another process; not configuring it",
+                addr,
+                interface.name()
+            );
+            return None;
+        }
+
+        tracing::warn!(
+            "{} is already configured on {} by another process; overwriting its lifetimes",
+            addr,
+            interface.name()
+        );
+    }
+
+    let mut retries = config.dad_retries;
+    loop {
+        if !add_prefix_addr(interface, prefix, addr, preferred_ratio).await {
+            return None;
+        }
+
+        let state = match wait_for_dad(move || interface.dad_state(addr), DAD_TIMEOUT).await {
+            Ok(state) => state,
+            Err(err) => {
+                tracing::error!("failed to query DAD state of {}: {:?}", addr, err);
+                return Some(addr);
+            }
+        };
+
+        match dad_outcome(state, &mut retries) {
+            DadOutcome::Keep => {
+                if state == DadState::Tentative {
+                    tracing::warn!(
+                        "duplicate address detection for {} is still in progress",
+                        addr
+                    );
+                }
+
+                return Some(addr);
+            }
+            outcome => {
+                tracing::warn!("duplicate address detection failed for {}", addr);
+
+                if let Err(err) = interface.del_addr(IpAddr::V6(addr)).await {
+                    tracing::error!("failed to remove addr from interface: {:?}", err);
+                }
+
+                if outcome == DadOutcome::GiveUp {
+                    return None;
+                }
+
+                addr = addr_with_iid(prefix.prefix, prefix.prefix_length, rng.gen());
+                tracing::info!("retrying with random address {}", addr);
+            }
+        }
+    }
+}
+
+/// What to do with an address after waiting for DAD.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DadOutcome {
+    /// Keep the address.
+    Keep,
+    /// Remove the address and try another one.
+    Retry,
+    /// Remove the address without replacement.
+    GiveUp,
+}
+
+/// Returns what to do with an address in DAD `state`, consuming one of the
+/// `retries` if another address should be tried.
+///
+/// Addresses still tentative are kept, the kernel finishes DAD on its own.
+fn dad_outcome(state: DadState, retries: &mut u32) -> DadOutcome {
+    match state {
+        DadState::Done | DadState::Tentative => DadOutcome::Keep,
+        DadState::Failed if *retries == 0 => DadOutcome::GiveUp,
+        DadState::Failed => {
+            *retries -= 1;
+            DadOutcome::Retry
+        }
+    }
+}
+
+async fn add_prefix_addr(
+    interface: &Interface,
+    prefix: &Prefix,
+    addr: Ipv6Addr,
+    preferred_ratio: Option<f64>,
+) -> bool {
+    if let Err(err) = interface
+        .add_addr(
+            IpAddr::V6(addr),
+            prefix.prefix_length,
+            Some(prefix.advertised_preferred_lifetime(preferred_ratio)),
+            Some(prefix.valid_remaining()),
+        )
+        .await
+    {
+        tracing::error!("failed to add addr to interface: {:?}", err);
+        return false;
+    }
+
+    true
+}
+
+/// Configures the extra addresses of `prefix` on the interface.
+///
+/// At most `max_addresses_per_prefix` addresses are configured for the prefix,
+/// including the `generated` address. `ours` are the extra addresses we
+/// configured previously, they are kept even if refreshing them fails.
+/// Returns the configured extra addresses.
+async fn configure_extra_addrs(
+    interface: &Interface,
+    prefix: &Prefix,
+    generated: Option<Ipv6Addr>,
+    ours: &[Ipv6Addr],
+    interface_addrs: &[Ipv6Addr],
+    config: &Config,
+) -> Vec<Ipv6Addr> {
+    if !prefix.on_link || prefix.extra_addrs.is_empty() {
+        return Vec::new();
+    }
+
+    // Prefixes loaded from the database may predate a lower maximum.
+    let max = config.max_addresses_per_prefix.saturating_sub(1) as usize;
+    if prefix.extra_addrs.len() > max {
+        tracing::warn!(
+            "not configuring {} extra addresses for {}/{}: at most {} addresses are allowed",
+            prefix.extra_addrs.len() - max,
+            prefix.prefix,
+            prefix.prefix_length,
+            config.max_addresses_per_prefix,
+        );
+    }
+
+    let mut addrs = Vec::new();
+    for &addr in prefix.extra_addrs.iter().take(max) {
+        if generated == Some(addr) {
+            continue;
+        }
+
+        if !ours.contains(&addr) && interface_addrs.contains(&addr) {
+            if config.respect_foreign_addresses {
+                tracing::warn!(
+                    "{} is already configured on {} by another process; not configuring it",
+                    addr,
+                    interface.name()
+                );
+                continue;
+            }
+
+            tracing::warn!(
+                "{} is already configured on {} by another process; overwriting its lifetimes",
+                addr,
+                interface.name()
+            );
+        }
+
+        // Like in `configure_addr`, an address we configured previously keeps
+        // working if refreshing its lifetimes fails.
+        if add_prefix_addr(interface, prefix, addr, config.preferred_ratio).await
+            || ours.contains(&addr)
+        {
+            addrs.push(addr);
+        }
+    }
+
+    addrs
+}
+
+/// Returns `true` if `addr` is configured on the interface, but is not `ours`,
+/// the address we configured for the prefix.
+fn is_foreign_addr(addr: Ipv6Addr, ours: Option<Ipv6Addr>, interface_addrs: &[Ipv6Addr]) -> bool {
+    ours != Some(addr) && interface_addrs.contains(&addr)
+}
+
+/// How long to wait for the kernel to complete DAD for a new address.
+///
+/// DAD takes RetransTimer (1s by default) per transmitted solicitation.
+const DAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Waits until the kernel completed DAD for an address, polling its state
+/// with `dad_state`.
+///
+/// Returns [`DadState::Tentative`] if DAD did not complete within `timeout`
+/// and [`DadState::Failed`] if the address disappeared.
+async fn wait_for_dad<F, Fut>(mut dad_state: F, timeout: Duration) -> Result<DadState, linux::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Option<DadState>, linux::Error>>,
+{
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match dad_state().await? {
+            Some(DadState::Tentative) if Instant::now() < deadline => {
+                tokio::time::sleep(POLL_INTERVAL).await
+            }
+            Some(state) => return Ok(state),
+            None => return Ok(DadState::Failed),
+        }
+    }
+}
+
+/// Generates the address for `prefix` using the EUI-64 interface identifier
+/// derived from `mac`.
+///
+/// Returns `None` if the host portion of the prefix is too small to fit the
+/// 64-bit interface identifier (i.e. `prefix_length > 64`).
+fn generate_addr(prefix: Ipv6Addr, prefix_length: u8, mac: [u8; 6]) -> Option<Ipv6Addr> {
+    if prefix_length > 64 {
+        return None;
+    }
 
-    Ipv6Addr::from([
-        prefix[0],
-        prefix[1],
-        prefix[2],
-        prefix[3],
-        prefix[4],
-        prefix[5],
-        prefix[6],
-        prefix[7],
+    let iid = u64::from_be_bytes([
         mac[0] ^ 2,
         mac[1],
         mac[2],
@@ -561,18 +2139,1680 @@ fn generate_addr(prefix: Ipv6Addr, mac: [u8; 6]) -> Ipv6Addr {
         mac[3],
         mac[4],
         mac[5],
-    ])
-}
+    ]);
 
-const MAX_INITIAL_RTR_ADVERT_INTERVAL: Duration = Duration::from_secs(16);
-const MAX_INITIAL_RTR_ADVERTISEMENTS: u8 = 3;
+    Some(addr_with_iid(prefix, prefix_length, iid))
+}
 
-const MAX_FINAL_RTR_ADVERTISEMENTS: u8 = 3;
-const MIN_DELAY_BETWEEN_RAS: Duration = Duration::from_secs(3);
-const MAX_RA_DELAY_TIME: Duration = Duration::from_millis(500);
+/// Returns the address in `prefix` with the interface identifier `iid`.
+///
+/// `prefix_length` must not be longer than 64.
+fn addr_with_iid(prefix: Ipv6Addr, prefix_length: u8, iid: u64) -> Ipv6Addr {
+    // Only keep the network bits of the prefix, the host portion
+    // is filled with the interface identifier.
+    let mask = u128::MAX
+        .checked_shl(128 - u32::from(prefix_length))
+        .unwrap_or(0);
+    let prefix = u128::from(prefix) & mask;
+
+    Ipv6Addr::from(prefix | u128::from(iid))
+}
 
+/// The schedule of unsolicited multicast RAs.
 #[derive(Copy, Clone, Debug)]
-enum Command {
-    SendRouterAdvertisement(SocketAddrV6),
-    NewConfig,
+struct MulticastSchedule {
+    /// When the last multicast RA was sent.
+    last: Instant,
+    /// When the next multicast RA is sent.
+    next: Instant,
+}
+
+impl MulticastSchedule {
+    /// Creates a schedule that sends the first multicast RA at `now`.
+    fn new(now: Instant) -> Self {
+        Self {
+            last: now,
+            next: now,
+        }
+    }
+
+    /// Schedules the response to a RS received at `now`, delayed by `delay`.
+    ///
+    /// Returns when to send a unicast response, or `None` if the host is
+    /// answered by the next multicast RA. If `multicast` is `true` the next
+    /// multicast RA is moved forward to answer the RS instead.
+    fn solicit(&mut self, now: Instant, delay: Duration, multicast: bool) -> Option<Instant> {
+        let ts = now + delay;
+
+        if multicast {
+            // Multicast RAs MUST be sent no faster than `MIN_DELAY_BETWEEN_RAS`.
+            let ts = Instant::max(ts, self.last + MIN_DELAY_BETWEEN_RAS);
+            self.next = Instant::min(self.next, ts);
+            return None;
+        }
+
+        // The next multicast RA reaches the host before the delayed response
+        // would, so there is no need to send both.
+        if ts >= self.next {
+            None
+        } else {
+            Some(ts)
+        }
+    }
+
+    /// Records a multicast RA sent at `now`, scheduling the next one after `interval`.
+    fn sent(&mut self, now: Instant, interval: Duration) {
+        self.last = now;
+        self.next = now + interval;
+    }
+}
+
+/// The upcoming events (deprecation or expiry) of all prefixes, ordered by
+/// time.
+#[derive(Clone, Debug, Default)]
+struct ExpiryQueue {
+    events: BinaryHeap<Reverse<(Instant, Ipv6Addr)>>,
+}
+
+impl ExpiryQueue {
+    /// Replaces all events with the next event of each of `prefixes`.
+    fn rebuild<'a, I>(&mut self, now: Instant, prefixes: I)
+    where
+        I: IntoIterator<Item = &'a Prefix>,
+    {
+        self.events.clear();
+        for prefix in prefixes {
+            self.schedule(now, prefix);
+        }
+    }
+
+    /// Schedules the next event of `prefix`. Prefixes with an infinite
+    /// lifetime have no events.
+    fn schedule(&mut self, now: Instant, prefix: &Prefix) {
+        if let Some(next_event) = prefix.next_event() {
+            self.events.push(Reverse((now + next_event, prefix.prefix)));
+        }
+    }
+
+    /// Returns when the next event is due.
+    fn next(&self) -> Option<Instant> {
+        self.events.peek().map(|Reverse((at, _))| *at)
+    }
+
+    /// Removes all events due at `now` and returns their prefixes, earliest
+    /// first.
+    fn pop_due(&mut self, now: Instant) -> Vec<Ipv6Addr> {
+        let mut due = Vec::new();
+        while let Some(&Reverse((at, prefix))) = self.events.peek() {
+            if at > now {
+                break;
+            }
+
+            self.events.pop();
+            due.push(prefix);
+        }
+
+        due
+    }
+}
+
+/// Tracks the first `MAX_INITIAL_RTR_ADVERTISEMENTS` RAs, which are sent with
+/// an interval of at most `MAX_INITIAL_RTR_ADVERT_INTERVAL`.
+#[derive(Clone, Debug, Default)]
+struct InitialAdvertisements {
+    sent: u8,
+    last_options: Vec<IcmpOption>,
+}
+
+impl InitialAdvertisements {
+    /// Restarts the initial advertisements.
+    fn reset(&mut self) {
+        self.sent = 0;
+    }
+
+    /// Records the `options` of the next RA.
+    ///
+    /// We MAY repeat the initial advertisements if the advertised information
+    /// changes, so this restarts them if `options` differ from the last RA.
+    fn update(&mut self, options: &[IcmpOption]) {
+        if !same_information(&self.last_options, options) {
+            self.reset();
+            self.last_options = options.to_vec();
+        }
+    }
+
+    /// Clamps the `interval` until the next RA if it is one of the initial RAs.
+    fn clamp(&mut self, interval: Duration) -> Duration {
+        if self.sent < MAX_INITIAL_RTR_ADVERTISEMENTS {
+            self.sent += 1;
+            Duration::min(interval, MAX_INITIAL_RTR_ADVERT_INTERVAL)
+        } else {
+            interval
+        }
+    }
+}
+
+/// Tracks the RAs after a config change, which are sent with an interval of
+/// `MIN_DELAY_BETWEEN_RAS` to quickly propagate the change.
+#[derive(Copy, Clone, Debug)]
+struct FastAdvertisements {
+    count: u32,
+    remaining: u32,
+}
+
+impl FastAdvertisements {
+    /// Creates a tracker that sends `count` fast RAs after every change.
+    fn new(count: u32) -> Self {
+        Self {
+            count,
+            remaining: 0,
+        }
+    }
+
+    /// Starts the fast RAs after a config change.
+    fn reset(&mut self) {
+        self.remaining = self.count;
+    }
+
+    /// Clamps the `interval` until the next RA if it is one of the fast RAs.
+    fn clamp(&mut self, interval: Duration) -> Duration {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            Duration::min(interval, MIN_DELAY_BETWEEN_RAS)
+        } else {
+            interval
+        }
+    }
+}
+
+/// Returns `true` if both option sets advertise the same information.
+fn same_information(lhs: &[IcmpOption], rhs: &[IcmpOption]) -> bool {
+    lhs.len() == rhs.len()
+        && lhs.iter().zip(rhs).all(|(lhs, rhs)| match (lhs, rhs) {
+            // The lifetimes of prefixes with a fixed point in time decrease
+            // with every RA, this is not a change of the advertised information.
+            (IcmpOption::PrefixInformation(lhs), IcmpOption::PrefixInformation(rhs)) => {
+                lhs.prefix == rhs.prefix
+                    && lhs.prefix_length == rhs.prefix_length
+                    && lhs.on_link == rhs.on_link
+                    && lhs.autonomous == rhs.autonomous
+            }
+            (lhs, rhs) => lhs == rhs,
+        })
+}
+
+const MAX_INITIAL_RTR_ADVERT_INTERVAL: Duration = Duration::from_secs(16);
+const MAX_INITIAL_RTR_ADVERTISEMENTS: u8 = 3;
+
+const MAX_FINAL_RTR_ADVERTISEMENTS: u8 = 3;
+const MIN_DELAY_BETWEEN_RAS: Duration = Duration::from_secs(3);
+const MAX_RA_DELAY_TIME: Duration = Duration::from_millis(500);
+
+/// The minimum MTU of IPv6 links.
+const MIN_RECV_BUFFER_SIZE: usize = 1280;
+/// The maximum size of an IPv6 packet without jumbograms.
+const MAX_RECV_BUFFER_SIZE: usize = 65535;
+
+const MAX_RECENT_SOLICITATIONS: usize = 64;
+const MAX_RECENT_EVENTS: usize = 256;
+
+const WITHDRAWN_VALID_LIFETIME: Duration = Duration::from_secs(2 * 60 * 60);
+
+#[derive(Copy, Clone, Debug)]
+enum Command {
+    /// Respond to a RS from the address. The second field is the number of
+    /// transmissions left, including this one.
+    SendRouterAdvertisement(SocketAddrV6, u32),
+    NewConfig,
+}
+
+/// Queues RSs for the RA scheduler without blocking the receiver.
+///
+/// RSs are dropped if the scheduler falls behind and the queue is full. A RS
+/// from a source that is already queued is coalesced into the queued one.
+#[derive(Debug)]
+struct SolicitationQueue {
+    tx: mpsc::Sender<Command>,
+    /// The sources of all queued RSs.
+    queued: parking_lot::Mutex<HashSet<SocketAddrV6>>,
+    /// The number of RSs dropped because the queue was full.
+    dropped: AtomicU64,
+    /// Set once shutdown began. RSs are ignored from then on.
+    closed: AtomicBool,
+}
+
+impl SolicitationQueue {
+    fn new(tx: mpsc::Sender<Command>) -> Self {
+        Self {
+            tx,
+            queued: Default::default(),
+            dropped: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Queues a response to a RS from `addr` with `transmissions` transmissions.
+    ///
+    /// Returns `false` if the RS was dropped or the queue is closed.
+    fn push(&self, addr: SocketAddrV6, transmissions: u32) -> bool {
+        if self.closed.load(Ordering::Acquire) {
+            return false;
+        }
+
+        let mut queued = self.queued.lock();
+        if queued.contains(&addr) {
+            return true;
+        }
+
+        match self
+            .tx
+            .try_send(Command::SendRouterAdvertisement(addr, transmissions))
+        {
+            Ok(()) => {
+                queued.insert(addr);
+                true
+            }
+            Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+
+                // Only log every power of two to not flood the log while overloaded.
+                if dropped.is_power_of_two() {
+                    tracing::warn!("RA scheduler overloaded; dropped {} RSs", dropped);
+                }
+
+                false
+            }
+        }
+    }
+
+    /// Marks the RS from `addr` as taken from the queue.
+    fn pop(&self, addr: &SocketAddrV6) {
+        self.queued.lock().remove(addr);
+    }
+
+    /// Stops queueing RSs, so that no solicited RAs interleave with the final
+    /// RAs sent on shutdown.
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+    }
+
+    /// Returns the number of dropped RSs.
+    fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// The failures that make the daemon unhealthy, reported by [`Request::Ping`].
+///
+/// Failures of the RA scheduler and the RS listener are permanent, while the
+/// last send result and the link state recover on their own.
+///
+/// [`Request::Ping`]: rsadv_control::Request::Ping
+#[derive(Debug)]
+struct Health {
+    /// Set if the RA scheduler failed.
+    scheduler_failed: AtomicBool,
+    /// Set if receiving RSs failed.
+    rs_listener_failed: AtomicBool,
+    /// Whether the last RA was sent.
+    last_send_ok: AtomicBool,
+    /// Whether the interface is up, updated by [`link_loop`].
+    link_up: AtomicBool,
+}
+
+impl Health {
+    fn new() -> Self {
+        Self {
+            scheduler_failed: AtomicBool::new(false),
+            rs_listener_failed: AtomicBool::new(false),
+            last_send_ok: AtomicBool::new(true),
+            link_up: AtomicBool::new(true),
+        }
+    }
+
+    /// Returns `true` if none of the failures is present.
+    fn healthy(&self) -> bool {
+        !self.scheduler_failed.load(Ordering::Relaxed)
+            && !self.rs_listener_failed.load(Ordering::Relaxed)
+            && self.last_send_ok.load(Ordering::Relaxed)
+            && self.link_up.load(Ordering::Relaxed)
+    }
+}
+
+/// How often [`link_loop`] checks whether the interface is up.
+const LINK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Checks every [`LINK_CHECK_INTERVAL`] whether `interface` is up.
+async fn link_loop(state: Arc<State>, interface: Interface) {
+    let mut interval = tokio::time::interval(LINK_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let up = match interface.is_up().await {
+            Ok(up) => up,
+            Err(err) => {
+                tracing::error!(
+                    "failed to get link state of {}: {:?}",
+                    interface.name(),
+                    err
+                );
+                false
+            }
+        };
+
+        if state.health.link_up.swap(up, Ordering::Relaxed) == up {
+            continue;
+        }
+
+        if up {
+            tracing::info!("interface {} is up again", interface.name());
+        } else {
+            tracing::warn!("interface {} is down", interface.name());
+        }
+    }
+}
+
+/// Checks every `interval` whether `upstream` has a default route and
+/// readvertises if that changed.
+async fn upstream_loop(state: Arc<State>, upstream: Interface, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        let up = match upstream.has_default_route().await {
+            Ok(up) => up,
+            Err(err) => {
+                tracing::error!(
+                    "failed to get routes of upstream {}: {:?}",
+                    upstream.name(),
+                    err
+                );
+                continue;
+            }
+        };
+
+        if state.upstream.swap(up, Ordering::Relaxed) == up {
+            continue;
+        }
+
+        if up {
+            tracing::info!(
+                "upstream {} has a default route again; advertising as default router",
+                upstream.name()
+            );
+        } else {
+            tracing::warn!(
+                "upstream {} has no default route; advertising a router lifetime of 0",
+                upstream.name()
+            );
+        }
+        state.config_changed.notify_one();
+    }
+}
+
+/// The maximum time [`settle`] waits, in multiples of its window.
+const MAX_SETTLE_WINDOWS: u32 = 4;
+
+/// Waits until `changed` was not notified for `window`, so that a burst of
+/// changes is handled at once.
+///
+/// Continuous changes are handled after at most [`MAX_SETTLE_WINDOWS`]
+/// windows.
+async fn settle(changed: &Notify, window: Duration) {
+    let deadline = tokio::time::Instant::now() + window * MAX_SETTLE_WINDOWS;
+
+    loop {
+        let wake = std::cmp::min(tokio::time::Instant::now() + window, deadline);
+
+        futures::select_biased! {
+            _ = changed.notified().fuse() => (),
+            _ = tokio::time::sleep_until(wake).fuse() => return,
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            return;
+        }
+    }
+}
+
+/// Calls `save` after `changed` is notified until `shutdown` completes.
+///
+/// `save` is called at most once per `interval`, changes within it are saved
+/// together. Pending changes are saved on shutdown.
+async fn save_loop<F, S>(changed: &Notify, interval: Duration, shutdown: F, mut save: S)
+where
+    F: Future,
+    S: FnMut(),
+{
+    pin_mut!(shutdown);
+    loop {
+        futures::select_biased! {
+            _ = shutdown.as_mut().fuse() => {
+                // A change may be pending without having woken us up yet.
+                if changed.notified().now_or_never().is_some() {
+                    save();
+                }
+
+                return;
+            }
+            _ = changed.notified().fuse() => (),
+        }
+
+        futures::select_biased! {
+            _ = shutdown.as_mut().fuse() => {
+                save();
+                return;
+            }
+            _ = tokio::time::sleep(interval).fuse() => (),
+        }
+
+        // Changes during the interval are part of this save.
+        let _ = changed.notified().now_or_never();
+        save();
+    }
+}
+
+/// Returns a random number generator seeded with `seed`, or from the OS if
+/// `seed` is `None`.
+fn new_rng(seed: Option<u64>) -> SmallRng {
+    match seed {
+        Some(seed) => SmallRng::seed_from_u64(seed),
+        None => SmallRng::from_entropy(),
+    }
+}
+
+/// Returns the random delay before a RA in response to a RS, which is less
+/// than `max_delay`.
+fn solicited_delay<R>(rng: &mut R, max_delay: Duration) -> Duration
+where
+    R: Rng,
+{
+    rng.gen_range(Duration::ZERO..max_delay)
+}
+
+/// Returns the time until the next transmission of a solicited RA to `addr`
+/// and the number of transmissions left then, or `None` if this is the last.
+///
+/// Unicast retransmissions are spaced by `max_ra_delay`, the longest random
+/// delay of a solicited RA, so that they are never sent before the previous
+/// transmission.
+fn next_retransmission(
+    addr: &SocketAddrV6,
+    remaining: u32,
+    max_ra_delay: Duration,
+) -> Option<(Duration, u32)> {
+    if remaining <= 1 {
+        return None;
+    }
+
+    // RSs from the unspecified address are answered with multicast RAs, which
+    // MUST NOT be sent faster than `MIN_DELAY_BETWEEN_RAS`.
+    let interval = if addr.ip().is_unspecified() {
+        MIN_DELAY_BETWEEN_RAS
+    } else {
+        max_ra_delay
+    };
+
+    Some((interval, remaining - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv6Addr, SocketAddrV6};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant, SystemTime};
+
+    use futures::FutureExt;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use rsadv_control::{Event, Lifetime};
+    use tokio::sync::{oneshot, Notify};
+
+    use super::{
+        advance_withdrawal, build_advertisement, dad_outcome, generate_addr, is_foreign_addr,
+        new_rng, next_retransmission, parse_interface_index, recv_buffer_size,
+        router_advert_is_valid, save_loop, select_link_local, settle, solicited_delay,
+        unusable_dns_server, wait_for_dad, ClockAnchor, Command, DadOutcome, ExpiryQueue,
+        FastAdvertisements, Health, InitialAdvertisements, MulticastSchedule, Prefix,
+        SolicitationQueue, State, MAX_INITIAL_RTR_ADVERTISEMENTS, MAX_INITIAL_RTR_ADVERT_INTERVAL,
+        MAX_RA_DELAY_TIME, MAX_RECV_BUFFER_SIZE, MAX_SETTLE_WINDOWS, MIN_DELAY_BETWEEN_RAS,
+        MIN_RECV_BUFFER_SIZE, WITHDRAWN_VALID_LIFETIME,
+    };
+    use crate::config::Config;
+    use crate::linux::{netlink_lifetime, DadState};
+    use crate::ndp::{
+        Decode, Encode, IcmpContent, IcmpOption, IcmpPacket, IcmpType, RouterAdvertisement,
+    };
+
+    const MAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    #[test]
+    fn generate_addr_48() {
+        let prefix = "2001:db8:1::".parse().unwrap();
+        let addr = generate_addr(prefix, 48, MAC).unwrap();
+        assert_eq!(
+            addr,
+            "2001:db8:1:0:211:22ff:fe33:4455"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn generate_addr_56() {
+        // Subnet bits past the prefix length are masked off.
+        let prefix = "2001:db8:1:ab12::".parse().unwrap();
+        let addr = generate_addr(prefix, 56, MAC).unwrap();
+        assert_eq!(
+            addr,
+            "2001:db8:1:ab00:211:22ff:fe33:4455"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn generate_addr_64() {
+        let prefix = "2001:db8:1:2::".parse().unwrap();
+        let addr = generate_addr(prefix, 64, MAC).unwrap();
+        assert_eq!(
+            addr,
+            "2001:db8:1:2:211:22ff:fe33:4455"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn until_survives_backward_clock_step() {
+        // The prefix was added 20s ago with 10s left, after which the
+        // system clock was stepped back by an hour.
+        let added = ClockAnchor {
+            instant: Instant::now() - Duration::from_secs(20),
+            system: SystemTime::now() + Duration::from_secs(3600) - Duration::from_secs(20),
+        };
+        let expired = Lifetime::Until(added.system + Duration::from_secs(10));
+
+        // Compared with the current wall clock it would come back to life.
+        assert!(expired.duration() > Duration::from_secs(3000));
+        assert_eq!(added.remaining(expired), Duration::ZERO);
+
+        let valid = Lifetime::Until(added.system + Duration::from_secs(60));
+        let remaining = added.remaining(valid);
+        assert!(remaining <= Duration::from_secs(40));
+        assert!(remaining > Duration::from_secs(30));
+
+        assert_eq!(added.remaining(Lifetime::Infinite), Lifetime::INFINITE);
+    }
+
+    fn prefix_until(prefix: Ipv6Addr, valid: u64, deprecate_before: Option<u64>) -> Prefix {
+        Prefix {
+            prefix,
+            prefix_length: 64,
+            preferred_lifetime: Lifetime::Infinite,
+            valid_lifetime: Lifetime::Until(SystemTime::now() + Duration::from_secs(valid)),
+            deprecate_before: deprecate_before.map(Duration::from_secs),
+            on_link: true,
+            label: None,
+            extra_addrs: Vec::new(),
+            withdrawal: None,
+            added: ClockAnchor::now(),
+        }
+    }
+
+    #[test]
+    fn expiry_queue_interleaved() {
+        let a = Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 0);
+        let b = Ipv6Addr::new(0x2001, 0xdb8, 2, 0, 0, 0, 0, 0);
+        let c = Ipv6Addr::new(0x2001, 0xdb8, 3, 0, 0, 0, 0, 0);
+        let d = Ipv6Addr::new(0x2001, 0xdb8, 4, 0, 0, 0, 0, 0);
+
+        let now = Instant::now();
+        let prefixes = [
+            prefix_until(a, 300, None),
+            prefix_until(b, 100, None),
+            Prefix {
+                valid_lifetime: Lifetime::Infinite,
+                ..prefix_until(c, 0, None)
+            },
+            // Deprecated after 50s, expires after 200s.
+            prefix_until(d, 200, Some(150)),
+        ];
+
+        let mut queue = ExpiryQueue::default();
+        queue.rebuild(now, &prefixes);
+
+        let next = queue.next().unwrap() - now;
+        assert!(next <= Duration::from_secs(50) && next > Duration::from_secs(49));
+
+        assert!(queue.pop_due(now + Duration::from_secs(10)).is_empty());
+        assert_eq!(queue.pop_due(now + Duration::from_secs(60)), [d]);
+
+        // The expiry of `d` follows its deprecation.
+        let later = now + Duration::from_secs(60);
+        queue.schedule(later, &prefix_until(d, 140, Some(150)));
+
+        assert_eq!(queue.pop_due(now + Duration::from_secs(250)), [b, d]);
+        assert_eq!(queue.pop_due(now + Duration::from_secs(1000)), [a]);
+        assert_eq!(queue.next(), None);
+    }
+
+    #[test]
+    fn unconfigured_state() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let state = State::new(&Config::default(), tx);
+        assert!(state.is_unconfigured());
+
+        // DNS servers alone are still useful to hosts.
+        state
+            .dns_servers
+            .write()
+            .insert(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53), None);
+        assert!(!state.is_unconfigured());
+    }
+
+    #[test]
+    fn pause_resume() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let state = State::new(&Config::default(), tx);
+        assert!(!state.paused.load(Ordering::Relaxed));
+
+        // The scheduler is woken up to send the withdrawal RA.
+        assert!(state.set_paused(true));
+        assert!(state.paused.load(Ordering::Relaxed));
+        assert!(state.pause_changed.notified().now_or_never().is_some());
+
+        // Pausing again changes nothing and does not wake the scheduler.
+        assert!(!state.set_paused(true));
+        assert!(state.pause_changed.notified().now_or_never().is_none());
+
+        assert!(state.set_paused(false));
+        assert!(!state.paused.load(Ordering::Relaxed));
+        assert!(state.pause_changed.notified().now_or_never().is_some());
+        assert!(!state.set_paused(false));
+
+        let events: Vec<_> = state
+            .events
+            .lock()
+            .iter()
+            .map(|(_, event)| *event)
+            .collect();
+        assert_eq!(events, [Event::Paused, Event::Resumed]);
+    }
+
+    #[test]
+    fn load_database_prunes_expired() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let state = State::new(&Config::default(), tx);
+
+        let prefix = |prefix, valid| crate::database::Prefix {
+            prefix,
+            prefix_length: 64,
+            preferred: crate::database::Lifetime::Duration(Duration::ZERO),
+            valid,
+            deprecate_before: None,
+            on_link: true,
+            label: None,
+            extra_addrs: Vec::new(),
+        };
+        let expired = Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 0);
+        let future = Ipv6Addr::new(0x2001, 0xdb8, 2, 0, 0, 0, 0, 0);
+        let infinite = Ipv6Addr::new(0x2001, 0xdb8, 3, 0, 0, 0, 0, 0);
+        let db = crate::database::Database {
+            prefixes: vec![
+                prefix(
+                    expired,
+                    crate::database::Lifetime::Until(SystemTime::now() - Duration::from_secs(60)),
+                ),
+                prefix(
+                    future,
+                    crate::database::Lifetime::Until(SystemTime::now() + Duration::from_secs(3600)),
+                ),
+                prefix(infinite, crate::database::Lifetime::Infinite),
+            ],
+            dns_servers: vec![crate::database::DnsServer {
+                addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53),
+                label: None,
+            }],
+        };
+
+        assert_eq!(state.load_database(&db), 3);
+
+        let prefixes = state.prefixes.read();
+        assert!(!prefixes.contains_key(&expired));
+        assert!(prefixes.contains_key(&future));
+        assert!(prefixes.contains_key(&infinite));
+    }
+
+    #[test]
+    fn load_database_skips_invalid_prefixes() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let state = State::new(
+            &Config {
+                max_addresses_per_prefix: 2,
+                ..Default::default()
+            },
+            tx,
+        );
+
+        let prefix =
+            |prefix: Ipv6Addr, prefix_length, extra_addrs: &[Ipv6Addr]| crate::database::Prefix {
+                prefix,
+                prefix_length,
+                preferred: crate::database::Lifetime::Duration(Duration::from_secs(1800)),
+                valid: crate::database::Lifetime::Duration(Duration::from_secs(3600)),
+                deprecate_before: None,
+                on_link: true,
+                label: None,
+                extra_addrs: extra_addrs.to_vec(),
+            };
+        let valid = Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 0);
+        let outside = Ipv6Addr::new(0x2001, 0xdb8, 2, 0, 0, 0, 0, 0);
+        let too_many = Ipv6Addr::new(0x2001, 0xdb8, 3, 0, 0, 0, 0, 0);
+        let too_long = Ipv6Addr::new(0x2001, 0xdb8, 4, 0, 0, 0, 0, 0);
+        let preferred_exceeds_valid = Ipv6Addr::new(0x2001, 0xdb8, 5, 0, 0, 0, 0, 0);
+
+        let db = crate::database::Database {
+            prefixes: vec![
+                prefix(valid, 64, &[Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 1)]),
+                prefix(
+                    outside,
+                    64,
+                    &[Ipv6Addr::new(0x2001, 0xdb8, 9, 0, 0, 0, 0, 1)],
+                ),
+                prefix(
+                    too_many,
+                    64,
+                    &[
+                        Ipv6Addr::new(0x2001, 0xdb8, 3, 0, 0, 0, 0, 1),
+                        Ipv6Addr::new(0x2001, 0xdb8, 3, 0, 0, 0, 0, 2),
+                    ],
+                ),
+                prefix(too_long, 129, &[]),
+                crate::database::Prefix {
+                    preferred: crate::database::Lifetime::Infinite,
+                    ..prefix(preferred_exceeds_valid, 64, &[])
+                },
+            ],
+            dns_servers: Vec::new(),
+        };
+
+        assert!(state.check_database(&db).is_err());
+        assert_eq!(state.load_database(&db), 1);
+        assert_eq!(
+            state.prefixes.read().keys().copied().collect::<Vec<_>>(),
+            [valid]
+        );
+
+        let db = crate::database::Database {
+            prefixes: vec![prefix(valid, 64, &[])],
+            dns_servers: Vec::new(),
+        };
+        assert_eq!(state.check_database(&db), Ok(()));
+    }
+
+    #[test]
+    fn build_advertisement_golden() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let state = State::new(&Config::default(), tx);
+
+        state.prefixes.write().insert(
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+            Prefix {
+                prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+                prefix_length: 64,
+                preferred_lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+                valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+                deprecate_before: None,
+                on_link: true,
+                label: None,
+                extra_addrs: Vec::new(),
+                withdrawal: None,
+                added: ClockAnchor::now(),
+            },
+        );
+        state
+            .dns_servers
+            .write()
+            .insert(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53), None);
+
+        let adv = build_advertisement(
+            &state,
+            MAC,
+            Duration::from_secs(1800),
+            Duration::from_secs(1800),
+        );
+
+        let mut buf = Vec::new();
+        adv.encode(&mut buf);
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            // Hop limit, flags, router lifetime, reachable time, retrans timer.
+            64, 0, 0x07, 0x08, 0, 0, 0, 0, 0, 0, 0, 0,
+            // MTU
+            5, 1, 0, 0, 0, 0, 0x05, 0xdc,
+            // Source link-layer address
+            1, 1, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55,
+            // RDNSS
+            25, 3, 0, 0, 0, 0, 0x07, 0x08,
+            0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x53,
+            // Prefix information
+            3, 4, 64, 0xc0, 0, 0, 0x0e, 0x10, 0, 0, 0x07, 0x08, 0, 0, 0, 0,
+            0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn select_link_local_prefers_eui64() {
+        let eui64: Ipv6Addr = "fe80::211:22ff:fe33:4455".parse().unwrap();
+        let random: Ipv6Addr = "fe80::1234:5678:9abc:def0".parse().unwrap();
+        let low: Ipv6Addr = "fe80::1".parse().unwrap();
+        let global: Ipv6Addr = "2001:db8::1".parse().unwrap();
+
+        assert_eq!(
+            select_link_local(&[global, random, eui64, low], MAC),
+            Some(eui64)
+        );
+        assert_eq!(select_link_local(&[global, random, low], MAC), Some(low));
+        assert_eq!(select_link_local(&[low, random], MAC), Some(low));
+        assert_eq!(select_link_local(&[global], MAC), None);
+    }
+
+    #[test]
+    fn health_failures_are_tracked_separately() {
+        let health = Health::new();
+        assert!(health.healthy());
+
+        // A RA sent after the RS listener died does not hide the failure.
+        health.rs_listener_failed.store(true, Ordering::Relaxed);
+        health.last_send_ok.store(true, Ordering::Relaxed);
+        assert!(!health.healthy());
+
+        let health = Health::new();
+        health.link_up.store(false, Ordering::Relaxed);
+        assert!(!health.healthy());
+        health.link_up.store(true, Ordering::Relaxed);
+        assert!(health.healthy());
+
+        health.last_send_ok.store(false, Ordering::Relaxed);
+        assert!(!health.healthy());
+        health.last_send_ok.store(true, Ordering::Relaxed);
+        assert!(health.healthy());
+
+        health.scheduler_failed.store(true, Ordering::Relaxed);
+        assert!(!health.healthy());
+    }
+
+    #[test]
+    fn dad_outcome_retries() {
+        let mut retries = 1;
+        assert_eq!(dad_outcome(DadState::Done, &mut retries), DadOutcome::Keep);
+        assert_eq!(
+            dad_outcome(DadState::Tentative, &mut retries),
+            DadOutcome::Keep
+        );
+        assert_eq!(retries, 1);
+
+        assert_eq!(
+            dad_outcome(DadState::Failed, &mut retries),
+            DadOutcome::Retry
+        );
+        assert_eq!(retries, 0);
+        assert_eq!(
+            dad_outcome(DadState::Failed, &mut retries),
+            DadOutcome::GiveUp
+        );
+    }
+
+    #[tokio::test]
+    async fn wait_for_dad_polls_until_complete() {
+        let mut states = vec![Some(DadState::Failed), Some(DadState::Tentative)];
+        let state = wait_for_dad(
+            || futures::future::ready(Ok(states.pop().unwrap())),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(state, DadState::Failed);
+        assert!(states.is_empty());
+
+        // The address disappeared.
+        let state = wait_for_dad(|| futures::future::ready(Ok(None)), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(state, DadState::Failed);
+    }
+
+    #[tokio::test]
+    async fn wait_for_dad_keeps_tentative_after_timeout() {
+        let state = wait_for_dad(
+            || futures::future::ready(Ok(Some(DadState::Tentative))),
+            Duration::from_millis(250),
+        )
+        .await
+        .unwrap();
+        assert_eq!(state, DadState::Tentative);
+    }
+
+    #[test]
+    fn router_advert_requires_link_local_and_hop_limit() {
+        let packet = IcmpPacket {
+            typ: IcmpType::RouterAdvertisement,
+            code: 0,
+            checksum: 0,
+            content: IcmpContent::RouterAdvertisement(RouterAdvertisement {
+                cur_hop_limit: 64,
+                managed: false,
+                other: false,
+                router_lifetime: Duration::from_secs(1800),
+                reachable_timer: None,
+                retrans_timer: None,
+                options: Vec::new(),
+            }),
+        };
+        let link_local: Ipv6Addr = "fe80::1".parse().unwrap();
+        let global: Ipv6Addr = "2001:db8::1".parse().unwrap();
+
+        assert!(router_advert_is_valid(link_local, Some(255), &packet));
+        assert!(!router_advert_is_valid(global, Some(255), &packet));
+        assert!(!router_advert_is_valid(link_local, Some(254), &packet));
+        assert!(!router_advert_is_valid(link_local, None, &packet));
+    }
+
+    #[test]
+    fn recv_buffer_size_from_mtu() {
+        assert_eq!(recv_buffer_size(None, None), 1500);
+        assert_eq!(recv_buffer_size(None, Some(9000)), 9000);
+        assert_eq!(recv_buffer_size(Some(4000), Some(9000)), 4000);
+        assert_eq!(recv_buffer_size(None, Some(1000)), MIN_RECV_BUFFER_SIZE);
+        assert_eq!(recv_buffer_size(Some(1 << 20), None), MAX_RECV_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn generate_addr_80() {
+        let prefix = "2001:db8:1:2:3::".parse().unwrap();
+        assert_eq!(generate_addr(prefix, 80, MAC), None);
+    }
+
+    #[test]
+    fn prefix_deprecated_before_expiry() {
+        let prefix = Prefix {
+            prefix: "2001:db8::".parse().unwrap(),
+            prefix_length: 64,
+            preferred_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+            valid_lifetime: Lifetime::Until(SystemTime::now() + Duration::from_secs(1000)),
+            deprecate_before: Some(Duration::from_secs(300)),
+            on_link: true,
+            label: None,
+            extra_addrs: Vec::new(),
+            withdrawal: None,
+            added: ClockAnchor::now(),
+        };
+
+        // The deprecation RA is scheduled before the removal.
+        assert!(!prefix.advertised_preferred_lifetime(None).is_zero());
+        let next_event = prefix.next_event().unwrap();
+        assert!(next_event <= Duration::from_secs(700));
+        assert!(next_event > Duration::from_secs(600));
+
+        let prefix = Prefix {
+            valid_lifetime: Lifetime::Until(SystemTime::now() + Duration::from_secs(200)),
+            ..prefix
+        };
+
+        // Once deprecated the next event is the removal.
+        assert!(prefix.advertised_preferred_lifetime(None).is_zero());
+        let next_event = prefix.next_event().unwrap();
+        assert!(next_event <= Duration::from_secs(200));
+        assert!(next_event > Duration::from_secs(100));
+    }
+
+    #[test]
+    fn prefix_information_on_link() {
+        for on_link in [true, false] {
+            let prefix = Prefix {
+                prefix: "2001:db8::".parse().unwrap(),
+                prefix_length: 64,
+                preferred_lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+                valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+                deprecate_before: None,
+                on_link,
+                label: None,
+                extra_addrs: Vec::new(),
+                withdrawal: None,
+                added: ClockAnchor::now(),
+            };
+
+            let mut buf = Vec::new();
+            IcmpOption::PrefixInformation(prefix.information(None, true)).encode(&mut buf);
+
+            // The L flag is the most significant bit of the flags byte.
+            assert_eq!(buf[3] & 0x80 != 0, on_link);
+        }
+    }
+
+    #[test]
+    fn source_link_layer_address_option() {
+        for include_source_lladdr in [true, false] {
+            let (tx, _rx) = tokio::sync::mpsc::channel(1);
+            let config = Config {
+                include_source_lladdr,
+                ..Default::default()
+            };
+            let state = State::new(&config, tx);
+
+            let adv = build_advertisement(
+                &state,
+                MAC,
+                Duration::from_secs(1800),
+                Duration::from_secs(1800),
+            );
+            let lladdr = adv.options.iter().find_map(|opt| match opt {
+                IcmpOption::SourceLinkLayerAddress(addr) => Some(addr.0),
+                _ => None,
+            });
+
+            assert_eq!(lladdr, include_source_lladdr.then_some(MAC));
+        }
+    }
+
+    #[test]
+    fn prefix_information_flags() {
+        // L=1/A=0 lets hosts reach the prefix directly while getting their
+        // address elsewhere, e.g. via DHCPv6.
+        for (on_link, autonomous, flags) in [
+            (true, true, 0xc0),
+            (true, false, 0x80),
+            (false, true, 0x40),
+            (false, false, 0x00),
+        ] {
+            let (tx, _rx) = tokio::sync::mpsc::channel(1);
+            let config = Config {
+                autonomous,
+                ..Default::default()
+            };
+            let state = State::new(&config, tx);
+
+            state.prefixes.write().insert(
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+                Prefix {
+                    prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+                    prefix_length: 64,
+                    preferred_lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+                    valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+                    deprecate_before: None,
+                    on_link,
+                    label: None,
+                    extra_addrs: Vec::new(),
+                    withdrawal: None,
+                    added: ClockAnchor::now(),
+                },
+            );
+
+            let adv = build_advertisement(
+                &state,
+                MAC,
+                Duration::from_secs(1800),
+                Duration::from_secs(1800),
+            );
+            let info = adv.prefixes().next().unwrap();
+
+            let mut buf = Vec::new();
+            IcmpOption::PrefixInformation(*info).encode(&mut buf);
+
+            // L is the most and A the second most significant bit of the
+            // flags byte, all other bits are reserved.
+            assert_eq!(buf[3], flags, "L={} A={}", on_link, autonomous);
+
+            let IcmpOption::PrefixInformation(decoded) = IcmpOption::decode(&buf[..]).unwrap()
+            else {
+                panic!("decoded a different option");
+            };
+            assert_eq!(decoded.on_link, on_link);
+            assert_eq!(decoded.autonomous, autonomous);
+        }
+    }
+
+    #[test]
+    fn prefix_withdrawal() {
+        let mut prefix = Prefix {
+            prefix: "2001:db8::".parse().unwrap(),
+            prefix_length: 64,
+            preferred_lifetime: Lifetime::Infinite,
+            valid_lifetime: Lifetime::Infinite,
+            deprecate_before: None,
+            on_link: true,
+            label: None,
+            extra_addrs: Vec::new(),
+            withdrawal: Some(3),
+            added: ClockAnchor::now(),
+        };
+
+        let info = prefix.information(None, true);
+        assert!(info.preferred_lifetime.is_zero());
+        assert_eq!(info.valid_lifetime, WITHDRAWN_VALID_LIFETIME);
+
+        // The prefix is dropped after it was advertised 3 times.
+        assert!(advance_withdrawal(&mut prefix));
+        assert!(advance_withdrawal(&mut prefix));
+        assert!(!advance_withdrawal(&mut prefix));
+
+        let mut prefix = Prefix {
+            withdrawal: None,
+            added: ClockAnchor::now(),
+            ..prefix
+        };
+        assert!(advance_withdrawal(&mut prefix));
+        assert_eq!(
+            prefix.information(None, true).preferred_lifetime,
+            Lifetime::INFINITE
+        );
+    }
+
+    #[test]
+    fn prefix_infinite_never_expires() {
+        let prefix = Prefix {
+            prefix: "2001:db8::".parse().unwrap(),
+            prefix_length: 64,
+            preferred_lifetime: Lifetime::Infinite,
+            valid_lifetime: Lifetime::Infinite,
+            deprecate_before: Some(Duration::from_secs(300)),
+            on_link: true,
+            label: None,
+            extra_addrs: Vec::new(),
+            withdrawal: None,
+            added: ClockAnchor::now(),
+        };
+
+        assert!(!prefix.valid_remaining().is_zero());
+        assert_eq!(prefix.next_event(), None);
+        assert_eq!(
+            prefix.advertised_preferred_lifetime(None),
+            Lifetime::INFINITE
+        );
+        assert_eq!(
+            prefix.advertised_preferred_lifetime(Some(0.5)),
+            Lifetime::INFINITE
+        );
+    }
+
+    #[test]
+    fn prefix_infinite_end_to_end() {
+        // As sent by `rsadv_control_cli add-prefix --preferred infinite --valid 4294967295`.
+        let req = rsadv_control::Request::AddPrefix(rsadv_control::Prefix {
+            prefix: "2001:db8::".parse().unwrap(),
+            prefix_length: 64,
+            preferred_lifetime: Lifetime::Infinite,
+            valid_lifetime: Lifetime::from_secs(u64::from(u32::MAX)),
+            deprecate_before: None,
+            on_link: true,
+            label: None,
+            extra_addrs: Vec::new(),
+        });
+        let mut buf = Vec::new();
+        req.encode(&mut buf);
+        let rsadv_control::Request::AddPrefix(prefix) =
+            rsadv_control::Request::decode(&buf[..]).unwrap()
+        else {
+            panic!("decoded a different request");
+        };
+
+        let prefix = Prefix {
+            prefix: prefix.prefix,
+            prefix_length: prefix.prefix_length,
+            preferred_lifetime: prefix.preferred_lifetime,
+            valid_lifetime: prefix.valid_lifetime,
+            deprecate_before: prefix.deprecate_before,
+            on_link: prefix.on_link,
+            label: prefix.label,
+            extra_addrs: prefix.extra_addrs,
+            withdrawal: None,
+            added: ClockAnchor::now(),
+        };
+        assert_eq!(prefix.valid_lifetime, Lifetime::Infinite);
+
+        // Never reaped.
+        assert_eq!(prefix.next_event(), None);
+        assert!(!prefix.valid_remaining().is_zero());
+
+        // Configured as infinite.
+        assert_eq!(
+            netlink_lifetime(Some(prefix.advertised_preferred_lifetime(None))),
+            u32::MAX
+        );
+        assert_eq!(netlink_lifetime(Some(prefix.valid_remaining())), u32::MAX);
+
+        // Advertised as infinite, with and without a preferred ratio.
+        for preferred_ratio in [None, Some(0.5)] {
+            let mut buf = Vec::new();
+            IcmpOption::PrefixInformation(prefix.information(preferred_ratio, true))
+                .encode(&mut buf);
+            // Valid lifetime, then preferred lifetime.
+            assert_eq!(buf[4..12], [0xff; 8]);
+        }
+    }
+
+    #[test]
+    fn prefix_preferred_ratio() {
+        let prefix = Prefix {
+            prefix: "2001:db8::".parse().unwrap(),
+            prefix_length: 64,
+            preferred_lifetime: Lifetime::Infinite,
+            valid_lifetime: Lifetime::Duration(Duration::from_secs(3600)),
+            deprecate_before: None,
+            on_link: true,
+            label: None,
+            extra_addrs: Vec::new(),
+            withdrawal: None,
+            added: ClockAnchor::now(),
+        };
+
+        assert_eq!(
+            prefix.advertised_preferred_lifetime(Some(0.5)),
+            Duration::from_secs(1800)
+        );
+        assert_eq!(
+            prefix.advertised_preferred_lifetime(Some(0.0)),
+            Duration::ZERO
+        );
+
+        // The preferred lifetime counts down together with the valid lifetime.
+        for remaining in [1000, 200] {
+            let prefix = Prefix {
+                valid_lifetime: Lifetime::Until(SystemTime::now() + Duration::from_secs(remaining)),
+                ..prefix.clone()
+            };
+
+            let preferred = prefix.advertised_preferred_lifetime(Some(0.5));
+            assert!(preferred <= Duration::from_secs(remaining / 2));
+            assert!(preferred > Duration::from_secs(remaining / 2 - 5));
+        }
+    }
+
+    #[test]
+    fn solicited_ra_not_dropped_by_unicast_response() {
+        let start = Instant::now();
+        let mut schedule = MulticastSchedule::new(start);
+        schedule.sent(start, Duration::from_secs(3));
+
+        // The first host is answered by the imminent multicast RA.
+        let now = start + Duration::from_millis(2900);
+        assert_eq!(
+            schedule.solicit(now, Duration::from_millis(400), false),
+            None
+        );
+
+        // The second host is answered directly before the multicast RA.
+        let ts = schedule
+            .solicit(now, Duration::from_millis(50), false)
+            .unwrap();
+        assert!(ts < schedule.next);
+
+        // The unicast response does not move the multicast RA the first
+        // host is waiting for.
+        assert_eq!(schedule.next, start + Duration::from_secs(3));
+    }
+
+    #[test]
+    fn unicast_responses_keep_multicast_schedule() {
+        let start = Instant::now();
+        let interval = MIN_DELAY_BETWEEN_RAS;
+        let mut schedule = MulticastSchedule::new(start);
+        schedule.sent(start, interval);
+
+        // Hosts soliciting shortly before every multicast RA are answered
+        // directly. As in the scheduler, only multicast RAs are recorded.
+        let mut multicast = Vec::new();
+        for _ in 0..4 {
+            let now = schedule.next - Duration::from_millis(100);
+            let ts = schedule
+                .solicit(now, Duration::from_millis(50), false)
+                .unwrap();
+            assert!(ts < schedule.next);
+
+            let ts = schedule.next;
+            multicast.push(ts - start);
+            schedule.sent(ts, interval);
+        }
+
+        assert_eq!(
+            multicast,
+            [interval, interval * 2, interval * 3, interval * 4]
+        );
+    }
+
+    #[test]
+    fn solicited_multicast_ra_moved_forward() {
+        let start = Instant::now();
+        let mut schedule = MulticastSchedule::new(start);
+        schedule.sent(start, Duration::from_secs(600));
+
+        let now = start + Duration::from_secs(10);
+        let delay = Duration::from_millis(100);
+        assert_eq!(schedule.solicit(now, delay, true), None);
+        assert_eq!(schedule.next, now + delay);
+
+        // Multicast RAs are still rate limited.
+        let mut schedule = MulticastSchedule::new(start);
+        schedule.sent(start, Duration::from_secs(600));
+        assert_eq!(schedule.solicit(start, delay, true), None);
+        assert_eq!(schedule.next, start + MIN_DELAY_BETWEEN_RAS);
+    }
+
+    /// Returns the number of times a solicited RA is sent to `addr` and the
+    /// shortest interval between two transmissions.
+    fn count_transmissions(
+        addr: SocketAddrV6,
+        retransmits: u32,
+        max_ra_delay: Duration,
+    ) -> (u32, Option<Duration>) {
+        let mut sent = 1;
+        let mut min_interval = None;
+
+        let mut remaining = retransmits;
+        while let Some((interval, next)) = next_retransmission(&addr, remaining, max_ra_delay) {
+            sent += 1;
+            min_interval = Some(min_interval.map_or(interval, |min| Duration::min(min, interval)));
+            remaining = next;
+        }
+
+        (sent, min_interval)
+    }
+
+    #[test]
+    fn seeded_rng_reproducible() {
+        let mut lhs = new_rng(Some(42));
+        let mut rhs = new_rng(Some(42));
+        for _ in 0..100 {
+            assert_eq!(lhs.gen::<u64>(), rhs.gen::<u64>());
+        }
+
+        let mut other = new_rng(Some(43));
+        assert_ne!(
+            (0..4).map(|_| lhs.gen::<u64>()).collect::<Vec<_>>(),
+            (0..4).map(|_| other.gen::<u64>()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn solicited_delay_bounded() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        for max_delay in [1, 50, 500, 2000].map(Duration::from_millis) {
+            for _ in 0..1000 {
+                assert!(solicited_delay(&mut rng, max_delay) < max_delay);
+            }
+        }
+    }
+
+    #[test]
+    fn solicited_ra_retransmits() {
+        let unicast = SocketAddrV6::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1), 0, 0, 0);
+        let multicast = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0);
+
+        assert_eq!(
+            count_transmissions(unicast, 0, MAX_RA_DELAY_TIME),
+            (1, None)
+        );
+        assert_eq!(
+            count_transmissions(unicast, 1, MAX_RA_DELAY_TIME),
+            (1, None)
+        );
+        assert_eq!(
+            count_transmissions(unicast, 3, MAX_RA_DELAY_TIME),
+            (3, Some(MAX_RA_DELAY_TIME))
+        );
+        assert_eq!(
+            count_transmissions(multicast, 3, MAX_RA_DELAY_TIME),
+            (3, Some(MIN_DELAY_BETWEEN_RAS))
+        );
+
+        // A longer configured delay spaces unicast retransmissions further
+        // apart, so they can not overtake the previous transmission.
+        let max_ra_delay = Duration::from_secs(2);
+        assert_eq!(
+            count_transmissions(unicast, 3, max_ra_delay),
+            (3, Some(max_ra_delay))
+        );
+        assert_eq!(
+            count_transmissions(multicast, 3, max_ra_delay),
+            (3, Some(MIN_DELAY_BETWEEN_RAS))
+        );
+    }
+
+    #[test]
+    fn solicitation_queue_drops_when_full() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let queue = SolicitationQueue::new(tx);
+
+        let host1 = SocketAddrV6::new("fe80::1".parse().unwrap(), 0, 0, 1);
+        let host2 = SocketAddrV6::new("fe80::2".parse().unwrap(), 0, 0, 1);
+
+        assert!(queue.push(host1, 1));
+        // Coalesced into the queued RS.
+        assert!(queue.push(host1, 1));
+        assert_eq!(queue.dropped(), 0);
+
+        assert!(!queue.push(host2, 1));
+        assert!(!queue.push(host2, 1));
+        assert_eq!(queue.dropped(), 2);
+
+        match rx.try_recv().unwrap() {
+            Command::SendRouterAdvertisement(addr, _) => {
+                assert_eq!(addr, host1);
+                queue.pop(&addr);
+            }
+            Command::NewConfig => panic!("unexpected command"),
+        }
+
+        assert!(queue.push(host2, 1));
+        assert!(rx.try_recv().is_ok());
+        assert!(rx.try_recv().is_err());
+        assert_eq!(queue.dropped(), 2);
+    }
+
+    #[test]
+    fn solicitation_queue_closed_on_shutdown() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        let queue = SolicitationQueue::new(tx);
+
+        let host = SocketAddrV6::new("fe80::1".parse().unwrap(), 0, 0, 1);
+        queue.close();
+
+        // RSs arriving during shutdown are ignored, not counted as dropped.
+        assert!(!queue.push(host, 1));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn save_loop_batches_changes() {
+        let changed = Arc::new(Notify::new());
+        let saves = Arc::new(AtomicU32::new(0));
+        let (tx, rx) = oneshot::channel::<()>();
+
+        let task = {
+            let changed = changed.clone();
+            let saves = saves.clone();
+            tokio::task::spawn(async move {
+                save_loop(&changed, Duration::from_millis(200), rx, || {
+                    saves.fetch_add(1, Ordering::Relaxed);
+                })
+                .await;
+            })
+        };
+
+        for _ in 0..100 {
+            changed.notify_one();
+            tokio::task::yield_now().await;
+        }
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(saves.load(Ordering::Relaxed), 1);
+
+        // A change right before shutdown is still saved.
+        changed.notify_one();
+        tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(saves.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn settle_coalesces_changes() {
+        let changed = Arc::new(Notify::new());
+        let handled = Arc::new(AtomicU32::new(0));
+
+        let task = {
+            let changed = changed.clone();
+            let handled = handled.clone();
+            tokio::task::spawn(async move {
+                loop {
+                    changed.notified().await;
+                    settle(&changed, Duration::from_millis(100)).await;
+                    handled.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        };
+
+        for _ in 0..10 {
+            changed.notify_one();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(handled.load(Ordering::Relaxed), 1);
+
+        task.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn settle_is_bounded() {
+        let changed = Arc::new(Notify::new());
+
+        // Keep notifying more often than the window.
+        let notifier = {
+            let changed = changed.clone();
+            tokio::task::spawn(async move {
+                loop {
+                    changed.notify_one();
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+        };
+
+        let window = Duration::from_millis(50);
+        let start = tokio::time::Instant::now();
+        tokio::time::timeout(Duration::from_secs(5), settle(&changed, window))
+            .await
+            .expect("settle did not return");
+        let elapsed = start.elapsed();
+        notifier.abort();
+
+        assert_eq!(elapsed, window * MAX_SETTLE_WINDOWS);
+    }
+
+    #[test]
+    fn foreign_addr() {
+        let addr = generate_addr(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0), 64, MAC).unwrap();
+        let link_local = Ipv6Addr::new(0xfe80, 0, 0, 0, 0x0211, 0x22ff, 0xfe33, 0x4455);
+
+        // Not configured at all.
+        assert!(!is_foreign_addr(addr, None, &[link_local]));
+        // Configured by another process before we got to it.
+        assert!(is_foreign_addr(addr, None, &[link_local, addr]));
+        // Configured by us.
+        assert!(!is_foreign_addr(addr, Some(addr), &[link_local, addr]));
+    }
+
+    #[test]
+    fn interface_index() {
+        assert_eq!(parse_interface_index("2"), Some(2));
+        assert_eq!(parse_interface_index("if2"), Some(2));
+        assert_eq!(parse_interface_index("eth0"), None);
+        assert_eq!(parse_interface_index("if"), None);
+        assert_eq!(parse_interface_index("ifb0"), None);
+    }
+
+    #[test]
+    fn dns_server_usability() {
+        for addr in [
+            Ipv6Addr::UNSPECIFIED,
+            Ipv6Addr::LOCALHOST,
+            Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1),
+            Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0x53),
+            Ipv6Addr::new(0xfebf, 0, 0, 0, 0, 0, 0, 0x53),
+        ] {
+            assert!(unusable_dns_server(addr).is_some(), "{} is usable", addr);
+        }
+
+        for addr in [
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53),
+            Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x53),
+            // Deprecated site-local, but not link-local.
+            Ipv6Addr::new(0xfec0, 0, 0, 0, 0, 0, 0, 0x53),
+        ] {
+            assert_eq!(unusable_dns_server(addr), None, "{} is unusable", addr);
+        }
+    }
+
+    #[test]
+    fn initial_advertisements_restart_on_change() {
+        let mut initial = InitialAdvertisements::default();
+        let interval = Duration::from_secs(600);
+
+        let options = [IcmpOption::Mtu(1500)];
+        for _ in 0..MAX_INITIAL_RTR_ADVERTISEMENTS {
+            initial.update(&options);
+            assert_eq!(initial.clamp(interval), MAX_INITIAL_RTR_ADVERT_INTERVAL);
+        }
+
+        initial.update(&options);
+        assert_eq!(initial.clamp(interval), interval);
+
+        let options = [IcmpOption::Mtu(1280)];
+        initial.update(&options);
+        assert_eq!(initial.clamp(interval), MAX_INITIAL_RTR_ADVERT_INTERVAL);
+    }
+
+    #[test]
+    fn fast_advertisements_after_change() {
+        let mut initial = InitialAdvertisements::default();
+        let mut fast = FastAdvertisements::new(5);
+        let interval = Duration::from_secs(600);
+
+        let options = [IcmpOption::Mtu(1500)];
+        let now = Instant::now();
+        let mut schedule = MulticastSchedule::new(now);
+
+        // The RA on change is followed by the fast RAs, which take precedence
+        // over the initial RAs.
+        initial.reset();
+        fast.reset();
+        let mut sent = Vec::new();
+        for _ in 0..7 {
+            let ts = schedule.next;
+            sent.push(ts - now);
+
+            initial.update(&options);
+            schedule.sent(ts, fast.clamp(initial.clamp(interval)));
+        }
+
+        let secs: Vec<_> = sent.iter().map(|dur| dur.as_secs()).collect();
+        assert_eq!(secs, [0, 3, 6, 9, 12, 15, 615]);
+
+        // Without fast RAs the initial RAs apply.
+        let mut fast = FastAdvertisements::new(0);
+        initial.reset();
+        fast.reset();
+        assert_eq!(
+            fast.clamp(initial.clamp(interval)),
+            MAX_INITIAL_RTR_ADVERT_INTERVAL
+        );
+    }
 }
@@ -1,3 +1,5 @@
+use std::fmt::{self, Display, Formatter};
+use std::io;
 use std::net::Ipv6Addr;
 use std::time::Duration;
 
@@ -8,9 +10,61 @@ pub enum Error {
     Eof,
     UnknownOptionCode,
     UnknownIcmpType,
+    InvalidRoutePreference,
+    /// A prefix length is longer than 128 bits.
+    InvalidPrefixLength,
+    /// The length field of an option does not match the length of its content.
+    InvalidOptionLength,
+    /// A domain name is compressed or not valid UTF-8.
+    InvalidDomainName,
+    /// A PvD option contains another PvD option (RFC 8801 3.1).
+    NestedProvisioningDomain,
 }
 
+/// An [`Error`] together with the position in the packet at which it occurred.
 #[derive(Clone, Debug)]
+pub struct DecodeError {
+    /// The number of bytes successfully consumed before the error.
+    pub offset: usize,
+    pub error: Error,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.error {
+            Error::Eof => write!(f, "truncated at byte {}", self.offset),
+            _ => write!(f, "{:?} at byte {}", self.error, self.offset),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for io::Error {
+    fn from(err: DecodeError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err))
+    }
+}
+
+/// Decodes `T` from `buf`, reporting the offset at which decoding failed.
+pub fn decode_with_offset<T>(buf: &[u8]) -> Result<T, DecodeError>
+where
+    T: Decode<Error = Error>,
+{
+    let mut rest = buf;
+    T::decode(&mut rest).map_err(|error| DecodeError {
+        offset: buf.len() - rest.len(),
+        error,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct IcmpPacket {
     pub typ: IcmpType,
     pub code: u8,
@@ -63,7 +117,7 @@ impl Decode for IcmpPacket {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum IcmpContent {
     RouterSolicitation(RouterSolicitation),
     RouterAdvertisement(RouterAdvertisement),
@@ -92,19 +146,163 @@ impl IcmpType {
     }
 }
 
-#[derive(Clone, Debug)]
+/// The maximum reachable time a router may advertise (RFC 4861 6.2.1).
+pub const MAX_REACHABLE_TIME: Duration = Duration::from_millis(3_600_000);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RouterAdvertisement {
     pub cur_hop_limit: u8,
     pub managed: bool,
     pub other: bool,
     pub router_lifetime: Duration,
+    /// The reachable time, `None` if unspecified (0 on the wire).
+    ///
+    /// Values above [`MAX_REACHABLE_TIME`] are clamped when encoding.
     pub reachable_timer: Option<Duration>,
+    /// The retransmission timer, `None` if unspecified (0 on the wire).
     pub retrans_timer: Option<Duration>,
     pub options: Vec<IcmpOption>,
 }
 
-impl Encode for RouterAdvertisement {
-    fn encode<B>(&self, mut buf: B)
+impl RouterAdvertisement {
+    /// Returns all Prefix Information options.
+    pub fn prefixes(&self) -> impl Iterator<Item = &PrefixInformation> {
+        self.options.iter().filter_map(|opt| match opt {
+            IcmpOption::PrefixInformation(info) => Some(info),
+            _ => None,
+        })
+    }
+
+    /// Returns all Recursive DNS Server options.
+    pub fn rdnss(&self) -> impl Iterator<Item = &RecursiveDnsServer> {
+        self.options.iter().filter_map(|opt| match opt {
+            IcmpOption::RecursiveDnsServer(rdnss) => Some(rdnss),
+            _ => None,
+        })
+    }
+
+    /// Returns all Route Information options.
+    pub fn routes(&self) -> impl Iterator<Item = &RouteInformation> {
+        self.options.iter().filter_map(|opt| match opt {
+            IcmpOption::RouteInformation(info) => Some(info),
+            _ => None,
+        })
+    }
+
+    /// Returns the advertised MTU, or `None` if the RA has no MTU option.
+    pub fn mtu(&self) -> Option<u32> {
+        self.options.iter().find_map(|opt| match opt {
+            IcmpOption::Mtu(mtu) => Some(*mtu),
+            _ => None,
+        })
+    }
+}
+
+/// Renders the RA on a single line for logging, e.g. `hop limit 64, router
+/// lifetime 1800s, mtu 1500, prefix 2001:db8::/64 LA valid 3600s preferred
+/// 1800s`.
+impl Display for RouterAdvertisement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hop limit {}, router lifetime {}s",
+            self.cur_hop_limit,
+            self.router_lifetime.as_secs()
+        )?;
+
+        if self.managed || self.other {
+            f.write_str(", flags ")?;
+            if self.managed {
+                f.write_str("M")?;
+            }
+            if self.other {
+                f.write_str("O")?;
+            }
+        }
+
+        if let Some(reachable_timer) = self.reachable_timer {
+            write!(f, ", reachable {}ms", reachable_timer.as_millis())?;
+        }
+        if let Some(retrans_timer) = self.retrans_timer {
+            write!(f, ", retrans {}ms", retrans_timer.as_millis())?;
+        }
+
+        if let Some(mtu) = self.mtu() {
+            write!(f, ", mtu {}", mtu)?;
+        }
+
+        for info in self.prefixes() {
+            write!(f, ", prefix {}/{} ", info.prefix, info.prefix_length)?;
+            if info.on_link {
+                f.write_str("L")?;
+            }
+            if info.autonomous {
+                f.write_str("A")?;
+            }
+            write!(
+                f,
+                " valid {} preferred {}",
+                DisplayLifetime(info.valid_lifetime),
+                DisplayLifetime(info.preferred_lifetime)
+            )?;
+        }
+
+        for rdnss in self.rdnss() {
+            f.write_str(", dns")?;
+            for addr in &rdnss.addrs {
+                write!(f, " {}", addr)?;
+            }
+            write!(f, " lifetime {}", DisplayLifetime(rdnss.lifetime))?;
+        }
+
+        for info in self.routes() {
+            write!(
+                f,
+                ", route {}/{} {:?} lifetime {}",
+                info.prefix,
+                info.prefix_length,
+                info.preference,
+                DisplayLifetime(info.lifetime)
+            )?;
+        }
+
+        let other = self
+            .options
+            .iter()
+            .filter(|opt| {
+                !matches!(
+                    opt,
+                    IcmpOption::Mtu(_)
+                        | IcmpOption::PrefixInformation(_)
+                        | IcmpOption::RecursiveDnsServer(_)
+                        | IcmpOption::RouteInformation(_)
+                )
+            })
+            .count();
+        if other != 0 {
+            write!(f, ", {} other options", other)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Renders a lifetime in seconds, where `u32::MAX` seconds is infinity.
+struct DisplayLifetime(Duration);
+
+impl Display for DisplayLifetime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.as_secs() >= u64::from(u32::MAX) {
+            f.write_str("infinite")
+        } else {
+            write!(f, "{}s", self.0.as_secs())
+        }
+    }
+}
+
+impl RouterAdvertisement {
+    /// Encodes the fields following the ICMP header, without the options.
+    fn encode_header<B>(&self, mut buf: B)
     where
         B: BufMut,
     {
@@ -118,16 +316,25 @@ impl Encode for RouterAdvertisement {
         (self.router_lifetime.as_secs() as u16).encode(&mut buf);
 
         if let Some(reachable_timer) = self.reachable_timer {
-            (reachable_timer.as_secs() as u32).encode(&mut buf);
+            encode_timer(reachable_timer.min(MAX_REACHABLE_TIME)).encode(&mut buf);
         } else {
             0u32.encode(&mut buf);
         }
 
         if let Some(retrans_timer) = self.retrans_timer {
-            (retrans_timer.as_secs() as u32).encode(&mut buf);
+            encode_timer(retrans_timer).encode(&mut buf);
         } else {
             0u32.encode(&mut buf);
         }
+    }
+}
+
+impl Encode for RouterAdvertisement {
+    fn encode<B>(&self, mut buf: B)
+    where
+        B: BufMut,
+    {
+        self.encode_header(&mut buf);
 
         for opt in &self.options {
             opt.encode(&mut buf);
@@ -148,11 +355,11 @@ impl Decode for RouterAdvertisement {
 
         let reachable_timer = match u32::decode(&mut buf)? {
             0 => None,
-            val => Some(Duration::from_secs(val.into())),
+            val => Some(Duration::from_millis(val.into())),
         };
         let retrans_timer = match u32::decode(&mut buf)? {
             0 => None,
-            val => Some(Duration::from_secs(val.into())),
+            val => Some(Duration::from_millis(val.into())),
         };
 
         let mut options = Vec::new();
@@ -174,13 +381,49 @@ impl Decode for RouterAdvertisement {
     }
 }
 
-#[derive(Clone, Debug)]
+/// Returns the timer in milliseconds as sent on the wire.
+///
+/// A non-zero timer is never encoded as 0 since that means unspecified.
+fn encode_timer(timer: Duration) -> u32 {
+    u32::try_from(timer.as_millis()).unwrap_or(u32::MAX).max(1)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum IcmpOption {
     SourceLinkLayerAddress(LinkLayerAddress),
     TargetLinkLayerAddress(LinkLayerAddress),
     PrefixInformation(PrefixInformation),
     Mtu(u32),
     RecursiveDnsServer(RecursiveDnsServer),
+    /// Nonce option from SEND (RFC 3971).
+    Nonce(Vec<u8>),
+    RouteInformation(RouteInformation),
+    /// Home Agent Information option from Mobile IPv6 (RFC 6275 7.4).
+    HomeAgentInfo {
+        /// The preference of the home agent, higher values are preferred.
+        preference: i16,
+        /// The lifetime of the home agent in seconds.
+        lifetime: u16,
+    },
+    /// Provisioning Domain option (RFC 8801).
+    ProvisioningDomain {
+        /// The PvD ID, e.g. `pvd.example.com`.
+        fqdn: String,
+        /// Changes whenever the additional information of the PvD changes.
+        sequence: u16,
+        /// Whether additional information is available over HTTPS (H flag).
+        http: bool,
+        /// Whether DHCPv4 on the link belongs to the PvD (L flag).
+        legacy: bool,
+        /// Hosts wait up to `2^(2 * delay)` milliseconds before fetching the
+        /// additional information. Only the lower 4 bits are encoded.
+        delay: u8,
+        /// The RA header of the PvD (R flag). Its options are not encoded,
+        /// see `options`.
+        header: Option<RouterAdvertisement>,
+        /// The options belonging to the PvD.
+        options: Vec<IcmpOption>,
+    },
 }
 
 impl Encode for IcmpOption {
@@ -209,8 +452,11 @@ impl Encode for IcmpOption {
                 flags |= (opt.autonomous as u8) << 6;
                 flags.encode(&mut buf);
 
-                (opt.valid_lifetime.as_secs() as u32).encode(&mut buf);
-                (opt.preferred_lifetime.as_secs() as u32).encode(&mut buf);
+                // Lifetimes that do not fit are saturated to 0xffffffff (infinity).
+                let valid_lifetime = u32::try_from(opt.valid_lifetime.as_secs());
+                let preferred_lifetime = u32::try_from(opt.preferred_lifetime.as_secs());
+                valid_lifetime.unwrap_or(u32::MAX).encode(&mut buf);
+                preferred_lifetime.unwrap_or(u32::MAX).encode(&mut buf);
                 0u32.encode(&mut buf);
                 buf.put_slice(&opt.prefix.octets());
             }
@@ -232,6 +478,92 @@ impl Encode for IcmpOption {
                     buf.put_slice(&addr.octets());
                 }
             }
+            Self::Nonce(nonce) => {
+                OptionCode::Nonce.to_u8().encode(&mut buf);
+
+                // The option is padded to the next multiple of 8 bytes.
+                let len = (2 + nonce.len()).div_ceil(8);
+                (len as u8).encode(&mut buf);
+
+                buf.put_slice(nonce);
+                for _ in 2 + nonce.len()..len * 8 {
+                    0u8.encode(&mut buf);
+                }
+            }
+            Self::RouteInformation(opt) => {
+                OptionCode::RouteInformation.to_u8().encode(&mut buf);
+
+                // The prefix is truncated to the number of 8 byte blocks
+                // required to contain `prefix_length` bits.
+                let prefix_len = match opt.prefix_length {
+                    0 => 0,
+                    1..=64 => 8,
+                    _ => 16,
+                };
+                (1 + prefix_len as u8 / 8).encode(&mut buf);
+
+                opt.prefix_length.encode(&mut buf);
+                (opt.preference.to_bits() << 3).encode(&mut buf);
+                (opt.lifetime.as_secs() as u32).encode(&mut buf);
+                buf.put_slice(&opt.prefix.octets()[..prefix_len]);
+            }
+            Self::HomeAgentInfo {
+                preference,
+                lifetime,
+            } => {
+                OptionCode::HomeAgentInfo.to_u8().encode(&mut buf);
+                1u8.encode(&mut buf);
+
+                buf.put_slice(&[0, 0]);
+                (*preference as u16).encode(&mut buf);
+                lifetime.encode(&mut buf);
+            }
+            Self::ProvisioningDomain {
+                fqdn,
+                sequence,
+                http,
+                legacy,
+                delay,
+                header,
+                options,
+            } => {
+                // The option length is only known once the contents are encoded.
+                let mut body = Vec::new();
+
+                let mut flags = 0u16;
+                flags |= (*http as u16) << 15;
+                flags |= (*legacy as u16) << 14;
+                flags |= (header.is_some() as u16) << 13;
+                flags |= u16::from(delay & 0xf);
+                flags.encode(&mut body);
+                sequence.encode(&mut body);
+
+                // The FQDN is padded to the next multiple of 8 bytes.
+                encode_domain_name(fqdn, &mut body);
+                while (2 + body.len()) % 8 != 0 {
+                    0u8.encode(&mut body);
+                }
+
+                if let Some(header) = header {
+                    // Type, Code and Checksum, which is 0.
+                    IcmpType::RouterAdvertisement.to_u8().encode(&mut body);
+                    body.put_slice(&[0, 0, 0]);
+                    header.encode_header(&mut body);
+                }
+
+                for opt in options {
+                    opt.encode(&mut body);
+                }
+
+                // The length field counts units of 8 bytes, limiting the
+                // option to 2040 bytes.
+                let len = u8::try_from((2 + body.len()) / 8)
+                    .expect("PvD option is longer than 2040 bytes");
+
+                OptionCode::ProvisioningDomain.to_u8().encode(&mut buf);
+                len.encode(&mut buf);
+                buf.put_slice(&body);
+            }
         }
     }
 }
@@ -248,10 +580,18 @@ impl Decode for IcmpOption {
 
         match OptionCode::from_u8(code) {
             Some(OptionCode::SourceLinkLayerAddress) => {
+                if len != LINK_LAYER_ADDRESS_LEN {
+                    return Err(Error::InvalidOptionLength);
+                }
+
                 let addr = LinkLayerAddress::decode(&mut buf)?;
                 Ok(Self::SourceLinkLayerAddress(addr))
             }
             Some(OptionCode::TargetLinkLayerAddress) => {
+                if len != LINK_LAYER_ADDRESS_LEN {
+                    return Err(Error::InvalidOptionLength);
+                }
+
                 let addr = LinkLayerAddress::decode(&mut buf)?;
                 Ok(Self::TargetLinkLayerAddress(addr))
             }
@@ -313,6 +653,116 @@ impl Decode for IcmpOption {
                     addrs,
                 }))
             }
+            Some(OptionCode::Nonce) => {
+                let mut nonce = Vec::new();
+                for _ in 0..(usize::from(len) * 8).saturating_sub(2) {
+                    nonce.push(u8::decode(&mut buf)?);
+                }
+
+                Ok(Self::Nonce(nonce))
+            }
+            Some(OptionCode::RouteInformation) => {
+                let prefix_length = u8::decode(&mut buf)?;
+                if prefix_length > 128 {
+                    return Err(Error::InvalidPrefixLength);
+                }
+
+                let flags = u8::decode(&mut buf)?;
+                let lifetime = u32::decode(&mut buf)?;
+
+                let mut prefix = [0; 16];
+                for index in 0..(usize::from(len) * 8).saturating_sub(8) {
+                    let byte = u8::decode(&mut buf)?;
+                    if let Some(b) = prefix.get_mut(index) {
+                        *b = byte;
+                    }
+                }
+
+                // Options with the reserved preference MUST be ignored.
+                let preference = RoutePreference::from_bits((flags >> 3) & 0b11)
+                    .ok_or(Error::InvalidRoutePreference)?;
+
+                // Bits after the prefix length are reserved and MUST be
+                // ignored (RFC 4191 2.3).
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(prefix_length))
+                    .unwrap_or(0);
+
+                Ok(Self::RouteInformation(RouteInformation {
+                    prefix_length,
+                    preference,
+                    lifetime: Duration::from_secs(lifetime.into()),
+                    prefix: Ipv6Addr::from(u128::from_be_bytes(prefix) & mask),
+                }))
+            }
+            Some(OptionCode::HomeAgentInfo) => {
+                if len != 1 {
+                    return Err(Error::InvalidOptionLength);
+                }
+
+                for _ in 0..2 {
+                    u8::decode(&mut buf)?;
+                }
+
+                let preference = u16::decode(&mut buf)? as i16;
+                let lifetime = u16::decode(&mut buf)?;
+
+                Ok(Self::HomeAgentInfo {
+                    preference,
+                    lifetime,
+                })
+            }
+            Some(OptionCode::ProvisioningDomain) => {
+                let mut body = vec![0; (usize::from(len) * 8).saturating_sub(2)];
+                if buf.remaining() < body.len() {
+                    return Err(Error::Eof);
+                }
+                buf.copy_to_slice(&mut body);
+                let mut body = &body[..];
+
+                let flags = u16::decode(&mut body)?;
+                let sequence = u16::decode(&mut body)?;
+                let fqdn = decode_domain_name(&mut body)?;
+
+                // Skip the padding after the FQDN. Everything following it is
+                // a multiple of 8 bytes.
+                let padding = body.len() % 8;
+                body = &body[padding..];
+
+                let header = if flags & (1 << 13) != 0 {
+                    if body.len() < 16 {
+                        return Err(Error::InvalidOptionLength);
+                    }
+
+                    // Skip Type, Code and Checksum.
+                    let header = RouterAdvertisement::decode(&body[4..16])?;
+                    body = &body[16..];
+                    Some(header)
+                } else {
+                    None
+                };
+
+                let mut options = Vec::new();
+                while !body.is_empty() {
+                    match IcmpOption::decode(&mut body) {
+                        Ok(IcmpOption::ProvisioningDomain { .. }) => {
+                            return Err(Error::NestedProvisioningDomain);
+                        }
+                        Ok(opt) => options.push(opt),
+                        Err(_) => (),
+                    }
+                }
+
+                Ok(Self::ProvisioningDomain {
+                    fqdn,
+                    sequence,
+                    http: flags & (1 << 15) != 0,
+                    legacy: flags & (1 << 14) != 0,
+                    delay: (flags & 0xf) as u8,
+                    header,
+                    options,
+                })
+            }
             None => {
                 // The length is given as factor of 8 bytes and includes
                 // the header (option + len) with length of 2 which we already
@@ -329,7 +779,52 @@ impl Decode for IcmpOption {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// Encodes `name` as uncompressed DNS labels (RFC 1035 3.1).
+fn encode_domain_name<B>(name: &str, mut buf: B)
+where
+    B: BufMut,
+{
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        (label.len() as u8).encode(&mut buf);
+        buf.put_slice(label.as_bytes());
+    }
+
+    0u8.encode(&mut buf);
+}
+
+fn decode_domain_name<B>(mut buf: B) -> Result<String, Error>
+where
+    B: Buf,
+{
+    let mut name = String::new();
+
+    loop {
+        let len = u8::decode(&mut buf)?;
+        if len == 0 {
+            return Ok(name);
+        }
+
+        // Longer labels are compression pointers, which NDP does not allow.
+        if len > 63 {
+            return Err(Error::InvalidDomainName);
+        }
+
+        if buf.remaining() < usize::from(len) {
+            return Err(Error::Eof);
+        }
+
+        let mut label = vec![0; usize::from(len)];
+        buf.copy_to_slice(&mut label);
+        let label = String::from_utf8(label).map_err(|_| Error::InvalidDomainName)?;
+
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(&label);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct PrefixInformation {
     pub prefix_length: u8,
     pub on_link: bool,
@@ -339,7 +834,10 @@ pub struct PrefixInformation {
     pub prefix: Ipv6Addr,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// The length of a link-layer address option in units of 8 bytes.
+const LINK_LAYER_ADDRESS_LEN: u8 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct LinkLayerAddress(pub [u8; 6]);
 
 impl Encode for LinkLayerAddress {
@@ -367,7 +865,7 @@ impl Decode for LinkLayerAddress {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RouterSolicitation {
     pub source_link_layer_addr: Option<LinkLayerAddress>,
 }
@@ -403,9 +901,13 @@ impl Decode for RouterSolicitation {
 
         if buf.remaining() > 0 {
             let code = OptionCode::from_u8(u8::decode(&mut buf)?).ok_or(Error::Eof)?;
-            let _len = u8::decode(&mut buf)?;
+            let len = u8::decode(&mut buf)?;
 
             if code == OptionCode::SourceLinkLayerAddress {
+                if len != LINK_LAYER_ADDRESS_LEN {
+                    return Err(Error::InvalidOptionLength);
+                }
+
                 source_link_layer_addr = Some(LinkLayerAddress::decode(&mut buf)?);
             }
         }
@@ -509,6 +1011,10 @@ pub enum OptionCode {
     PrefixInformation,
     RedirectedHeader,
     Mtu,
+    HomeAgentInfo,
+    Nonce,
+    ProvisioningDomain,
+    RouteInformation,
     RecursiveDnsServer,
 }
 
@@ -520,6 +1026,10 @@ impl OptionCode {
             3 => Some(Self::PrefixInformation),
             4 => Some(Self::RedirectedHeader),
             5 => Some(Self::Mtu),
+            8 => Some(Self::HomeAgentInfo),
+            14 => Some(Self::Nonce),
+            21 => Some(Self::ProvisioningDomain),
+            24 => Some(Self::RouteInformation),
             25 => Some(Self::RecursiveDnsServer),
             _ => None,
         }
@@ -532,13 +1042,399 @@ impl OptionCode {
             Self::PrefixInformation => 3,
             Self::RedirectedHeader => 4,
             Self::Mtu => 5,
+            Self::HomeAgentInfo => 8,
+            Self::Nonce => 14,
+            Self::ProvisioningDomain => 21,
+            Self::RouteInformation => 24,
             Self::RecursiveDnsServer => 25,
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RecursiveDnsServer {
     pub lifetime: Duration,
     pub addrs: Vec<Ipv6Addr>,
 }
+
+/// Route Information option (RFC 4191).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RouteInformation {
+    pub prefix_length: u8,
+    pub preference: RoutePreference,
+    /// A lifetime of `u32::MAX` seconds represents infinity.
+    pub lifetime: Duration,
+    pub prefix: Ipv6Addr,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RoutePreference {
+    High,
+    Medium,
+    Low,
+}
+
+impl RoutePreference {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0b01 => Some(Self::High),
+            0b00 => Some(Self::Medium),
+            0b11 => Some(Self::Low),
+            _ => None,
+        }
+    }
+
+    fn to_bits(self) -> u8 {
+        match self {
+            Self::High => 0b01,
+            Self::Medium => 0b00,
+            Self::Low => 0b11,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+    use std::time::Duration;
+
+    use super::{
+        decode_with_offset, Decode, Encode, Error, IcmpOption, IcmpPacket, LinkLayerAddress,
+        PrefixInformation, RecursiveDnsServer, RouteInformation, RoutePreference,
+        RouterAdvertisement, RouterSolicitation, MAX_REACHABLE_TIME,
+    };
+
+    fn advertisement(
+        reachable_timer: Option<Duration>,
+        retrans_timer: Option<Duration>,
+    ) -> RouterAdvertisement {
+        RouterAdvertisement {
+            cur_hop_limit: 64,
+            managed: false,
+            other: false,
+            router_lifetime: Duration::from_secs(1800),
+            reachable_timer,
+            retrans_timer,
+            options: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn router_advertisement_option_accessors() {
+        let mut ra = advertisement(None, None);
+        assert_eq!(ra.mtu(), None);
+        assert_eq!(ra.prefixes().count(), 0);
+
+        let prefix = PrefixInformation {
+            prefix_length: 64,
+            on_link: true,
+            autonomous: true,
+            valid_lifetime: Duration::from_secs(3600),
+            preferred_lifetime: Duration::from_secs(1800),
+            prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+        };
+        let rdnss = RecursiveDnsServer {
+            lifetime: Duration::from_secs(3600),
+            addrs: vec![Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53)],
+        };
+
+        ra.options = vec![
+            IcmpOption::Mtu(1500),
+            IcmpOption::SourceLinkLayerAddress(LinkLayerAddress([0; 6])),
+            IcmpOption::PrefixInformation(prefix),
+            IcmpOption::RecursiveDnsServer(rdnss.clone()),
+            IcmpOption::PrefixInformation(PrefixInformation {
+                prefix_length: 48,
+                ..prefix
+            }),
+        ];
+
+        assert_eq!(ra.mtu(), Some(1500));
+        let lengths: Vec<_> = ra.prefixes().map(|info| info.prefix_length).collect();
+        assert_eq!(lengths, [64, 48]);
+        assert_eq!(ra.rdnss().collect::<Vec<_>>(), [&rdnss]);
+        assert_eq!(ra.routes().count(), 0);
+    }
+
+    #[test]
+    fn router_advertisement_display() {
+        let mut ra = advertisement(Some(Duration::from_secs(30)), None);
+        ra.other = true;
+        ra.options = vec![
+            IcmpOption::Mtu(1500),
+            IcmpOption::SourceLinkLayerAddress(LinkLayerAddress([0; 6])),
+            IcmpOption::PrefixInformation(PrefixInformation {
+                prefix_length: 64,
+                on_link: true,
+                autonomous: true,
+                valid_lifetime: Duration::from_secs(u32::MAX.into()),
+                preferred_lifetime: Duration::from_secs(1800),
+                prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+            }),
+            IcmpOption::RecursiveDnsServer(RecursiveDnsServer {
+                lifetime: Duration::from_secs(3600),
+                addrs: vec![
+                    Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53),
+                    Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x54),
+                ],
+            }),
+        ];
+
+        assert_eq!(
+            ra.to_string(),
+            "hop limit 64, router lifetime 1800s, flags O, reachable 30000ms, mtu 1500, \
+             prefix 2001:db8::/64 LA valid infinite preferred 1800s, \
+             dns 2001:db8::53 2001:db8::54 lifetime 3600s, 1 other options"
+        );
+
+        assert_eq!(
+            advertisement(None, None).to_string(),
+            "hop limit 64, router lifetime 1800s"
+        );
+    }
+
+    #[test]
+    fn link_layer_address_invalid_length() {
+        // Source Link-Layer Address option claiming 16 bytes.
+        let opt = [1, 2, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+        assert!(matches!(
+            IcmpOption::decode(&opt[..]),
+            Err(Error::InvalidOptionLength)
+        ));
+
+        let mut rs = vec![0, 0, 0, 0];
+        rs.extend(opt);
+        assert!(matches!(
+            RouterSolicitation::decode(&rs[..]),
+            Err(Error::InvalidOptionLength)
+        ));
+
+        rs[5] = 1;
+        let rs = RouterSolicitation::decode(&rs[..]).unwrap();
+        assert_eq!(
+            rs.source_link_layer_addr.unwrap().0,
+            [0x00, 0x11, 0x22, 0x33, 0x44, 0x55]
+        );
+    }
+
+    #[test]
+    fn decode_truncated_offset() {
+        // A RA whose reachable timer is cut off after 2 of its 4 bytes.
+        let buf = [134, 0, 0, 0, 64, 0, 0x07, 0x08, 0, 0];
+        let err = decode_with_offset::<IcmpPacket>(&buf).unwrap_err();
+        assert!(matches!(err.error, Error::Eof));
+        assert_eq!(err.offset, 8);
+        assert_eq!(err.to_string(), "truncated at byte 8");
+
+        // A RS with a Source Link-Layer Address option missing its last byte.
+        let buf = [133, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0x00, 0x11, 0x22, 0x33, 0x44];
+        let err = decode_with_offset::<IcmpPacket>(&buf).unwrap_err();
+        assert!(matches!(err.error, Error::Eof));
+        assert_eq!(err.offset, 15);
+
+        // Nothing is consumed for an unknown ICMP type besides the type itself.
+        let err = decode_with_offset::<IcmpPacket>(&[1, 0, 0, 0]).unwrap_err();
+        assert!(matches!(err.error, Error::UnknownIcmpType));
+        assert_eq!(err.offset, 1);
+
+        let err = std::io::Error::from(err);
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "UnknownIcmpType at byte 1");
+    }
+
+    #[test]
+    fn route_information_decode() {
+        // Type, Length, Prefix Length, Flags, Route Lifetime, Prefix. The
+        // prefix has bits set after its /48.
+        #[rustfmt::skip]
+        let buf = [
+            24, 3, 48, 0b0000_1000, 0, 0, 0x07, 0x08,
+            0x20, 0x01, 0x0d, 0xb8, 0, 1, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 1,
+        ];
+
+        let opt = IcmpOption::decode(&buf[..]).unwrap();
+        assert_eq!(
+            opt,
+            IcmpOption::RouteInformation(RouteInformation {
+                prefix_length: 48,
+                preference: RoutePreference::High,
+                lifetime: Duration::from_secs(1800),
+                prefix: Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 0),
+            })
+        );
+
+        let mut buf = buf;
+        buf[2] = 129;
+        assert!(matches!(
+            IcmpOption::decode(&buf[..]),
+            Err(Error::InvalidPrefixLength)
+        ));
+    }
+
+    #[test]
+    fn home_agent_info_decode() {
+        // Type, Length, Reserved, Home Agent Preference, Home Agent Lifetime.
+        let buf = [8, 1, 0, 0, 0xff, 0xfe, 0x07, 0x08];
+
+        let opt = IcmpOption::decode(&buf[..]).unwrap();
+        assert_eq!(
+            opt,
+            IcmpOption::HomeAgentInfo {
+                preference: -2,
+                lifetime: 1800,
+            }
+        );
+
+        let mut output = Vec::new();
+        opt.encode(&mut output);
+        assert_eq!(output, buf);
+
+        // Home Agent Information option claiming 16 bytes.
+        let buf = [8, 2, 0, 0, 0xff, 0xfe, 0x07, 0x08, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(
+            IcmpOption::decode(&buf[..]),
+            Err(Error::InvalidOptionLength)
+        ));
+    }
+
+    #[test]
+    fn provisioning_domain_decode() {
+        #[rustfmt::skip]
+        let buf = [
+            // Type, Length, H, L and R flags, Delay.
+            21, 6, 0b1010_0000, 0x03,
+            // Sequence Number.
+            0x00, 0x2a,
+            // PvD ID FQDN and padding.
+            3, b'p', b'v', b'd',
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e',
+            3, b'c', b'o', b'm',
+            0, 0,
+            // RA header with Type, Code and Checksum.
+            134, 0, 0, 0, 64, 1 << 6, 0x07, 0x08, 0, 0, 0, 0, 0, 0, 0, 0,
+            // MTU option.
+            5, 1, 0, 0, 0, 0, 0x05, 0xdc,
+        ];
+
+        let opt = IcmpOption::decode(&buf[..]).unwrap();
+        assert_eq!(
+            opt,
+            IcmpOption::ProvisioningDomain {
+                fqdn: String::from("pvd.example.com"),
+                sequence: 42,
+                http: true,
+                legacy: false,
+                delay: 3,
+                header: Some(RouterAdvertisement {
+                    other: true,
+                    ..advertisement(None, None)
+                }),
+                options: vec![IcmpOption::Mtu(1500)],
+            }
+        );
+
+        let mut output = Vec::new();
+        opt.encode(&mut output);
+        assert_eq!(output, buf);
+
+        // Only the L flag, without a RA header or options.
+        let mut buf = buf[..24].to_vec();
+        buf[1] = 3;
+        buf[2] = 0b0100_0000;
+        let IcmpOption::ProvisioningDomain {
+            http,
+            legacy,
+            header,
+            options,
+            ..
+        } = IcmpOption::decode(&buf[..]).unwrap()
+        else {
+            panic!("decoded a different option");
+        };
+        assert!(!http);
+        assert!(legacy);
+        assert_eq!(header, None);
+        assert!(options.is_empty());
+
+        // The R flag without room for the RA header.
+        buf[2] = 0b0010_0000;
+        assert!(matches!(
+            IcmpOption::decode(&buf[..]),
+            Err(Error::InvalidOptionLength)
+        ));
+    }
+
+    fn provisioning_domain(options: Vec<IcmpOption>) -> IcmpOption {
+        IcmpOption::ProvisioningDomain {
+            fqdn: String::from("pvd.example.com"),
+            sequence: 0,
+            http: false,
+            legacy: false,
+            delay: 0,
+            header: None,
+            options,
+        }
+    }
+
+    #[test]
+    fn provisioning_domain_nested() {
+        let opt = provisioning_domain(vec![provisioning_domain(Vec::new())]);
+
+        let mut buf = Vec::new();
+        opt.encode(&mut buf);
+        assert!(matches!(
+            IcmpOption::decode(&buf[..]),
+            Err(Error::NestedProvisioningDomain)
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "longer than 2040 bytes")]
+    fn provisioning_domain_too_long() {
+        // 24 bytes for the header and FQDN and 8 per MTU option.
+        let opt = provisioning_domain(vec![IcmpOption::Mtu(1500); 253]);
+        opt.encode(&mut Vec::new());
+    }
+
+    #[test]
+    fn router_advertisement_timers_unspecified() {
+        let ra = advertisement(None, None);
+
+        let mut buf = Vec::new();
+        ra.encode(&mut buf);
+        assert_eq!(&buf[4..12], &[0; 8]);
+
+        let decoded = RouterAdvertisement::decode(&buf[..]).unwrap();
+        assert_eq!(decoded, ra);
+    }
+
+    #[test]
+    fn router_advertisement_reachable_timer_max() {
+        let ra = advertisement(Some(MAX_REACHABLE_TIME), Some(Duration::from_millis(1000)));
+
+        let mut buf = Vec::new();
+        ra.encode(&mut buf);
+        let decoded = RouterAdvertisement::decode(&buf[..]).unwrap();
+        assert_eq!(decoded, ra);
+
+        let ra = advertisement(Some(MAX_REACHABLE_TIME + Duration::from_millis(1)), None);
+
+        let mut buf = Vec::new();
+        ra.encode(&mut buf);
+        let decoded = RouterAdvertisement::decode(&buf[..]).unwrap();
+        assert_eq!(decoded.reachable_timer, Some(MAX_REACHABLE_TIME));
+    }
+
+    #[test]
+    fn router_advertisement_retrans_timer_saturates() {
+        let ra = advertisement(None, Some(Duration::from_secs(u64::from(u32::MAX))));
+
+        let mut buf = Vec::new();
+        ra.encode(&mut buf);
+        let decoded = RouterAdvertisement::decode(&buf[..]).unwrap();
+        assert_eq!(
+            decoded.retrans_timer,
+            Some(Duration::from_millis(u32::MAX.into()))
+        );
+    }
+}
@@ -0,0 +1,378 @@
+//! Routes learned from Route Information options of other routers.
+//!
+//! Route Information: https://www.rfc-editor.org/rfc/rfc4191
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::Ipv6Addr;
+use std::time::{Duration, Instant};
+
+use futures::{pin_mut, FutureExt};
+use tokio::sync::mpsc;
+
+use crate::linux::Interface;
+use crate::ndp::{RouteInformation, RoutePreference};
+
+/// The maximum number of routes installed from Route Information options.
+/// Further routes are ignored until installed routes are removed.
+const MAX_ROUTES: usize = 256;
+
+/// A route is identified by its destination and gateway. The metric
+/// reflects the advertised preference and may change with every RA.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct RouteKey {
+    prefix: Ipv6Addr,
+    prefix_length: u8,
+    gateway: Ipv6Addr,
+}
+
+impl RouteKey {
+    fn with_metric(self, metric: u32) -> Route {
+        Route {
+            prefix: self.prefix,
+            prefix_length: self.prefix_length,
+            gateway: self.gateway,
+            metric,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Route {
+    prefix: Ipv6Addr,
+    prefix_length: u8,
+    gateway: Ipv6Addr,
+    metric: u32,
+}
+
+impl Route {
+    fn key(&self) -> RouteKey {
+        RouteKey {
+            prefix: self.prefix,
+            prefix_length: self.prefix_length,
+            gateway: self.gateway,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Entry {
+    metric: u32,
+    /// Routes with an infinite lifetime have no expiry.
+    expiry: Option<Instant>,
+}
+
+/// The changes to the kernel routes for a received Route Information.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Change {
+    /// The route to add or refresh.
+    add: Option<Route>,
+    /// Whether `add` refreshes the route that is already installed.
+    refresh: bool,
+    /// The previously installed route to remove.
+    remove: Option<Route>,
+}
+
+/// Returns the changes required to install `route` with `lifetime`, given the
+/// currently installed `routes`.
+///
+/// New routes are ignored once [`MAX_ROUTES`] routes are installed.
+fn change(routes: &HashMap<RouteKey, Entry>, route: Route, lifetime: Duration) -> Change {
+    let installed = routes
+        .get(&route.key())
+        .map(|entry| route.key().with_metric(entry.metric));
+
+    if lifetime.is_zero() {
+        return Change {
+            add: None,
+            refresh: false,
+            remove: installed,
+        };
+    }
+
+    if installed.is_none() && routes.len() >= MAX_ROUTES {
+        tracing::warn!(
+            "ignoring route {}/{} via {}: {} routes are already installed",
+            route.prefix,
+            route.prefix_length,
+            route.gateway,
+            MAX_ROUTES
+        );
+        return Change::default();
+    }
+
+    Change {
+        add: Some(route),
+        refresh: installed.is_some_and(|installed| installed.metric == route.metric),
+        // The kernel keys routes by their metric, so a route with a changed
+        // preference would otherwise be installed twice.
+        remove: installed.filter(|installed| installed.metric != route.metric),
+    }
+}
+
+/// Installs the routes received on `rx` from the router with the given address.
+///
+/// Routes are removed again once their lifetime expires, `rx` is closed or
+/// `shutdown` completes.
+pub async fn route_loop<F>(
+    interface: Interface,
+    mut rx: mpsc::Receiver<(RouteInformation, Ipv6Addr)>,
+    shutdown: F,
+) where
+    F: Future,
+{
+    pin_mut!(shutdown);
+
+    let mut routes: HashMap<RouteKey, Entry> = HashMap::new();
+
+    loop {
+        let next_expiry = routes.values().filter_map(|entry| entry.expiry).min();
+
+        let sleep = async {
+            match next_expiry {
+                Some(ts) => tokio::time::sleep_until(ts.into()).await,
+                None => futures::future::pending().await,
+            }
+        };
+
+        futures::select_biased! {
+            _ = shutdown.as_mut().fuse() => break,
+            res = rx.recv().fuse() => {
+                let Some((info, gateway)) = res else {
+                    break;
+                };
+
+                let route = Route {
+                    prefix: info.prefix,
+                    prefix_length: info.prefix_length,
+                    gateway,
+                    metric: metric(info.preference),
+                };
+
+                let change = change(&routes, route, info.lifetime);
+
+                if let Some(route) = change.add {
+                    if let Err(err) = interface
+                        .add_route(
+                            route.prefix,
+                            route.prefix_length,
+                            route.gateway,
+                            route.metric,
+                            change.refresh,
+                        )
+                        .await
+                    {
+                        tracing::error!("failed to add route: {:?}", err);
+                        continue;
+                    }
+
+                    let expiry = if info.lifetime == Duration::from_secs(u32::MAX.into()) {
+                        None
+                    } else {
+                        Some(Instant::now() + info.lifetime)
+                    };
+
+                    routes.insert(route.key(), Entry { metric: route.metric, expiry });
+                }
+
+                if let Some(old) = change.remove {
+                    if change.add.is_none() {
+                        routes.remove(&old.key());
+                    }
+
+                    del_route(&interface, old).await;
+                }
+            }
+            _ = sleep.fuse() => {
+                let now = Instant::now();
+
+                let mut expired = Vec::new();
+                routes.retain(|key, entry| match entry.expiry {
+                    Some(ts) if ts <= now => {
+                        expired.push(key.with_metric(entry.metric));
+                        false
+                    }
+                    _ => true,
+                });
+
+                for route in expired {
+                    del_route(&interface, route).await;
+                }
+            }
+        }
+    }
+
+    // Routes must not outlive the daemon that keeps them fresh.
+    for (key, entry) in routes {
+        del_route(&interface, key.with_metric(entry.metric)).await;
+    }
+}
+
+async fn del_route(interface: &Interface, route: Route) {
+    if let Err(err) = interface
+        .del_route(
+            route.prefix,
+            route.prefix_length,
+            route.gateway,
+            route.metric,
+        )
+        .await
+    {
+        tracing::error!("failed to remove route: {:?}", err);
+    }
+}
+
+/// Returns the route metric for the preference. Lower metrics are preferred.
+fn metric(preference: RoutePreference) -> u32 {
+    match preference {
+        RoutePreference::High => 512,
+        RoutePreference::Medium => 1024,
+        RoutePreference::Low => 2048,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::Ipv6Addr;
+    use std::time::Duration;
+
+    use super::{change, Change, Entry, Route, RouteKey, MAX_ROUTES};
+
+    const ROUTE: Route = Route {
+        prefix: Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 0),
+        prefix_length: 48,
+        gateway: Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+        metric: 1024,
+    };
+
+    #[test]
+    fn change_adds_new_route() {
+        let routes = HashMap::new();
+        assert_eq!(
+            change(&routes, ROUTE, Duration::from_secs(1800)),
+            Change {
+                add: Some(ROUTE),
+                refresh: false,
+                remove: None,
+            }
+        );
+        assert_eq!(change(&routes, ROUTE, Duration::ZERO), Change::default());
+    }
+
+    #[test]
+    fn change_replaces_metric() {
+        let routes = HashMap::from([(
+            ROUTE.key(),
+            Entry {
+                metric: 512,
+                expiry: None,
+            },
+        )]);
+
+        assert_eq!(
+            change(&routes, ROUTE, Duration::from_secs(1800)),
+            Change {
+                add: Some(ROUTE),
+                refresh: false,
+                remove: Some(Route {
+                    metric: 512,
+                    ..ROUTE
+                }),
+            }
+        );
+
+        // A route is removed with the metric it was installed with.
+        assert_eq!(
+            change(&routes, ROUTE, Duration::ZERO),
+            Change {
+                add: None,
+                refresh: false,
+                remove: Some(Route {
+                    metric: 512,
+                    ..ROUTE
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn change_refreshes_same_metric() {
+        let routes = HashMap::from([(
+            ROUTE.key(),
+            Entry {
+                metric: ROUTE.metric,
+                expiry: None,
+            },
+        )]);
+
+        assert_eq!(
+            change(&routes, ROUTE, Duration::from_secs(1800)),
+            Change {
+                add: Some(ROUTE),
+                refresh: true,
+                remove: None,
+            }
+        );
+    }
+
+    #[test]
+    fn routes_differ_by_gateway() {
+        let other = Route {
+            gateway: Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2),
+            ..ROUTE
+        };
+        let routes = HashMap::from([(
+            other.key(),
+            Entry {
+                metric: 512,
+                expiry: None,
+            },
+        )]);
+
+        assert_eq!(
+            change(&routes, ROUTE, Duration::from_secs(1800)),
+            Change {
+                add: Some(ROUTE),
+                refresh: false,
+                remove: None,
+            }
+        );
+    }
+
+    #[test]
+    fn change_limits_routes() {
+        let routes: HashMap<_, _> = (0..MAX_ROUTES as u16)
+            .map(|index| {
+                let key = RouteKey {
+                    prefix: Ipv6Addr::new(0x2001, 0xdb8, 2, index, 0, 0, 0, 0),
+                    ..ROUTE.key()
+                };
+                let entry = Entry {
+                    metric: ROUTE.metric,
+                    expiry: None,
+                };
+                (key, entry)
+            })
+            .collect();
+
+        assert_eq!(
+            change(&routes, ROUTE, Duration::from_secs(1800)),
+            Change::default()
+        );
+
+        // Installed routes are still refreshed.
+        let route = Route {
+            prefix: Ipv6Addr::new(0x2001, 0xdb8, 2, 0, 0, 0, 0, 0),
+            ..ROUTE
+        };
+        assert_eq!(
+            change(&routes, route, Duration::from_secs(1800)),
+            Change {
+                add: Some(route),
+                refresh: true,
+                remove: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! Encodes and decodes one of each NDP option and control message to check
+//! that the codecs of the running build are sound, see `--selftest`.
+
+use std::fmt::Debug;
+use std::net::Ipv6Addr;
+use std::time::{Duration, SystemTime};
+
+use rsadv_control::{
+    AdvertisementStats, ConfiguredAddress, ConfiguredDnsServer, DnsServer, Event, Lifetime,
+    LogEntry, Prefix, Request, Response, Solicitation, Status, PROTOCOL_VERSION,
+};
+
+use crate::ndp::{
+    Decode, Encode, IcmpOption, LinkLayerAddress, PrefixInformation, RecursiveDnsServer,
+    RouteInformation, RoutePreference,
+};
+
+const PREFIX: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0);
+const DNS_SERVER: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53);
+const MAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+/// Runs all checks and prints a report.
+///
+/// Returns `true` if all checks passed.
+pub fn run() -> bool {
+    let mut failed = 0;
+    let mut total = 0;
+
+    for (name, res) in options().chain(requests()).chain(responses()) {
+        total += 1;
+        match res {
+            Ok(()) => println!("ok   {}", name),
+            Err(err) => {
+                failed += 1;
+                println!("FAIL {}: {}", name, err);
+            }
+        }
+    }
+
+    println!("{} of {} checks passed", total - failed, total);
+    failed == 0
+}
+
+type Check = (&'static str, Result<(), String>);
+
+fn options() -> impl Iterator<Item = Check> {
+    let options = [
+        (
+            "SourceLinkLayerAddress",
+            IcmpOption::SourceLinkLayerAddress(LinkLayerAddress(MAC)),
+        ),
+        (
+            "TargetLinkLayerAddress",
+            IcmpOption::TargetLinkLayerAddress(LinkLayerAddress(MAC)),
+        ),
+        (
+            "PrefixInformation",
+            IcmpOption::PrefixInformation(PrefixInformation {
+                prefix_length: 64,
+                on_link: true,
+                autonomous: false,
+                valid_lifetime: Duration::from_secs(3600),
+                preferred_lifetime: Duration::from_secs(1800),
+                prefix: PREFIX,
+            }),
+        ),
+        ("Mtu", IcmpOption::Mtu(1500)),
+        (
+            "RecursiveDnsServer",
+            IcmpOption::RecursiveDnsServer(RecursiveDnsServer {
+                lifetime: Duration::from_secs(1800),
+                addrs: vec![
+                    DNS_SERVER,
+                    Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x54),
+                ],
+            }),
+        ),
+        ("Nonce", IcmpOption::Nonce(vec![1, 2, 3, 4, 5, 6])),
+        (
+            "RouteInformation",
+            IcmpOption::RouteInformation(RouteInformation {
+                prefix_length: 48,
+                preference: RoutePreference::High,
+                lifetime: Duration::from_secs(1800),
+                prefix: PREFIX,
+            }),
+        ),
+        (
+            "HomeAgentInfo",
+            IcmpOption::HomeAgentInfo {
+                preference: -2,
+                lifetime: 1800,
+            },
+        ),
+        (
+            "ProvisioningDomain",
+            IcmpOption::ProvisioningDomain {
+                fqdn: String::from("pvd.example.com"),
+                sequence: 42,
+                http: true,
+                legacy: false,
+                delay: 3,
+                header: None,
+                options: vec![IcmpOption::Mtu(1500)],
+            },
+        ),
+    ];
+
+    options.into_iter().map(|(name, opt)| {
+        let res = round_trip(
+            &opt,
+            |opt, buf| opt.encode(buf),
+            |buf| IcmpOption::decode(buf).map_err(|err| format!("{:?}", err)),
+        );
+        (name, res)
+    })
+}
+
+fn requests() -> impl Iterator<Item = Check> {
+    let prefix = Prefix {
+        prefix: PREFIX,
+        prefix_length: 64,
+        preferred_lifetime: Lifetime::Duration(Duration::from_secs(1800)),
+        valid_lifetime: Lifetime::Until(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(2_000_000_000),
+        ),
+        deprecate_before: Some(Duration::from_secs(600)),
+        on_link: true,
+        label: Some(String::from("guest-vlan")),
+        extra_addrs: vec![Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)],
+    };
+    let server = DnsServer {
+        addr: DNS_SERVER,
+        lifetime: Lifetime::Infinite,
+        label: Some(String::from("resolver")),
+    };
+
+    let requests = [
+        ("GetPrefix", Request::GetPrefix(PREFIX)),
+        ("AddPrefix", Request::AddPrefix(prefix.clone())),
+        ("RemovePrefix", Request::RemovePrefix(prefix)),
+        ("AddDnsServer", Request::AddDnsServer(server.clone())),
+        ("RemoveDnsServer", Request::RemoveDnsServer(server)),
+        ("FlushPrefixes", Request::FlushPrefixes),
+        ("FlushDnsServers", Request::FlushDnsServers),
+        ("Ping", Request::Ping),
+        ("GetStatus", Request::GetStatus),
+        ("Reload", Request::Reload),
+        ("Pause", Request::Pause),
+        ("Resume", Request::Resume),
+        ("Export", Request::Export),
+        ("Import", Request::Import(vec![1, 2, 3])),
+        ("Log", Request::Log { max: 16 }),
+        ("ListConfiguredAddresses", Request::ListConfiguredAddresses),
+        (
+            "Authenticate",
+            Request::Authenticate(String::from("secret")),
+        ),
+        (
+            "Hello",
+            Request::Hello {
+                client_version: PROTOCOL_VERSION,
+            },
+        ),
+    ];
+
+    requests.into_iter().map(|(name, req)| {
+        let res = round_trip(
+            &req,
+            |req, buf| {
+                req.encode(buf);
+            },
+            |buf| Request::decode(buf).map_err(|err| format!("{:?}", err)),
+        );
+        (name, res)
+    })
+}
+
+fn responses() -> impl Iterator<Item = Check> {
+    let prefix = Prefix {
+        prefix: PREFIX,
+        prefix_length: 64,
+        preferred_lifetime: Lifetime::Infinite,
+        valid_lifetime: Lifetime::Infinite,
+        deprecate_before: None,
+        on_link: true,
+        label: None,
+        extra_addrs: Vec::new(),
+    };
+    let status = Status {
+        solicitations: vec![Solicitation {
+            addr: Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+            link_layer_addr: Some(MAC),
+            elapsed: Duration::from_secs(3),
+        }],
+        dropped_solicitations: 7,
+        advertisements: AdvertisementStats {
+            size: 120,
+            max_size: 136,
+            prefix_options: 2,
+            dns_options: 1,
+            other_options: 2,
+        },
+        paused: false,
+        prefixes: vec![prefix.clone()],
+        dns_servers: vec![ConfiguredDnsServer {
+            addr: DNS_SERVER,
+            label: Some(String::from("iot")),
+        }],
+    };
+
+    let responses = [
+        ("Ok", Response::Ok { affected: 1 }),
+        (
+            "Pong",
+            Response::Pong {
+                uptime: Duration::from_secs(3600),
+                healthy: true,
+            },
+        ),
+        ("Status", Response::Status(status)),
+        (
+            "Error",
+            Response::Error {
+                message: String::from("no such prefix"),
+            },
+        ),
+        ("State", Response::State(vec![1, 2, 3])),
+        (
+            "Log",
+            Response::Log(vec![
+                LogEntry {
+                    elapsed: Duration::from_secs(60),
+                    event: Event::PrefixAdded(PREFIX, 64),
+                },
+                LogEntry {
+                    elapsed: Duration::from_secs(30),
+                    event: Event::DnsServerRemoved(DNS_SERVER),
+                },
+                LogEntry {
+                    elapsed: Duration::from_secs(10),
+                    event: Event::Reloaded,
+                },
+            ]),
+        ),
+        (
+            "Addresses",
+            Response::Addresses(vec![ConfiguredAddress {
+                addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0x0211, 0x22ff, 0xfe33, 0x4455),
+                prefix_length: 64,
+                present: true,
+            }]),
+        ),
+        ("Prefix", Response::Prefix(Some(prefix))),
+        ("PrefixNotFound", Response::Prefix(None)),
+        ("Added", Response::Added),
+        ("Updated", Response::Updated),
+        (
+            "Hello",
+            Response::Hello {
+                server_version: PROTOCOL_VERSION,
+            },
+        ),
+    ];
+
+    responses.into_iter().map(|(name, resp)| {
+        let res = round_trip(
+            &resp,
+            |resp, buf| {
+                resp.encode(buf);
+            },
+            |buf| Response::decode(buf).map_err(|err| format!("{:?}", err)),
+        );
+        (name, res)
+    })
+}
+
+/// Encodes and decodes `value`, returning an error if it does not decode to
+/// the same value.
+fn round_trip<T, E, D>(value: &T, encode: E, decode: D) -> Result<(), String>
+where
+    T: PartialEq + Debug,
+    E: FnOnce(&T, &mut Vec<u8>),
+    D: FnOnce(&[u8]) -> Result<T, String>,
+{
+    let mut buf = Vec::new();
+    encode(value, &mut buf);
+
+    let decoded = decode(&buf)?;
+    if decoded == *value {
+        Ok(())
+    } else {
+        Err(format!("encoded {:?}, decoded {:?}", value, decoded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+
+    #[test]
+    fn selftest_passes() {
+        assert!(run());
+    }
+}